@@ -1,6 +1,10 @@
 use std::{sync::mpsc::*, thread, time::Duration};
 
-use agisostack::{control_function::*, name::*, Address, CanFrame, CanNetworkManager};
+use agisostack::{
+    hardware_integration::{CanDriverTrait, CanWriteError},
+    name::*,
+    Address, CanFrame, CanNetworkManager,
+};
 
 fn main() {
     // Setup the logging interface.
@@ -46,35 +50,53 @@ fn name(id: u8) -> Name {
         .build()
 }
 
+/// A CAN driver that sends and receives CanFrames over channels, connecting the Isobus threads.
+struct ChannelCanDriver {
+    id: u8,
+    tx: Sender<CanFrame>,
+    rx: Receiver<CanFrame>,
+}
+
+impl CanDriverTrait for ChannelCanDriver {
+    fn is_valid(&mut self) -> bool {
+        true
+    }
+
+    fn open(&mut self) {}
+
+    fn close(&mut self) {}
+
+    fn read(&mut self) -> Option<CanFrame> {
+        // Receive a CanFrame without blocking
+        let frame = self.rx.try_recv().ok()?;
+        log::debug!("{} Read: {frame}", self.id);
+        Some(frame)
+    }
+
+    fn write(&mut self, frame: &CanFrame) -> Result<(), CanWriteError> {
+        log::debug!("{} Send: {frame}", self.id);
+        self.tx.send(*frame).map_err(|_| CanWriteError::NotOpen)
+    }
+}
+
 fn isobus_task(id: u8, tx: Sender<CanFrame>, rx: Receiver<CanFrame>) {
     // Create a new mannager for the CAN network we are connecting to.
-    let mut network_manager: CanNetworkManager = CanNetworkManager::new();
-
-    // Bind a callback to the network manager to be called when we send a can frame.
-    // This is the "glue" between the network manager and the CAN Driver.
-    let callback = |f| {
-        log::debug!("{id} Send: {f}");
-        let _ = tx.send(f);
-    };
-    network_manager.send_can_frame_callback(&callback);
+    // The channel driver is the "glue" between the network manager and the other thread.
+    let mut network_manager = CanNetworkManager::new(ChannelCanDriver { id, tx, rx });
 
     let test_device_name = name(id);
     let test_device_address = Address(0x80);
 
-    let mut test_internal_ecu =
-        InternalControlFunction::new(test_device_name, test_device_address).unwrap();
+    let test_internal_ecu_handle = network_manager.new_internal_control_function(test_device_name, test_device_address);
 
     // Initialize the internal control function.
-    test_internal_ecu.initialize();
+    if let Some(test_internal_ecu) = network_manager.internal_control_function_mut(test_internal_ecu_handle) {
+        test_internal_ecu.initialize();
+    }
 
     loop {
-        // Receive a CanFrame without blocking
-        if let Ok(frame) = rx.try_recv() {
-            log::debug!("{id} Read: {frame}");
-            network_manager.process_can_frame(frame);
-        }
-
-        // Update the internal control function
-        test_internal_ecu.update(&mut network_manager);
+        // Receive CanFrames and update the internal control function
+        network_manager.update();
+        thread::sleep(Duration::from_millis(1));
     }
 }
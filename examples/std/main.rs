@@ -1,8 +1,8 @@
 use std::{sync::mpsc::*, thread, time::Duration};
 
 use agisostack::{
-    control_function::*, hardware_integration::*, name::*, virtual_terminal_client::*, Address,
-    CanFrame, CanNetworkManager, ObjectPool,
+    hardware_integration::*, name::*, virtual_terminal_client::*, Address, CanNetworkManager,
+    ObjectPool,
 };
 
 const ALARM_SOFT_KEY: u16 = 5000; //0x1388
@@ -19,7 +19,7 @@ fn main() {
         .init();
 
     // Start the Isobus thread.
-    thread::spawn(|| isobus_task());
+    thread::spawn(isobus_task);
 
     // For example; Do all of our GUI in the main thread.
     loop {
@@ -87,9 +87,8 @@ fn isobus_task() {
 
     // Create the Control Functions used by the Virtual Terminal
     // let test_internal_ecu = InternalControlFunction::new(test_device_name, test_device_address);
-    // let test_partner_vt = PartneredControlFunction::new(&vt_name_filters);
     let test_internal_ecu_handle =  network_manager.new_internal_control_function(test_device_name, test_device_address);
-    let test_partner_vt_handle =  network_manager.new_partnered_control_function(test_partner_vt);
+    let test_partner_vt_handle =  network_manager.new_partnered_control_function(&vt_name_filters);
 
     // Create the channel used to send VTKeyEvents from the callback to this task.
     // event_tx and event_rx have to outlive test_virtual_terminal_client, so we define them first.
@@ -132,36 +131,33 @@ fn isobus_task() {
 
         // Receive VTKeyEvents without blocking using callback results
         if let Ok(event) = event_rx.try_recv() {
-            match event.key_event {
-                KeyActivationCode::ButtonUnlatchedOrReleased => {
-                    match event.object_id {
-                        PLUS_BUTTON => {
-                            example_number_output += 1;
-                            test_virtual_terminal_client.send_change_numeric_value(
-                                &mut network_manager,
-                                BUTTON_EXAMPLE_NUMBER_VAR_NUM,
-                                example_number_output,
-                            );
-                        }
-                        MINUS_BUTTON => {
-                            example_number_output -= 1;
-                            test_virtual_terminal_client.send_change_numeric_value(
-                                &mut network_manager,
-                                BUTTON_EXAMPLE_NUMBER_VAR_NUM,
-                                example_number_output,
-                            );
-                        }
-                        ALARM_SOFT_KEY => {
-                            // TestVirtualTerminalClient->send_change_active_mask(example_WorkingSet, example_AlarmMask);
-                        }
-                        ACKNOWLEDGE_ALARM_SOFT_KEY => {
-                            // TestVirtualTerminalClient->send_change_active_mask(example_WorkingSet, mainRunscreen_DataMask);
-                        }
-                        _ => {}
-                    };
-                }
-                _ => {}
-            };
+            if event.key_event == KeyActivationCode::ButtonUnlatchedOrReleased {
+                match event.object_id {
+                    PLUS_BUTTON => {
+                        example_number_output += 1;
+                        test_virtual_terminal_client.send_change_numeric_value(
+                            &mut network_manager,
+                            BUTTON_EXAMPLE_NUMBER_VAR_NUM,
+                            example_number_output,
+                        );
+                    }
+                    MINUS_BUTTON => {
+                        example_number_output -= 1;
+                        test_virtual_terminal_client.send_change_numeric_value(
+                            &mut network_manager,
+                            BUTTON_EXAMPLE_NUMBER_VAR_NUM,
+                            example_number_output,
+                        );
+                    }
+                    ALARM_SOFT_KEY => {
+                        // TestVirtualTerminalClient->send_change_active_mask(example_WorkingSet, example_AlarmMask);
+                    }
+                    ACKNOWLEDGE_ALARM_SOFT_KEY => {
+                        // TestVirtualTerminalClient->send_change_active_mask(example_WorkingSet, mainRunscreen_DataMask);
+                    }
+                    _ => {}
+                };
+            }
         }
     }
 
@@ -11,7 +11,7 @@ pub struct CanFrame {
 
 impl CanFrame {
     pub fn new(id: impl Into<Id>, data: &[u8]) -> Self {
-        let id = id.try_into().unwrap_or(Id::Extended(ExtendedId::MAX));
+        let id = id.into();
         let dlc = usize::min(data.len(), 8);
         let mut temp_data: [u8; 8] = [0x00; 8];
 
@@ -1,6 +1,10 @@
 use alloc::vec::Vec;
 
-use crate::{name::Name, Address, CanFrame, CanPriority, ExtendedId, Id, ParameterGroupNumber};
+use crate::{
+    name::Name,
+    payload::{check_j1939_bits, check_j1939_u16, check_j1939_u32, check_j1939_u8, PayloadError},
+    Address, CanFrame, CanPriority, ExtendedId, Id, ParameterGroupNumber,
+};
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct CanMessage {
@@ -42,7 +46,11 @@ impl CanMessage {
         }
     }
     pub fn new_from_id(id: Id, data: &[u8]) -> Self {
-        let pgn: ParameterGroupNumber = ((id.as_raw() >> 8) & 0x03FF00).into();
+        // The PDU specific byte is part of the PGN for PDU2, for PDU1 it holds the destination address.
+        let mut pgn: ParameterGroupNumber = ((id.as_raw() >> 8) & 0x03FFFF).into();
+        if pgn.is_pdu1() {
+            pgn = (pgn.as_u32() & 0x03FF00).into();
+        }
         CanMessage {
             priority: ((id.as_raw() >> 26) as u8 & 0b111).into(),
             pgn,
@@ -82,10 +90,15 @@ impl CanMessage {
         self.data.len()
     }
 
-    pub fn as_can_frame(&self) -> Result<CanFrame, ()> {
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The message as a single CAN frame, `None` when it needs a transport protocol.
+    pub fn as_can_frame(&self) -> Option<CanFrame> {
         match self.len() {
-            0..=8 => Ok(CanFrame::new(self.id(), &self.data)),
-            _ => Err(()),
+            0..=8 => Some(CanFrame::new(self.id(), &self.data)),
+            _ => None,
         }
     }
 
@@ -117,88 +130,103 @@ impl CanMessage {
     }
 
     pub fn get_bool_at(&self, index: usize, bit: usize) -> bool {
-        match self
-            .data
-            .get(index)
-            .map(|&value| (value & (0b1 << bit)) != 0)
-        {
-            Some(val) => val,
-            None => {
-                log::error!("Index out of range!");
-                bool::default()
-            }
-        }
+        Self::or_default(self.try_bool_at(index, bit as u8))
     }
     pub fn get_u8_at(&self, index: usize) -> u8 {
-        match self.data.get(index).copied() {
-            Some(val) => val,
-            None => {
-                log::error!("Index out of range!");
-                u8::default()
-            }
-        }
+        Self::or_default(self.try_u8_at(index))
     }
     pub fn get_u16_at(&self, index: usize) -> u16 {
-        match self.data.get(index..=index + 1).map(|value| {
-            let mut data: [u8; 2] = [0; 2];
-            data.copy_from_slice(value);
-            u16::from_le_bytes(data)
-        }) {
-            Some(val) => val,
-            None => {
-                log::error!("Index out of range!");
-                u16::default()
-            }
-        }
+        Self::or_default(self.try_u16_at(index))
     }
     pub fn get_u24_at(&self, index: usize) -> u32 {
-        match self.data.get(index..=index + 2).map(|value| {
-            let mut data: [u8; 3] = [0; 3];
-            data.copy_from_slice(value);
-            u32::from_le_bytes([data[0], data[1], data[2], 0])
-        }) {
-            Some(val) => val,
-            None => {
-                log::error!("Index out of range!");
-                u32::default()
-            }
-        }
+        Self::or_default(self.try_u24_at(index))
     }
     pub fn get_u32_at(&self, index: usize) -> u32 {
-        match self.data.get(index..=index + 3).map(|value| {
-            let mut data: [u8; 4] = [0; 4];
-            data.copy_from_slice(value);
-            u32::from_le_bytes(data)
-        }) {
-            Some(val) => val,
-            None => {
-                log::error!("Index out of range!");
-                u32::default()
-            }
-        }
+        Self::or_default(self.try_u32_at(index))
     }
-
     pub fn get_pgn_at(&self, index: usize) -> ParameterGroupNumber {
-        match self
-            .data
-            .get(index..index + 3)
-            .map(|value| ParameterGroupNumber::from([value[0], value[1], value[2], 0].as_slice()))
-        {
-            Some(val) => val,
-            None => {
-                log::error!("Index out of range!");
-                ParameterGroupNumber::default()
-            }
-        }
+        Self::or_default(self.try_pgn_at(index))
     }
     pub fn get_name(&self, index: usize) -> Name {
-        match self.data.get(index..index + 8).map(|value| value.into()) {
-            Some(val) => val,
-            None => {
-                log::error!("Index out of range!");
-                Name::default()
-            }
+        Self::or_default(self.try_name_at(index))
+    }
+
+    fn or_default<T: Default>(value: Result<T, PayloadError>) -> T {
+        value.unwrap_or_else(|_| {
+            log::error!("Index out of range!");
+            T::default()
+        })
+    }
+
+    fn try_bytes_at<const N: usize>(&self, index: usize) -> Result<[u8; N], PayloadError> {
+        index.checked_add(N)
+            .and_then(|end| self.data.get(index..end))
+            .and_then(|value| value.try_into().ok())
+            .ok_or(PayloadError::OutOfRange)
+    }
+
+    pub fn try_u8_at(&self, index: usize) -> Result<u8, PayloadError> {
+        self.try_bytes_at::<1>(index).map(|value| value[0])
+    }
+    pub fn try_u16_at(&self, index: usize) -> Result<u16, PayloadError> {
+        self.try_bytes_at(index).map(u16::from_le_bytes)
+    }
+    pub fn try_u24_at(&self, index: usize) -> Result<u32, PayloadError> {
+        self.try_bytes_at::<3>(index).map(|value| u32::from_le_bytes([value[0], value[1], value[2], 0]))
+    }
+    pub fn try_u32_at(&self, index: usize) -> Result<u32, PayloadError> {
+        self.try_bytes_at(index).map(u32::from_le_bytes)
+    }
+    pub fn try_u64_at(&self, index: usize) -> Result<u64, PayloadError> {
+        self.try_bytes_at(index).map(u64::from_le_bytes)
+    }
+    pub fn try_i8_at(&self, index: usize) -> Result<i8, PayloadError> {
+        self.try_bytes_at(index).map(i8::from_le_bytes)
+    }
+    pub fn try_i16_at(&self, index: usize) -> Result<i16, PayloadError> {
+        self.try_bytes_at(index).map(i16::from_le_bytes)
+    }
+    pub fn try_i32_at(&self, index: usize) -> Result<i32, PayloadError> {
+        self.try_bytes_at(index).map(i32::from_le_bytes)
+    }
+    pub fn try_i64_at(&self, index: usize) -> Result<i64, PayloadError> {
+        self.try_bytes_at(index).map(i64::from_le_bytes)
+    }
+    pub fn try_pgn_at(&self, index: usize) -> Result<ParameterGroupNumber, PayloadError> {
+        self.try_bytes_at::<3>(index).map(ParameterGroupNumber::from_le_bytes)
+    }
+    pub fn try_name_at(&self, index: usize) -> Result<Name, PayloadError> {
+        self.try_bytes_at::<8>(index).map(|value| Name::from(value.as_slice()))
+    }
+
+    /// Read `length` bits starting at bit `bit` of byte `index`, the bits may not cross a byte boundary.
+    pub fn try_bits_at(&self, index: usize, bit: u8, length: u8) -> Result<u8, PayloadError> {
+        if length == 0 || bit as u16 + length as u16 > 8 {
+            return Err(PayloadError::OutOfRange);
         }
+        self.try_u8_at(index)
+            .map(|value| (value >> bit) & (((1u16 << length) - 1) as u8))
+    }
+    pub fn try_bool_at(&self, index: usize, bit: u8) -> Result<bool, PayloadError> {
+        self.try_bits_at(index, bit, 1).map(|value| value != 0)
+    }
+
+    /// Read a 1 byte J1939 parameter, rejecting the "not available" and "error" indicators.
+    pub fn try_j1939_u8_at(&self, index: usize) -> Result<u8, PayloadError> {
+        self.try_u8_at(index).and_then(check_j1939_u8)
+    }
+    /// Read a 2 byte J1939 parameter, rejecting the "not available" and "error" indicators.
+    pub fn try_j1939_u16_at(&self, index: usize) -> Result<u16, PayloadError> {
+        self.try_u16_at(index).and_then(check_j1939_u16)
+    }
+    /// Read a 4 byte J1939 parameter, rejecting the "not available" and "error" indicators.
+    pub fn try_j1939_u32_at(&self, index: usize) -> Result<u32, PayloadError> {
+        self.try_u32_at(index).and_then(check_j1939_u32)
+    }
+    /// Read a discrete J1939 parameter, rejecting the "not available" and "error" indicators.
+    pub fn try_j1939_bits_at(&self, index: usize, bit: u8, length: u8) -> Result<u8, PayloadError> {
+        self.try_bits_at(index, bit, length)
+            .and_then(|value| check_j1939_bits(value, length))
     }
 }
 
@@ -263,7 +291,7 @@ impl CanMessageBuilder {
     }
 
     pub fn data(&mut self, value: &[u8]) -> &mut Self {
-        self.data.copy_from_slice(value);
+        self.data = value.to_vec();
         self
     }
 }
@@ -1,25 +1,97 @@
-use core::cell::RefCell;
+use core::time::Duration;
 
 use alloc::{
     collections::{BTreeMap, VecDeque},
-    vec::Vec, boxed::Box, rc::Rc,
+    vec::Vec, boxed::Box,
 };
 
 use crate::{
-    name::Name,
+    name::{Name, NameFilter},
     Address,
     CanFrame,
     CanMessage,
     CanPriority,
-    ParameterGroupNumber, hardware_integration::CanDriverTrait,
-    control_function::{InternalControlFunction, ExternalControlFunction, ControlFunction, ControlFunctionHandle},
+    AcknowledgementType,
+    ParameterGroupNumber,
+    hardware_integration::{CanDriverTrait, TimeDriver, TimeDriverTrait},
+    control_function::{InternalControlFunction, ExternalControlFunction, AddressViolationEvent, PartneredControlFunction, ControlFunction, ControlFunctionHandle},
+    protocol_managers::{ExtendedTransportProtocolManager, FastPacketManager, TransportProtocolManager},
 };
 
 // const MAX_CAN_FRAMES_SEND_PER_PROCESS: u8 = 255;
-const MAX_RECEIVED_CAN_MESSAGE_QUEUE_SIZE: usize = 32;
+const MAX_ADDRESS_VIOLATION_EVENT_QUEUE_SIZE: usize = 8;
+const MAX_REQUEST_RESULT_QUEUE_SIZE: usize = 32;
+const ADDRESS_CLAIM_RESPONSE_TIMEOUT: Duration = Duration::from_millis(1000);
 
 // const GLOBAL_PARAMETER_GROUP_NUMBER_CALLBACK_LIST_SIZE: usize = 4;
 
+/// The answer to a PGN request made with `CanNetworkManager::request_pgn`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum RequestResponse {
+    Response(CanMessage),                   //< The requested PGN was received, reassembled if it was sent using TP or ETP
+    Acknowledgement(AcknowledgementType),   //< The CF answered with an acknowledgement instead of the PGN
+    Timeout,                                //< The CF did not answer in time
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RequestResult {
+    pub id: RequestId,                  //< The id returned by `request_pgn`
+    pub address: Address,               //< The address the request was sent to
+    pub pgn: ParameterGroupNumber,      //< The requested PGN
+    pub response: RequestResponse,
+}
+
+/// The reason a message could not be sent from an internal control function.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SendError {
+    UnknownControlFunction, //< The handle is not an internal control function of this network manager
+    AddressNotClaimed,      //< The internal control function did not claim an address yet
+    InvalidMessage,         //< The message can not be encoded, like a value out of its range
+}
+
+/// Identifies a PGN request made with `CanNetworkManager::request_pgn`.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct RequestId(usize);
+
+/// Identifies a message subscription made on the `CanNetworkManager`.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct SubscriptionId(usize);
+
+enum Delivery {
+    Callback(Box<dyn FnMut(&CanMessage)>),
+    Queue(VecDeque<CanMessage>, usize), //< The received messages and the maximum size of the queue
+}
+
+struct Subscription {
+    pgn: ParameterGroupNumber,
+    source: Option<Name>,                           //< Only deliver messages from the CF with this NAME
+    destination: Option<ControlFunctionHandle>,     //< Only deliver messages received by this internal CF
+    delivery: Delivery,
+}
+
+impl Subscription {
+    fn deliver(&mut self, message: &CanMessage) {
+        match &mut self.delivery {
+            Delivery::Callback(callback) => callback(message),
+            Delivery::Queue(queue, max_size) => {
+                queue.push_back(message.clone());
+                while queue.len() > *max_size {
+                    queue.pop_front();
+                }
+            }
+        }
+    }
+}
+
+struct PendingRequest {
+    id: RequestId,
+    source: Address,                //< The address of the internal control function that sent the request
+    destination: Address,           //< The address the request was sent to
+    pgn: ParameterGroupNumber,
+    timeout: Duration,              //< The time at which the request times out
+    has_response: bool,
+}
+
 pub struct CanNetworkManager {
     can_driver: Box<dyn CanDriverTrait>,
 
@@ -30,18 +102,30 @@ pub struct CanNetworkManager {
     // internal_control_functions: BTreeMap<ControlFunctionHandle, InternalControlFunction>,
 
     control_functions: BTreeMap<ControlFunctionHandle, ControlFunction>,
+    next_control_function_id: usize,
+
+    tp_manager: TransportProtocolManager,           //< Instance of the transport protocol manager
+    etp_manager: ExtendedTransportProtocolManager,  //< Instance of the extended transport protocol manager
+    fast_packet_manager: FastPacketManager,         //< Instance of the NMEA2000 fast packet manager
+
+    address_claim_request_timestamp: Option<Duration>,  //< When the last global request for address claim was seen
+    address_violation_events: VecDeque<AddressViolationEvent>,
+
+    pending_requests: Vec<PendingRequest>,
+    request_results: VecDeque<RequestResult>,
+    next_request_id: usize,
 
     // send_can_frame_buffer: Vec<CanFrame>,
     // send_can_frame_callback: Option<&'a dyn Fn(CanFrame)>,
 
     // can_message_to_send: Option<CanMessage<'a>>,
-    received_can_message_queue: VecDeque<CanMessage>,
     // received_can_message_queue_iter_index: usize,
-    // global_parameter_group_number_callbacks: BTreeMap<u16, &'a dyn Fn(&CanMessage)>,
+    subscriptions: BTreeMap<SubscriptionId, Subscription>,
+    next_subscription_id: usize,
 }
 
 impl CanNetworkManager {
-    pub fn new(can_driver: impl CanDriverTrait) -> CanNetworkManager {
+    pub fn new(can_driver: impl CanDriverTrait + 'static) -> CanNetworkManager {
         CanNetworkManager {
             can_driver: Box::new(can_driver),
 
@@ -50,18 +134,59 @@ impl CanNetworkManager {
             // internal_control_functions: BTreeMap::new(),
         
             control_functions: BTreeMap::new(),
+            next_control_function_id: 0,
+
+            tp_manager: TransportProtocolManager::new(),
+            etp_manager: ExtendedTransportProtocolManager::new(),
+            fast_packet_manager: FastPacketManager::new(),
+
+            address_claim_request_timestamp: None,
+            address_violation_events: VecDeque::new(),
+
+            pending_requests: Vec::new(),
+            request_results: VecDeque::new(),
+            next_request_id: 0,
+
+            subscriptions: BTreeMap::new(),
+            next_subscription_id: 0,
 
             // send_can_frame_buffer: Vec::new(),
             // send_can_frame_callback: None,
 
-            received_can_message_queue: VecDeque::new(),
             // received_can_message_queue_iter_index: usize::default(),
         }
     }
 
-    pub fn new_internal_control_function(&self, name: Name, preferred_address: Address) -> ControlFunctionHandle {
-        let handle: ControlFunctionHandle = InternalControlFunction::new(name, preferred_address);
-        let _ = self.control_functions.insert(handle, ControlFunction::Internal(internal_control_function));
+    /// Sets the maximum number of TP and ETP sessions that can run at the same time.
+    ///
+    /// The limit applies to each protocol separately and counts both transmit and receive sessions.
+    pub fn set_max_transport_protocol_sessions(&mut self, max_sessions: usize) {
+        self.tp_manager.set_max_sessions(max_sessions);
+        self.etp_manager.set_max_sessions(max_sessions);
+    }
+
+    /// Sets the size of the largest message that is accepted using ETP, larger messages are refused.
+    pub fn set_max_extended_transport_protocol_message_size(&mut self, max_message_size: usize) {
+        self.etp_manager.set_max_receive_message_size(max_message_size);
+    }
+
+    /// Send and receive `pgn` using the NMEA2000 Fast Packet protocol.
+    pub fn add_fast_packet_pgn(&mut self, pgn: ParameterGroupNumber) {
+        self.fast_packet_manager.add_pgn(pgn);
+    }
+    pub fn remove_fast_packet_pgn(&mut self, pgn: ParameterGroupNumber) {
+        self.fast_packet_manager.remove_pgn(pgn);
+    }
+
+    pub fn new_internal_control_function(&mut self, name: Name, preferred_address: Address) -> ControlFunctionHandle {
+        self.add_control_function(ControlFunction::Internal(Box::new(InternalControlFunction::new(name, preferred_address))))
+    }
+
+    pub fn new_partnered_control_function(&mut self, filters: &[NameFilter]) -> ControlFunctionHandle {
+        let handle = self.add_control_function(ControlFunction::Partnered(PartneredControlFunction::new(filters)));
+
+        // The partner might already be on the bus.
+        self.update_partners();
         handle
     }
 
@@ -79,17 +204,23 @@ impl CanNetworkManager {
         #[cfg(feature = "log_can_write")]
         log::debug!("Send: {}", &message);
 
+        // Fast Packet PGNs always use the fast packet protocol, whatever their length.
+        if self.fast_packet_manager.is_fast_packet(message.pgn()) {
+            self.fast_packet_manager.send(message);
+            return;
+        }
+
         match message.len() {
             0..=8 => {
-                if let Ok(frame) = message.as_can_frame() {
+                if let Some(frame) = message.as_can_frame() {
                     self.send_can_frame(frame);
                 }
             }
             9..=1785 => {
-                // self.tp_manager.send(&mut self.can_driver, pdu, time);
+                self.tp_manager.send(message);
             }
             1786..=117_440_505 => {
-                // self.etp_manager.send(message);
+                self.etp_manager.send(message);
             }
             _ => {
                 log::error!("Can message to long; > 117.440.505 bytes!");
@@ -113,16 +244,101 @@ impl CanNetworkManager {
         #[cfg(feature = "log_all_can_read")]
         log::debug!("Read <-: {}", message);
 
+        // Another ECU sending from one of our addresses is an address violation, the owner re-claims its address.
+        // Address claims on our address are handled as contention by the address claim state machine.
+        if message.pgn() != ParameterGroupNumber::AddressClaim && message.source_address() < Address::NULL {
+            let event = self.internal_control_functions_mut().into_iter()
+                .find(|icf| icf.address() == message.source_address())
+                .map(|icf| icf.process_address_violation());
+
+            if let Some(event) = event {
+                log::warn!("[NM]: Address violation on address {} of internal control function {}, occurrence count {}", event.address, event.name, event.occurrence_count);
+                self.address_violation_events.push_back(event);
+
+                while self.address_violation_events.len() > MAX_ADDRESS_VIOLATION_EVENT_QUEUE_SIZE {
+                    self.address_violation_events.pop_front();
+                }
+            }
+        }
+
+        // Only listen to global messages and messages ment for us.
+        if !message.is_address_global()
+            && !self.is_address_internaly_claimed(message.destination_address())
+        {
+            return;
+        }
+
+        // Pass TP, ETP and Fast Packet frames to their managers, the reassembled message is processed like any other message.
+        let message = match message.pgn() {
+            ParameterGroupNumber::TransportProtocolCommand |
+            ParameterGroupNumber::TransportProtocolData => {
+                self.tp_manager.process_can_message(&message)
+            }
+            ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement |
+            ParameterGroupNumber::ExtendedTransportProtocolDataTransfer => {
+                self.etp_manager.process_can_message(&message)
+            }
+            pgn if self.fast_packet_manager.is_fast_packet(pgn) => {
+                self.fast_packet_manager.process_can_message(&message)
+            }
+            _ => Some(message),
+        };
+
+        if let Some(message) = message {
+            self.process_message(message);
+        }
+    }
+
+    /// Process a complete message, received in a single frame or reassembled by one of the protocol managers.
+    fn process_message(&mut self, message: CanMessage) {
+        // Keep track of all the external control functions on the network.
+        match message.pgn() {
+            ParameterGroupNumber::ParameterGroupNumberRequest => {
+                if message.is_address_global() && ParameterGroupNumber::AddressClaim == message.get_pgn_at(0) {
+                    self.address_claim_requested();
+                }
+                self.process_request(&message);
+            }
+            ParameterGroupNumber::RequestForRepetitionRate => {
+                self.process_repetition_rate_request(&message);
+            }
+            ParameterGroupNumber::DiagnosticMessage13 => {
+                let now = TimeDriver::time_elapsed();
+                for icf in self.internal_control_functions_mut() {
+                    if message.is_address_global() || message.is_address_specific(icf.address()) {
+                        icf.diagnostic_protocol_mut().process_dm13(message.data(), now);
+                    }
+                }
+            }
+            ParameterGroupNumber::DiagnosticMessage22 => {
+                self.process_dm22(&message);
+            }
+            ParameterGroupNumber::AddressClaim => {
+                self.update_control_functions_on_the_network(
+                    message.get_name(0),
+                    true,
+                    message.source_address(),
+                );
+            }
+            _ => {}
+        }
+
+        // Check if this answers one of our PGN requests.
+        self.process_request_response(&message);
+
+        // Deliver the message to the subscribers of its PGN.
+        self.process_subscriptions(&message);
+
         // Pass on global messages to all the internal control functions
         if message.is_address_global() {
             for icf in self.internal_control_functions_mut() {
-                icf.process_can_message(message);
+                icf.process_can_message(message.clone());
             }
         }
 
         // Pass on destination specific messages to the specified control function
-        else if let Some(&icf) = self.internal_control_functions().iter()
-            .find(|&icf| message.is_address_specific(icf.address()))
+        else if let Some(icf) = self.internal_control_functions_mut().into_iter()
+            .find(|icf| message.is_address_specific(icf.address()))
         {
             icf.process_can_message(message)
         }
@@ -208,9 +424,102 @@ impl CanNetworkManager {
             self.process_can_message(frame.into());
         }
 
-        // Update all the internal control functions
-        for ControlFunction::Internal(icf) in self.control_functions.values() {
-            icf.update(self);
+        // Report the PGN requests that were not answered in time.
+        let now = TimeDriver::time_elapsed();
+        let mut timed_out: Vec<RequestResult> = Vec::new();
+        self.pending_requests.retain(|request| {
+            if now < request.timeout {
+                return true;
+            }
+            if !request.has_response {
+                timed_out.push(RequestResult {
+                    id: request.id,
+                    address: request.destination,
+                    pgn: request.pgn,
+                    response: RequestResponse::Timeout,
+                });
+            }
+            false
+        });
+        for result in timed_out {
+            self.push_request_result(result);
+        }
+
+        // Send DM1 and the periodic broadcasts of the internal control functions.
+        let mut broadcasts: Vec<CanMessage> = Vec::new();
+        for icf in self.internal_control_functions_mut() {
+            if icf.address() >= Address::NULL {
+                continue;
+            }
+            let source = icf.address();
+
+            if let Some(data) = icf.diagnostic_protocol_mut().update(now) {
+                broadcasts.push(CanMessage::new(
+                    CanPriority::PriorityDefault6,
+                    ParameterGroupNumber::DiagnosticMessage1,
+                    source,
+                    Address::GLOBAL,
+                    &data,
+                ));
+            }
+
+            // A DM13 stop broadcast also suspends the scheduled broadcasts.
+            let is_suspended = icf.diagnostic_protocol().is_broadcast_suspended();
+            if is_suspended {
+                continue;
+            }
+            for (priority, pgn, data) in icf.broadcast_scheduler_mut().update(now) {
+                broadcasts.push(CanMessage::new(priority, pgn, source, Address::GLOBAL, &data));
+            }
+        }
+        for message in broadcasts {
+            self.send_can_message(message);
+        }
+
+        // Update the transport protocol sessions.
+        // The managers are taken out for the duration of the update, so they can send using the network manager.
+        let mut tp_manager = core::mem::take(&mut self.tp_manager);
+        tp_manager.update(self);
+        self.tp_manager = tp_manager;
+
+        let mut etp_manager = core::mem::take(&mut self.etp_manager);
+        etp_manager.update(self);
+        self.etp_manager = etp_manager;
+
+        let mut fast_packet_manager = core::mem::take(&mut self.fast_packet_manager);
+        fast_packet_manager.update(self);
+        self.fast_packet_manager = fast_packet_manager;
+
+        // Control functions that did not answer the last request for address claim have left the bus.
+        if let Some(timestamp) = self.address_claim_request_timestamp {
+            if TimeDriver::time_elapsed().saturating_sub(timestamp) >= ADDRESS_CLAIM_RESPONSE_TIMEOUT {
+                let mut timed_out: Vec<Address> = Vec::new();
+                for ecf in self.external_control_functions_mut() {
+                    if ecf.address() < Address::NULL && ecf.last_address_claim() < timestamp {
+                        log::debug!("[NM]: External control function {} at address {} timed out", ecf.name(), ecf.address());
+                        timed_out.push(ecf.address());
+                        ecf.time_out();
+                    }
+                }
+                for address in timed_out {
+                    self.release_requester(address);
+                }
+                self.address_claim_request_timestamp = None;
+                self.update_partners();
+            }
+        }
+
+        // Update all the internal control functions.
+        // Each one is taken out of the table for the duration of its update, so it can send using the network manager.
+        let handles: Vec<ControlFunctionHandle> = self.control_functions.iter()
+            .filter(|(_, cf)| matches!(cf, ControlFunction::Internal(_)))
+            .map(|(&handle, _)| handle)
+            .collect();
+        for handle in handles {
+            if let Some(ControlFunction::Internal(mut icf)) = self.control_functions.remove(&handle) {
+                icf.update(self);
+                let _ = self.control_functions.insert(handle, ControlFunction::Internal(icf));
+            }
         }
     }
 
@@ -218,13 +527,118 @@ impl CanNetworkManager {
     //     self.send_can_frame_callback = Some(callback);
     // }
 
-    /// Iterates over all messages, removing handled messages using the predicate.
-    ///
-    /// In other words, remove all messages `m` for which `f(&m)` returns `true`.
+    // /// Iterates over all messages, removing handled messages using the predicate.
+    // ///
+    // /// In other words, remove all messages `m` for which `f(&m)` returns `true`.
     // pub fn handle_message<F: FnMut(&CanMessage) -> bool>(&mut self, mut f: F) {
     //     self.received_can_message_queue.retain(move |m| !f(m));
     // }
 
+    pub fn next_address_violation_event(&mut self) -> Option<AddressViolationEvent> {
+        self.address_violation_events.pop_front()
+    }
+
+    /// Request `pgn` from the control function at address `to`, sent by the internal control function `from`.
+    ///
+    /// The outcome is reported by `next_request_result`, with the returned id.
+    /// A request to the global address reports every response until the timeout, or a timeout if nobody responded.
+    /// A `timeout` of `Duration::MAX` never times out.
+    pub fn request_pgn(&mut self, from: ControlFunctionHandle, to: Address, pgn: ParameterGroupNumber, timeout: Duration) -> Result<RequestId, SendError> {
+        let source = self.source_address(from).inspect_err(|_| {
+            log::error!("[NM]: Can not request PGN {:?} without a claimed internal control function", pgn);
+        })?;
+
+        let data: [u8; 3] = pgn.into();
+        let message = CanMessage::new(
+            CanPriority::PriorityDefault6,
+            ParameterGroupNumber::ParameterGroupNumberRequest,
+            source,
+            to,
+            &data,
+        );
+        self.send_can_message(message);
+
+        let id = RequestId(self.next_request_id);
+        self.next_request_id += 1;
+
+        self.pending_requests.push(PendingRequest {
+            id,
+            source,
+            destination: to,
+            pgn,
+            timeout: TimeDriver::time_elapsed().saturating_add(timeout),
+            has_response: false,
+        });
+        Ok(id)
+    }
+
+    /// Send a message from an internal control function, fails when it did not claim an address.
+    pub fn send_from(
+        &mut self,
+        from: ControlFunctionHandle,
+        priority: CanPriority,
+        pgn: ParameterGroupNumber,
+        destination: Address,
+        data: &[u8],
+    ) -> Result<(), SendError> {
+        let source = self.source_address(from).inspect_err(|_| {
+            log::error!("[NM]: Can not send PGN {:?} without a claimed internal control function", pgn);
+        })?;
+
+        self.send_can_message(CanMessage::new(priority, pgn, source, destination, data));
+        Ok(())
+    }
+
+    /// The claimed address of the internal control function `from`.
+    fn source_address(&self, from: ControlFunctionHandle) -> Result<Address, SendError> {
+        match self.internal_control_function(from) {
+            Some(icf) if icf.address() < Address::NULL => Ok(icf.address()),
+            Some(_) => Err(SendError::AddressNotClaimed),
+            None => Err(SendError::UnknownControlFunction),
+        }
+    }
+
+    pub fn next_request_result(&mut self) -> Option<RequestResult> {
+        self.request_results.pop_front()
+    }
+
+    /// Call `callback` for every received `pgn`, optionally filtered on the NAME of the sender and the receiving internal CF.
+    ///
+    /// Messages sent using TP or ETP are delivered once they are reassembled.
+    pub fn subscribe_callback(
+        &mut self,
+        pgn: ParameterGroupNumber,
+        source: Option<Name>,
+        destination: Option<ControlFunctionHandle>,
+        callback: impl FnMut(&CanMessage) + 'static,
+    ) -> SubscriptionId {
+        self.subscribe(pgn, source, destination, Delivery::Callback(Box::new(callback)))
+    }
+
+    /// Queue every received `pgn`, optionally filtered on the NAME of the sender and the receiving internal CF.
+    ///
+    /// The queue keeps the latest `max_size` messages, use `next_subscribed_message` to read them.
+    pub fn subscribe_queue(
+        &mut self,
+        pgn: ParameterGroupNumber,
+        source: Option<Name>,
+        destination: Option<ControlFunctionHandle>,
+        max_size: usize,
+    ) -> SubscriptionId {
+        self.subscribe(pgn, source, destination, Delivery::Queue(VecDeque::new(), max_size))
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) {
+        let _ = self.subscriptions.remove(&id);
+    }
+
+    pub fn next_subscribed_message(&mut self, id: SubscriptionId) -> Option<CanMessage> {
+        match self.subscriptions.get_mut(&id) {
+            Some(Subscription { delivery: Delivery::Queue(queue, _), .. }) => queue.pop_front(),
+            _ => None,
+        }
+    }
+
     pub fn next_free_address(&self, current_address: Address) -> Option<Address> {
         for i in (current_address.0..=247).chain(128..current_address.0) {
             let address = Address(i);
@@ -253,6 +667,18 @@ impl CanNetworkManager {
         self.control_functions.iter()
             .find_map(|(&handle, cf)| if cf.address() == address { Some(handle) } else { None })
     }
+    pub fn name_by_address(&self, address: Address) -> Option<Name> {
+        self.control_functions.values()
+            .find(|cf| cf.address() == address)
+            .map(|cf| cf.name())
+    }
+    fn external_handle_by_name(&self, name: Name) -> Option<ControlFunctionHandle> {
+        self.control_functions.iter()
+            .find_map(|(handle, cf)| match cf {
+                ControlFunction::External(ecf) if ecf.name() == name => Some(*handle),
+                _ => None,
+            })
+    }
 
     // pub fn internal_address(&self, name: Name) -> Option<Address> {
     //     self.control_functions_on_the_network
@@ -283,57 +709,383 @@ impl CanNetworkManager {
         self.control_functions.get(&handle)
     }
     pub fn internal_control_function(&self, handle: ControlFunctionHandle) -> Option<&InternalControlFunction> {
-        self.control_function(handle).map(|ControlFunction::Internal(icf)| icf)
+        match self.control_function(handle) {
+            Some(ControlFunction::Internal(icf)) => Some(icf.as_ref()),
+            _ => None,
+        }
     }
     pub fn external_control_function(&self, handle: ControlFunctionHandle) -> Option<&ExternalControlFunction> {
-        self.control_function(handle).map(|ControlFunction::External(ecf)| ecf)
+        match self.control_function(handle) {
+            Some(ControlFunction::External(ecf)) => Some(ecf),
+            _ => None,
+        }
     }
 
-    pub fn control_function_mut(&self, handle: ControlFunctionHandle) -> Option<&mut ControlFunction> {
+    pub fn control_function_mut(&mut self, handle: ControlFunctionHandle) -> Option<&mut ControlFunction> {
         self.control_functions.get_mut(&handle)
     }
-    pub fn internal_control_function_mut(&self, handle: ControlFunctionHandle) -> Option<&mut InternalControlFunction> {
-        self.control_function_mut(handle).map(|ControlFunction::Internal(icf)| icf)
+    pub fn internal_control_function_mut(&mut self, handle: ControlFunctionHandle) -> Option<&mut InternalControlFunction> {
+        match self.control_function_mut(handle) {
+            Some(ControlFunction::Internal(icf)) => Some(icf.as_mut()),
+            _ => None,
+        }
+    }
+    pub fn external_control_function_mut(&mut self, handle: ControlFunctionHandle) -> Option<&mut ExternalControlFunction> {
+        match self.control_function_mut(handle) {
+            Some(ControlFunction::External(ecf)) => Some(ecf),
+            _ => None,
+        }
     }
-    pub fn external_control_function_mut(&self, handle: ControlFunctionHandle) -> Option<&mut ExternalControlFunction> {
-        self.control_function_mut(handle).map(|ControlFunction::External(ecf)| ecf)
+
+    pub fn partnered_control_function(&self, handle: ControlFunctionHandle) -> Option<&PartneredControlFunction> {
+        match self.control_function(handle) {
+            Some(ControlFunction::Partnered(pcf)) => Some(pcf),
+            _ => None,
+        }
+    }
+    pub fn partnered_control_function_mut(&mut self, handle: ControlFunctionHandle) -> Option<&mut PartneredControlFunction> {
+        match self.control_function_mut(handle) {
+            Some(ControlFunction::Partnered(pcf)) => Some(pcf),
+            _ => None,
+        }
     }
 
     pub fn internal_control_functions(&self) -> Vec<&InternalControlFunction> {
         self.control_functions.values()
-            .map(|ControlFunction::Internal(cf)| cf)
+            .filter_map(|cf| if let ControlFunction::Internal(icf) = cf { Some(icf.as_ref()) } else { None })
             .collect()
     }
     pub fn external_control_functions(&self) -> Vec<&ExternalControlFunction> {
         self.control_functions.values()
-            .map(|ControlFunction::External(cf)| cf)
+            .filter_map(|cf| if let ControlFunction::External(ecf) = cf { Some(ecf) } else { None })
             .collect()
     }
 
-    pub fn internal_control_functions_mut(&self) -> Vec<&mut InternalControlFunction> {
-        self.control_functions.values()
-            .map(|ControlFunction::Internal(cf)| cf)
+    pub fn internal_control_functions_mut(&mut self) -> Vec<&mut InternalControlFunction> {
+        self.control_functions.values_mut()
+            .filter_map(|cf| if let ControlFunction::Internal(icf) = cf { Some(icf.as_mut()) } else { None })
             .collect()
     }
-    pub fn external_control_functions_mut(&self) -> Vec<&mut ExternalControlFunction> {
-        self.control_functions.values()
-            .map(|ControlFunction::External(cf)| cf)
+    pub fn external_control_functions_mut(&mut self) -> Vec<&mut ExternalControlFunction> {
+        self.control_functions.values_mut()
+            .filter_map(|cf| if let ControlFunction::External(ecf) = cf { Some(ecf) } else { None })
             .collect()
     }
 
-    fn update_control_function(
+    /// Keep the table of control functions in sync with the address claims on the bus.
+    fn update_control_functions_on_the_network(
         &mut self,
-        handle: ControlFunctionHandle,
+        name: Name,
+        is_external: bool,
         address: Address,
     ) {
-        if address == Address::NULL {
-            self.control_functions.remove(&handle);
+        // Whoever used this address before lost it to the claimer, it will re-claim or move to another address.
+        if address != Address::NULL {
+            let mut is_lost = false;
+            for ecf in self.external_control_functions_mut() {
+                if ecf.address() == address && ecf.name() != name {
+                    log::debug!("[NM]: External control function {} lost address {} to {}", ecf.name(), address, name);
+                    ecf.set_address(Address::NULL);
+                    is_lost = true;
+                }
+            }
+            if is_lost {
+                self.release_requester(address);
+            }
+        }
+
+        // Internal control functions keep track of their own address.
+        if is_external {
+            self.update_external_control_function(name, address);
+        }
+
+        self.update_partners();
+    }
+
+    fn update_external_control_function(&mut self, name: Name, address: Address) {
+        match self.external_handle_by_name(name) {
+            // A claim from the NULL address is a cannot claim, the control function is no longer on the bus.
+            Some(handle) if address == Address::NULL => {
+                log::debug!("[NM]: External control function {} could not claim an address", name);
+                if let Some(ControlFunction::External(ecf)) = self.control_functions.remove(&handle) {
+                    self.release_requester(ecf.address());
+                }
+            }
+            Some(handle) => {
+                let mut previous_address = None;
+                if let Some(ecf) = self.external_control_function_mut(handle) {
+                    if ecf.address() != address {
+                        log::debug!("[NM]: External control function {} moved from address {} to {}", name, ecf.address(), address);
+                        previous_address = Some(ecf.address());
+                    }
+                    ecf.process_address_claim(address, TimeDriver::time_elapsed());
+                }
+                if let Some(previous_address) = previous_address {
+                    self.release_requester(previous_address);
+                }
+            }
+            None if address != Address::NULL => {
+                log::debug!("[NM]: New external control function {} at address {}", name, address);
+                let _ = self.add_control_function(ControlFunction::External(ExternalControlFunction::new(name, address)));
+            }
+            None => {}
+        }
+    }
+
+    /// Drop the repetition rates requested from `address`, after the CF at that address left the bus.
+    fn release_requester(&mut self, address: Address) {
+        if address >= Address::NULL {
+            return;
+        }
+        for icf in self.internal_control_functions_mut() {
+            icf.broadcast_scheduler_mut().release_requesters(|requester| requester == address);
+        }
+    }
+
+    fn add_control_function(&mut self, control_function: ControlFunction) -> ControlFunctionHandle {
+        let handle = ControlFunctionHandle::new(self.next_control_function_id);
+        self.next_control_function_id += 1;
+
+        let _ = self.control_functions.insert(handle, control_function);
+        handle
+    }
+
+    /// Re-evaluate the partners against the table of external control functions, after it changed.
+    fn update_partners(&mut self) {
+        let external_control_functions: Vec<ExternalControlFunction> = self.external_control_functions()
+            .into_iter()
+            .copied()
+            .collect();
+
+        for cf in self.control_functions.values_mut() {
+            if let ControlFunction::Partnered(pcf) = cf {
+                pcf.update(&external_control_functions);
+            }
+        }
+    }
+
+    /// Answer a PGN request with the responders of the internal control functions it is meant for.
+    fn process_request(&mut self, message: &CanMessage) {
+        if message.len() < 3 {
+            return;
+        }
+        let pgn = message.get_pgn_at(0);
+
+        // Requests for address claim are answered by the address claim state machines.
+        if pgn == ParameterGroupNumber::AddressClaim {
+            return;
+        }
+
+        let mut responses: Vec<CanMessage> = Vec::new();
+        for icf in self.internal_control_functions_mut() {
+            if icf.address() >= Address::NULL
+                || !(message.is_address_global() || message.is_address_specific(icf.address()))
+            {
+                continue;
+            }
+
+            match icf.respond_to_request(pgn, message) {
+                Ok(data) => {
+                    let destination = if message.is_address_global() { Address::GLOBAL } else { message.source_address() };
+                    responses.push(CanMessage::new(
+                        CanPriority::PriorityDefault6,
+                        pgn,
+                        icf.address(),
+                        destination,
+                        &data,
+                    ));
+                }
+                // Only destination specific requests are answered with a NACK, as required by J1939-21.
+                Err(acknowledgement) if !message.is_address_global() => {
+                    responses.push(Self::acknowledgement_message(
+                        acknowledgement,
+                        icf.address(),
+                        message.source_address(),
+                        pgn,
+                    ));
+                }
+                Err(_) => {}
+            }
+        }
+
+        for response in responses {
+            self.send_can_message(response);
+        }
+    }
+
+    /// Adjust the periodic broadcasts of the internal control functions to a Request for Repetition Rate (ISO11783-7).
+    fn process_repetition_rate_request(&mut self, message: &CanMessage) {
+        if message.len() < 5 {
+            return;
+        }
+        let pgn = message.get_pgn_at(0);
+        let repetition_rate = message.get_u16_at(3);
+        let requester = message.source_address();
+
+        for icf in self.internal_control_functions_mut() {
+            if icf.address() < Address::NULL
+                && (message.is_address_global() || message.is_address_specific(icf.address()))
+                && icf.broadcast_scheduler_mut().process_repetition_rate_request(pgn, requester, repetition_rate)
+            {
+                log::debug!("[NM]: {} requested a repetition rate of {} ms for PGN {:?} from {}", requester, repetition_rate, pgn, icf.name());
+            }
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        pgn: ParameterGroupNumber,
+        source: Option<Name>,
+        destination: Option<ControlFunctionHandle>,
+        delivery: Delivery,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.next_subscription_id);
+        self.next_subscription_id += 1;
+
+        let _ = self.subscriptions.insert(id, Subscription { pgn, source, destination, delivery });
+        id
+    }
+
+    fn process_subscriptions(&mut self, message: &CanMessage) {
+        if self.subscriptions.is_empty() {
+            return;
+        }
+
+        let source_name = self.external_control_functions().into_iter()
+            .find(|ecf| ecf.address() == message.source_address())
+            .map(|ecf| ecf.name());
+
+        let matches: Vec<bool> = self.subscriptions.values()
+            .map(|subscription| {
+                subscription.pgn == message.pgn()
+                    && subscription.source.is_none_or(|name| Some(name) == source_name)
+                    && subscription.destination.is_none_or(|handle| {
+                        self.internal_control_function(handle).is_some_and(|icf| {
+                            icf.address() < Address::NULL
+                                && (message.is_address_global() || message.is_address_specific(icf.address()))
+                        })
+                    })
+            })
+            .collect();
+
+        for (subscription, _) in self.subscriptions.values_mut()
+            .zip(matches)
+            .filter(|(_, is_match)| *is_match)
+        {
+            subscription.deliver(message);
+        }
+    }
+
+    /// Answer a DM22 individual DTC clear, it is only accepted destination specific.
+    fn process_dm22(&mut self, message: &CanMessage) {
+        let response = self.internal_control_functions_mut().into_iter()
+            .find(|icf| icf.address() < Address::NULL && message.is_address_specific(icf.address()))
+            .and_then(|icf| {
+                icf.diagnostic_protocol_mut()
+                    .process_dm22(message.data())
+                    .map(|data| (icf.address(), data))
+            });
+
+        if let Some((source, data)) = response {
+            self.send_can_message(CanMessage::new(
+                CanPriority::PriorityDefault6,
+                ParameterGroupNumber::DiagnosticMessage22,
+                source,
+                message.source_address(),
+                &data,
+            ));
+        }
+    }
+
+    /// Match a received message against our pending PGN requests.
+    fn process_request_response(&mut self, message: &CanMessage) {
+        if self.pending_requests.is_empty() {
+            return;
+        }
+
+        // An acknowledgement holds the address of the requester in byte 4 and the acknowledged PGN in the last 3 bytes.
+        let acknowledgement = if message.pgn() == ParameterGroupNumber::Acknowledge && message.len() >= 8 {
+            AcknowledgementType::try_from(message.get_u8_at(0))
+                .ok()
+                .map(|acknowledgement| (acknowledgement, Address(message.get_u8_at(4)), message.get_pgn_at(5)))
         } else {
-            if let Some(cf) = self.control_function(handle) {
-                cf.set_address(address);
+            None
+        };
+
+        let mut results: Vec<RequestResult> = Vec::new();
+        self.pending_requests.retain_mut(|request| {
+            if (request.destination != Address::GLOBAL && message.source_address() != request.destination)
+                || !(message.is_address_global() || message.is_address_specific(request.source))
+            {
+                return true;
             }
+
+            let response = match acknowledgement {
+                Some((acknowledgement, address, pgn)) if address == request.source && pgn == request.pgn => {
+                    RequestResponse::Acknowledgement(acknowledgement)
+                }
+                _ if message.pgn() == request.pgn => RequestResponse::Response(message.clone()),
+                _ => return true,
+            };
+
+            results.push(RequestResult {
+                id: request.id,
+                address: message.source_address(),
+                pgn: request.pgn,
+                response,
+            });
+            request.has_response = true;
+
+            // Global requests collect responses until they time out.
+            request.destination == Address::GLOBAL
+        });
+
+        for result in results {
+            self.push_request_result(result);
+        }
+    }
+
+    fn push_request_result(&mut self, result: RequestResult) {
+        self.request_results.push_back(result);
+
+        while self.request_results.len() > MAX_REQUEST_RESULT_QUEUE_SIZE {
+            self.request_results.pop_front();
+        }
+    }
+
+    /// Build an acknowledgement for `pgn`, the acknowledged address is the CF that sent the request.
+    fn acknowledgement_message(
+        acknowledgement: AcknowledgementType,
+        source: Address,
+        acknowledged_address: Address,
+        pgn: ParameterGroupNumber,
+    ) -> CanMessage {
+        let pgn: [u8; 3] = pgn.into();
+        let data: [u8; 8] = [
+            acknowledgement as u8,
+            0xFF,
+            0xFF,
+            0xFF,
+            acknowledged_address.into(),
+            pgn[0],
+            pgn[1],
+            pgn[2],
+        ];
+
+        CanMessage::new(
+            CanPriority::PriorityDefault6,
+            ParameterGroupNumber::Acknowledge,
+            source,
+            Address::GLOBAL,
+            &data,
+        )
+    }
+
+    /// Start the response window in which all control functions must re-send their address claim.
+    fn address_claim_requested(&mut self) {
+        if self.address_claim_request_timestamp.is_none() {
+            self.address_claim_request_timestamp = Some(TimeDriver::time_elapsed());
         }
-        // log::debug!("{:?}", self.control_functions_on_the_network);
     }
 
     pub fn send_request_address_claim(&mut self) {
@@ -347,28 +1099,66 @@ impl CanNetworkManager {
             &data,
         );
         self.send_can_message(message);
+        self.address_claim_requested();
     }
 
-    pub fn send_address_claim(&mut self, handle: ControlFunctionHandle, address: Address) {
-        if address == Address::GLOBAL || address == Address::NULL {
+    /// Command the control function with `name` to move to `address`, sent from the internal control function `handle`.
+    pub fn send_commanded_address(&mut self, handle: ControlFunctionHandle, name: Name, address: Address) {
+        if address >= Address::NULL {
+            log::error!("[NM]: Can not command {} to the invalid address {}", name, address);
             return;
         }
 
-        if let Some(icf) = self.internal_control_function_mut(handle) {
-            let data: [u8; 8] = icf.name().into();
+        if let Some(icf) = self.internal_control_function(handle) {
+            if icf.address() >= Address::NULL {
+                log::error!("[NM]: Internal control function {} needs a claimed address to send a commanded address", icf.name());
+                return;
+            }
+
+            let mut data = [0u8; 9];
+            data[..8].copy_from_slice(&<[u8; 8]>::from(name));
+            data[8] = address.into();
 
+            // The 9 byte message is broadcast using BAM.
             let message = CanMessage::new(
                 CanPriority::PriorityDefault6,
-                ParameterGroupNumber::AddressClaim,
-                address,
+                ParameterGroupNumber::CommandedAddress,
+                icf.address(),
                 Address::GLOBAL,
                 &data,
             );
             self.send_can_message(message);
+        }
+    }
+
+    pub fn send_cannot_claim_address(&mut self, name: Name) {
+        let data: [u8; 8] = name.into();
+
+        let message = CanMessage::new(
+            CanPriority::PriorityDefault6,
+            ParameterGroupNumber::AddressClaim,
+            Address::NULL,
+            Address::GLOBAL,
+            &data,
+        );
+        self.send_can_message(message);
+    }
 
-            icf.set_address(address);
-            // self.update_control_functions_on_the_network(name, false, address);
+    pub fn send_address_claim(&mut self, name: Name, address: Address) {
+        if address == Address::GLOBAL || address == Address::NULL {
+            return;
         }
+
+        let data: [u8; 8] = name.into();
+
+        let message = CanMessage::new(
+            CanPriority::PriorityDefault6,
+            ParameterGroupNumber::AddressClaim,
+            address,
+            Address::GLOBAL,
+            &data,
+        );
+        self.send_can_message(message);
     }
 }
 
@@ -391,3 +1181,612 @@ impl CanNetworkManager {
 //             .cloned()
 //     }
 // }
+
+#[cfg(all(test, feature = "mock_can_driver"))]
+mod tests {
+    use super::*;
+    use crate::control_function::PartnerEvent;
+    use crate::diagnostics::{DiagnosticClear, DiagnosticTroubleCode};
+    use crate::hardware_integration::CanDriver;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    const LOCAL: Address = Address(0x80);
+    const REMOTE: Address = Address(0x26);
+
+    /// Create a network manager with an internal control function that claimed `LOCAL`.
+    fn setup() -> (CanNetworkManager, CanDriver, ControlFunctionHandle) {
+        let driver = CanDriver::new();
+        let mut network_manager = CanNetworkManager::new(driver.clone());
+        let handle = claim(&mut network_manager, 1, LOCAL);
+        let _ = driver.take_written_frames();
+        (network_manager, driver, handle)
+    }
+
+    /// Add an internal control function and wait until it claimed `address`.
+    fn claim(network_manager: &mut CanNetworkManager, identity_number: u32, address: Address) -> ControlFunctionHandle {
+        let name = Name::builder().identity_number(identity_number).build();
+        let handle = network_manager.new_internal_control_function(name, address);
+        network_manager.internal_control_function_mut(handle).unwrap().initialize();
+
+        for _ in 0..100 {
+            network_manager.update();
+            if network_manager.is_address_internaly_claimed(address) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(network_manager.is_address_internaly_claimed(address));
+        handle
+    }
+
+    fn receive(driver: &CanDriver, pgn: ParameterGroupNumber, source: Address, destination: Address, data: &[u8]) {
+        driver.receive(CanMessage::new(CanPriority::PriorityDefault6, pgn, source, destination, data).as_can_frame().unwrap());
+    }
+
+    fn receive_request(driver: &CanDriver, pgn: ParameterGroupNumber, destination: Address) {
+        receive(driver, ParameterGroupNumber::ParameterGroupNumberRequest, REMOTE, destination, &pgn.as_bytes());
+    }
+
+    /// The messages send since the last call, without the DM1 broadcasts.
+    fn sent(driver: &CanDriver) -> Vec<CanMessage> {
+        driver.take_written_frames().into_iter()
+            .map(CanMessage::from)
+            .filter(|message| message.pgn() != ParameterGroupNumber::DiagnosticMessage1)
+            .collect()
+    }
+
+    #[test]
+    fn nacks_only_destination_specific_requests() {
+        let (mut network_manager, driver, _) = setup();
+
+        receive_request(&driver, ParameterGroupNumber::ProprietaryA, Address::GLOBAL);
+        network_manager.update();
+        assert!(sent(&driver).is_empty());
+
+        receive_request(&driver, ParameterGroupNumber::ProprietaryA, LOCAL);
+        network_manager.update();
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].pgn(), ParameterGroupNumber::Acknowledge);
+        assert_eq!(messages[0].source_address(), LOCAL);
+        assert_eq!(messages[0].data(), [0x01, 0xFF, 0xFF, 0xFF, REMOTE.0, 0x00, 0xEF, 0x00]);
+
+        // Requests for other CFs are not ours to answer.
+        receive_request(&driver, ParameterGroupNumber::ProprietaryA, Address(0x81));
+        network_manager.update();
+        assert!(sent(&driver).is_empty());
+    }
+
+    #[test]
+    fn answers_requests_with_the_responder() {
+        let (mut network_manager, driver, handle) = setup();
+        let pgn = ParameterGroupNumber::SoftwareIdentification;
+        network_manager.internal_control_function_mut(handle).unwrap()
+            .add_request_responder(pgn, |_| Some(alloc::vec![1, 2, 3, 4, 5, 6, 7, 8]));
+
+        receive_request(&driver, pgn, LOCAL);
+        network_manager.update();
+        assert_eq!(sent(&driver), [CanMessage::new(CanPriority::PriorityDefault6, pgn, LOCAL, Address::GLOBAL, &[1, 2, 3, 4, 5, 6, 7, 8])]);
+
+        receive_request(&driver, pgn, Address::GLOBAL);
+        network_manager.update();
+        assert_eq!(sent(&driver).len(), 1);
+    }
+
+    #[test]
+    fn routes_messages_to_their_destination() {
+        let (mut network_manager, driver, handle) = setup();
+        let pgn = ParameterGroupNumber::ProprietaryA;
+        let queue_len = |network_manager: &CanNetworkManager| {
+            network_manager.internal_control_function(handle).unwrap().received_can_message_queue.len()
+        };
+
+        receive(&driver, pgn, REMOTE, Address(0x81), &[1]);
+        network_manager.update();
+        assert_eq!(queue_len(&network_manager), 0);
+
+        receive(&driver, pgn, REMOTE, LOCAL, &[2]);
+        receive(&driver, pgn, REMOTE, Address::GLOBAL, &[3]);
+        network_manager.update();
+        assert_eq!(queue_len(&network_manager), 2);
+    }
+
+    #[test]
+    fn request_results() {
+        let (mut network_manager, driver, handle) = setup();
+        let software_id = network_manager.request_pgn(handle, REMOTE, ParameterGroupNumber::SoftwareIdentification, Duration::from_secs(1)).unwrap();
+        let proprietary_id = network_manager.request_pgn(handle, REMOTE, ParameterGroupNumber::ProprietaryA, Duration::from_secs(1)).unwrap();
+        assert_ne!(software_id, proprietary_id);
+
+        // A NACK for a request of another CF is ignored.
+        receive(&driver, ParameterGroupNumber::Acknowledge, REMOTE, Address::GLOBAL, &[0x01, 0xFF, 0xFF, 0xFF, 0x81, 0x00, 0xEF, 0x00]);
+        network_manager.update();
+        assert_eq!(network_manager.next_request_result(), None);
+
+        receive(&driver, ParameterGroupNumber::Acknowledge, REMOTE, Address::GLOBAL, &[0x01, 0xFF, 0xFF, 0xFF, LOCAL.0, 0x00, 0xEF, 0x00]);
+        network_manager.update();
+        assert_eq!(network_manager.next_request_result(), Some(RequestResult {
+            id: proprietary_id,
+            address: REMOTE,
+            pgn: ParameterGroupNumber::ProprietaryA,
+            response: RequestResponse::Acknowledgement(AcknowledgementType::Negative),
+        }));
+
+        let timeout_id = network_manager.request_pgn(handle, REMOTE, ParameterGroupNumber::ProprietaryA, Duration::ZERO).unwrap();
+        network_manager.update();
+        assert_eq!(network_manager.next_request_result().map(|result| (result.id, result.response)), Some((timeout_id, RequestResponse::Timeout)));
+        assert_eq!(network_manager.next_request_result(), None);
+        assert_eq!(network_manager.pending_requests.len(), 1);
+
+        // A request without a timeout waits for its response.
+        let id = network_manager.request_pgn(handle, REMOTE, ParameterGroupNumber::SoftwareIdentification, Duration::MAX).unwrap();
+        network_manager.update();
+        assert_eq!(network_manager.next_request_result(), None);
+        assert!(network_manager.pending_requests.iter().any(|request| request.id == id));
+    }
+
+    #[test]
+    fn releases_repetition_rates_of_lost_control_functions() {
+        let (mut network_manager, driver, handle) = setup();
+        let pgn = ParameterGroupNumber::MaintainPower;
+        network_manager.internal_control_function_mut(handle).unwrap()
+            .add_periodic_broadcast(pgn, CanPriority::PriorityDefault6, Duration::from_millis(1000), || Some(alloc::vec![0xFF; 8]));
+        let interval = |network_manager: &CanNetworkManager| {
+            network_manager.internal_control_function(handle).unwrap().periodic_broadcast_interval(pgn)
+        };
+
+        let remote_name: [u8; 8] = Name::builder().identity_number(20).build().into();
+        receive(&driver, ParameterGroupNumber::AddressClaim, REMOTE, Address::GLOBAL, &remote_name);
+        receive(&driver, ParameterGroupNumber::RequestForRepetitionRate, REMOTE, LOCAL, &[0x47, 0xFE, 0x00, 100, 0, 0xFF, 0xFF, 0xFF]);
+        network_manager.update();
+        assert_eq!(interval(&network_manager), Some(Duration::from_millis(100)));
+
+        // The requester stays on the bus.
+        for _ in 0..3 {
+            network_manager.update();
+        }
+        assert_eq!(interval(&network_manager), Some(Duration::from_millis(100)));
+
+        // The requester loses its address to a NAME with a higher priority.
+        let other_name: [u8; 8] = Name::builder().identity_number(10).build().into();
+        receive(&driver, ParameterGroupNumber::AddressClaim, REMOTE, Address::GLOBAL, &other_name);
+        network_manager.update();
+        assert_eq!(interval(&network_manager), Some(Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn tracks_external_control_functions() {
+        let (mut network_manager, driver, _) = setup();
+        let remote_name = Name::builder().identity_number(20).build();
+        let external = |network_manager: &CanNetworkManager| {
+            network_manager.external_control_functions().into_iter()
+                .map(|ecf| (ecf.name(), ecf.address()))
+                .collect::<Vec<_>>()
+        };
+
+        // An address claim adds the control function.
+        receive(&driver, ParameterGroupNumber::AddressClaim, REMOTE, Address::GLOBAL, &<[u8; 8]>::from(remote_name));
+        network_manager.update();
+        assert_eq!(external(&network_manager), [(remote_name, REMOTE)]);
+        assert_eq!(network_manager.name_by_address(REMOTE), Some(remote_name));
+
+        // A claim for another address moves it, the handle stays the same.
+        let handle = network_manager.handle_by_address(REMOTE).unwrap();
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x30), Address::GLOBAL, &<[u8; 8]>::from(remote_name));
+        network_manager.update();
+        assert_eq!(external(&network_manager), [(remote_name, Address(0x30))]);
+        assert_eq!(network_manager.handle_by_address(Address(0x30)), Some(handle));
+        assert!(!network_manager.is_address_externaly_claimed(REMOTE));
+
+        // A cannot claim address, sent from the NULL address, removes it.
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address::NULL, Address::GLOBAL, &<[u8; 8]>::from(remote_name));
+        network_manager.update();
+        assert!(external(&network_manager).is_empty());
+        assert!(network_manager.external_control_function(handle).is_none());
+    }
+
+    #[test]
+    fn times_out_external_control_functions() {
+        let (mut network_manager, driver, _) = setup();
+        let remote_name = Name::builder().identity_number(20).build();
+        let other_name = Name::builder().identity_number(21).build();
+        receive(&driver, ParameterGroupNumber::AddressClaim, REMOTE, Address::GLOBAL, &<[u8; 8]>::from(remote_name));
+        network_manager.update();
+
+        // Wait for the request for address claim send while claiming `LOCAL` to complete.
+        std::thread::sleep(ADDRESS_CLAIM_RESPONSE_TIMEOUT);
+        network_manager.update();
+        assert!(network_manager.is_address_externaly_claimed(REMOTE));
+
+        // Only the control functions that answer the request for address claim stay on the bus.
+        receive_request(&driver, ParameterGroupNumber::AddressClaim, Address::GLOBAL);
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x27), Address::GLOBAL, &<[u8; 8]>::from(other_name));
+        network_manager.update();
+        assert!(network_manager.is_address_externaly_claimed(REMOTE));
+
+        std::thread::sleep(ADDRESS_CLAIM_RESPONSE_TIMEOUT);
+        network_manager.update();
+        let remote = network_manager.external_control_functions().into_iter()
+            .find(|ecf| ecf.name() == remote_name)
+            .copied()
+            .unwrap();
+        assert!(remote.is_timed_out());
+        assert_eq!(remote.address(), Address::NULL);
+        assert!(!network_manager.is_address_externaly_claimed(REMOTE));
+        assert_eq!(network_manager.name_by_address(Address(0x27)), Some(other_name));
+
+        // A new claim brings it back.
+        receive(&driver, ParameterGroupNumber::AddressClaim, REMOTE, Address::GLOBAL, &<[u8; 8]>::from(remote_name));
+        network_manager.update();
+        assert_eq!(network_manager.name_by_address(REMOTE), Some(remote_name));
+    }
+
+    #[test]
+    fn partners_follow_the_network() {
+        let (mut network_manager, driver, _) = setup();
+        let partner_name = Name::builder().manufacturer_code(42).identity_number(20).build();
+        let backup_name = Name::builder().manufacturer_code(42).identity_number(21).build();
+        let other_name = Name::builder().manufacturer_code(7).identity_number(1).build();
+        let handle = network_manager.new_partnered_control_function(&[NameFilter::ManufacturerCode(42)]);
+        let events = |network_manager: &mut CanNetworkManager, handle| {
+            let partner = network_manager.partnered_control_function_mut(handle).unwrap();
+            core::iter::from_fn(|| partner.next_event())
+                .map(|event| match event {
+                    PartnerEvent::Found(ecf) => (true, ecf.name(), ecf.address()),
+                    PartnerEvent::Lost(ecf) => (false, ecf.name(), ecf.address()),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x27), Address::GLOBAL, &<[u8; 8]>::from(other_name));
+        receive(&driver, ParameterGroupNumber::AddressClaim, REMOTE, Address::GLOBAL, &<[u8; 8]>::from(partner_name));
+        network_manager.update();
+        assert_eq!(events(&mut network_manager, handle), [(true, partner_name, REMOTE)]);
+
+        // The partner moves to another address, the partnered CF follows without new events.
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x30), Address::GLOBAL, &<[u8; 8]>::from(partner_name));
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x31), Address::GLOBAL, &<[u8; 8]>::from(backup_name));
+        network_manager.update();
+        assert!(events(&mut network_manager, handle).is_empty());
+        assert_eq!(network_manager.partnered_control_function(handle).unwrap().address(), Some(Address(0x30)));
+        assert_eq!(network_manager.control_function(handle).unwrap().address(), Address(0x30));
+
+        // The partner leaves the bus, the next matching ECF takes its place.
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address::NULL, Address::GLOBAL, &<[u8; 8]>::from(partner_name));
+        network_manager.update();
+        assert_eq!(events(&mut network_manager, handle), [(false, partner_name, Address(0x30)), (true, backup_name, Address(0x31))]);
+
+        // A partner that is already on the bus is found right away.
+        let late = network_manager.new_partnered_control_function(&[NameFilter::IdentityNumber(1)]);
+        assert_eq!(events(&mut network_manager, late), [(true, other_name, Address(0x27))]);
+
+        // Losing its address to another NAME also loses the partner.
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x31), Address::GLOBAL, &<[u8; 8]>::from(Name::builder().identity_number(2).build()));
+        network_manager.update();
+        assert_eq!(events(&mut network_manager, handle), [(false, backup_name, Address(0x31))]);
+        assert!(!network_manager.partnered_control_function(handle).unwrap().is_partnered());
+    }
+
+    /// Receive a commanded address for `name` from `REMOTE`, it is 9 bytes so it is broadcast using BAM.
+    fn receive_commanded_address(driver: &CanDriver, name: Name, address: Address) {
+        let name: [u8; 8] = name.into();
+        let pgn = ParameterGroupNumber::CommandedAddress.as_bytes();
+        receive(driver, ParameterGroupNumber::TransportProtocolCommand, REMOTE, Address::GLOBAL, &[0x20, 9, 0, 2, 0xFF, pgn[0], pgn[1], pgn[2]]);
+        receive(driver, ParameterGroupNumber::TransportProtocolData, REMOTE, Address::GLOBAL, &[1, name[0], name[1], name[2], name[3], name[4], name[5], name[6]]);
+        receive(driver, ParameterGroupNumber::TransportProtocolData, REMOTE, Address::GLOBAL, &[2, name[7], address.0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn moves_to_the_commanded_address() {
+        let (mut network_manager, driver, handle) = setup();
+        let name = network_manager.internal_control_function(handle).unwrap().name();
+
+        // A commanded address for another NAME is ignored.
+        receive_commanded_address(&driver, Name::builder().identity_number(2).build(), Address(0x90));
+        network_manager.update();
+        std::thread::sleep(Duration::from_millis(500));
+        network_manager.update();
+        assert!(network_manager.is_address_internaly_claimed(LOCAL));
+        assert!(sent(&driver).iter().all(|message| message.pgn() != ParameterGroupNumber::AddressClaim));
+
+        // Our NAME re-claims at the commanded address.
+        receive_commanded_address(&driver, name, Address(0x90));
+        for _ in 0..100 {
+            network_manager.update();
+            if network_manager.is_address_internaly_claimed(Address(0x90)) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(network_manager.internal_control_function(handle).unwrap().address(), Address(0x90));
+        assert!(!network_manager.is_address_internaly_claimed(LOCAL));
+        let claim = sent(&driver).into_iter()
+            .find(|message| message.pgn() == ParameterGroupNumber::AddressClaim && message.source_address() == Address(0x90))
+            .unwrap();
+        assert_eq!(claim.get_name(0), name);
+    }
+
+    #[test]
+    fn sends_commanded_address() {
+        let (mut network_manager, driver, handle) = setup();
+        let remote_name = Name::builder().identity_number(20).build();
+        let name: [u8; 8] = remote_name.into();
+
+        // Invalid addresses are not send.
+        network_manager.send_commanded_address(handle, remote_name, Address::NULL);
+        network_manager.update();
+        assert!(sent(&driver).is_empty());
+
+        network_manager.send_commanded_address(handle, remote_name, Address(0x30));
+        let mut messages = Vec::new();
+        for _ in 0..4 {
+            network_manager.update();
+            messages.extend(sent(&driver));
+            std::thread::sleep(Duration::from_millis(60));
+        }
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|message| message.source_address() == LOCAL && message.is_address_global()));
+        assert_eq!(messages[0].data(), [0x20, 9, 0, 2, 0xFF, 0xD8, 0xFE, 0x00]);
+        assert_eq!(messages[1].data(), [1, name[0], name[1], name[2], name[3], name[4], name[5], name[6]]);
+        assert_eq!(messages[2].data(), [2, name[7], 0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn address_violation_raises_dtc() {
+        let (mut network_manager, driver, handle) = setup();
+
+        receive(&driver, ParameterGroupNumber::ProprietaryA, LOCAL, Address::GLOBAL, &[0xFF; 8]);
+        receive(&driver, ParameterGroupNumber::ProprietaryA, LOCAL, Address::GLOBAL, &[0xFF; 8]);
+        network_manager.update();
+
+        // The address is re-claimed.
+        let claims = driver.take_written_frames().into_iter()
+            .map(CanMessage::from)
+            .filter(|message| message.pgn() == ParameterGroupNumber::AddressClaim)
+            .count();
+        assert!(claims > 0);
+
+        let event = network_manager.next_address_violation_event().unwrap();
+        assert_eq!((event.address, event.occurrence_count), (LOCAL, 1));
+        assert_eq!(network_manager.next_address_violation_event().unwrap().occurrence_count, 2);
+
+        let icf = network_manager.internal_control_function(handle).unwrap();
+        assert_eq!(icf.diagnostic_protocol().active_dtcs(), [DiagnosticTroubleCode {
+            suspect_parameter_number: 2000 + LOCAL.0 as u32,
+            failure_mode_indicator: 7,
+            occurrence_count: 2,
+        }]);
+        // SPN 2128 = 0x850, FMI 7, occurrence count 2.
+        assert_eq!(icf.diagnostic_protocol().dm1_payload()[2..6], [0x50, 0x08, 0x07, 0x02]);
+    }
+
+    #[test]
+    fn runs_tp_and_etp_sessions_concurrently() {
+        let (mut network_manager, driver, _) = setup();
+        let pgn = ParameterGroupNumber::ProprietaryA.as_bytes();
+        let control_bytes = |messages: &[CanMessage], protocol: ParameterGroupNumber| {
+            messages.iter()
+                .filter(|message| message.pgn() == protocol)
+                .map(|message| (message.get_u8_at(0), message.destination_address()))
+                .collect::<Vec<_>>()
+        };
+
+        // The session limit applies to TP and ETP separately, the second TP message waits in the backlog.
+        network_manager.set_max_transport_protocol_sessions(1);
+        network_manager.send_can_message(CanMessage::new(CanPriority::PriorityDefault6, ParameterGroupNumber::ProprietaryA, LOCAL, REMOTE, &[0; 100]));
+        network_manager.send_can_message(CanMessage::new(CanPriority::PriorityDefault6, ParameterGroupNumber::ProprietaryA, LOCAL, REMOTE, &[0; 2000]));
+        network_manager.send_can_message(CanMessage::new(CanPriority::PriorityDefault6, ParameterGroupNumber::ProprietaryA, LOCAL, Address(0x27), &[0; 100]));
+        network_manager.update();
+        let messages = sent(&driver);
+        assert_eq!(control_bytes(&messages, ParameterGroupNumber::TransportProtocolCommand), [(0x10, REMOTE)]);
+        assert_eq!(control_bytes(&messages, ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement), [(0x14, REMOTE)]);
+
+        // Both sessions send their data once cleared.
+        receive(&driver, ParameterGroupNumber::TransportProtocolCommand, REMOTE, LOCAL, &[0x11, 16, 1, 0xFF, 0xFF, pgn[0], pgn[1], pgn[2]]);
+        receive(&driver, ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement, REMOTE, LOCAL, &[0x15, 16, 1, 0, 0, pgn[0], pgn[1], pgn[2]]);
+        network_manager.update();
+        network_manager.update();
+        let messages = sent(&driver);
+        let count = |protocol: ParameterGroupNumber| messages.iter().filter(|message| message.pgn() == protocol).count();
+        assert_eq!(count(ParameterGroupNumber::TransportProtocolData), 15);
+        assert_eq!(control_bytes(&messages, ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement), [(0x16, REMOTE)]);
+        assert_eq!(count(ParameterGroupNumber::ExtendedTransportProtocolDataTransfer), 16);
+
+        // The backlogged TP message is send once the first TP session is acknowledged.
+        receive(&driver, ParameterGroupNumber::TransportProtocolCommand, REMOTE, LOCAL, &[0x13, 100, 0, 15, 0xFF, pgn[0], pgn[1], pgn[2]]);
+        network_manager.update();
+        network_manager.update();
+        assert_eq!(control_bytes(&sent(&driver), ParameterGroupNumber::TransportProtocolCommand), [(0x10, Address(0x27))]);
+    }
+
+    #[test]
+    fn subscribe_callback() {
+        let (mut network_manager, driver, _) = setup();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let id = network_manager.subscribe_callback(ParameterGroupNumber::ProprietaryA, None, None, {
+            let received = received.clone();
+            move |message| received.borrow_mut().push(message.get_u8_at(0))
+        });
+
+        receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, LOCAL, &[1]);
+        receive(&driver, ParameterGroupNumber::ProprietaryB, REMOTE, LOCAL, &[2]);
+        receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, Address::GLOBAL, &[3]);
+        network_manager.update();
+        assert_eq!(*received.borrow(), [1, 3]);
+
+        network_manager.unsubscribe(id);
+        receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, LOCAL, &[4]);
+        network_manager.update();
+        assert_eq!(*received.borrow(), [1, 3]);
+    }
+
+    #[test]
+    fn subscribe_queue_filters_on_source_name() {
+        let (mut network_manager, driver, _) = setup();
+        let remote_name = Name::builder().identity_number(20).build();
+        let other_name = Name::builder().identity_number(21).build();
+        receive(&driver, ParameterGroupNumber::AddressClaim, REMOTE, Address::GLOBAL, &<[u8; 8]>::from(remote_name));
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x27), Address::GLOBAL, &<[u8; 8]>::from(other_name));
+        network_manager.update();
+        let id = network_manager.subscribe_queue(ParameterGroupNumber::ProprietaryA, Some(remote_name), None, 8);
+
+        receive(&driver, ParameterGroupNumber::ProprietaryA, Address(0x27), Address::GLOBAL, &[1]);
+        receive(&driver, ParameterGroupNumber::ProprietaryA, Address(0x28), Address::GLOBAL, &[2]);
+        receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, Address::GLOBAL, &[3]);
+        network_manager.update();
+        assert_eq!(network_manager.next_subscribed_message(id).map(|message| message.get_u8_at(0)), Some(3));
+        assert_eq!(network_manager.next_subscribed_message(id), None);
+
+        // The filter follows the NAME to its new address.
+        receive(&driver, ParameterGroupNumber::AddressClaim, Address(0x30), Address::GLOBAL, &<[u8; 8]>::from(remote_name));
+        receive(&driver, ParameterGroupNumber::ProprietaryA, Address(0x30), Address::GLOBAL, &[4]);
+        network_manager.update();
+        assert_eq!(network_manager.next_subscribed_message(id).map(|message| message.get_u8_at(0)), Some(4));
+    }
+
+    #[test]
+    fn subscribe_queue_filters_on_destination() {
+        let (mut network_manager, driver, handle) = setup();
+        let other = claim(&mut network_manager, 2, Address(0x81));
+        let id = network_manager.subscribe_queue(ParameterGroupNumber::ProprietaryA, None, Some(handle), 8);
+        let other_id = network_manager.subscribe_queue(ParameterGroupNumber::ProprietaryA, None, Some(other), 8);
+
+        receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, LOCAL, &[1]);
+        receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, Address(0x81), &[2]);
+        receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, Address::GLOBAL, &[3]);
+        network_manager.update();
+        let mut next = |id| network_manager.next_subscribed_message(id).map(|message| message.get_u8_at(0));
+        assert_eq!([next(id), next(id), next(id)], [Some(1), Some(3), None]);
+        assert_eq!([next(other_id), next(other_id), next(other_id)], [Some(2), Some(3), None]);
+    }
+
+    #[test]
+    fn subscribe_queue_keeps_the_latest_messages() {
+        let (mut network_manager, driver, _) = setup();
+        let id = network_manager.subscribe_queue(ParameterGroupNumber::ProprietaryA, None, None, 2);
+
+        for value in 1..=3 {
+            receive(&driver, ParameterGroupNumber::ProprietaryA, REMOTE, LOCAL, &[value]);
+        }
+        network_manager.update();
+        let mut next = || network_manager.next_subscribed_message(id).map(|message| message.get_u8_at(0));
+        assert_eq!([next(), next(), next()], [Some(2), Some(3), None]);
+    }
+
+    #[test]
+    fn subscriptions_receive_tp_messages_once_complete() {
+        let (mut network_manager, driver, _) = setup();
+        let pgn = ParameterGroupNumber::ProprietaryA.as_bytes();
+        let id = network_manager.subscribe_queue(ParameterGroupNumber::ProprietaryA, None, None, 8);
+
+        receive(&driver, ParameterGroupNumber::TransportProtocolCommand, REMOTE, Address::GLOBAL, &[0x20, 20, 0, 3, 0xFF, pgn[0], pgn[1], pgn[2]]);
+        receive(&driver, ParameterGroupNumber::TransportProtocolData, REMOTE, Address::GLOBAL, &[1, 0, 1, 2, 3, 4, 5, 6]);
+        receive(&driver, ParameterGroupNumber::TransportProtocolData, REMOTE, Address::GLOBAL, &[2, 7, 8, 9, 10, 11, 12, 13]);
+        network_manager.update();
+        assert_eq!(network_manager.next_subscribed_message(id), None);
+
+        receive(&driver, ParameterGroupNumber::TransportProtocolData, REMOTE, Address::GLOBAL, &[3, 14, 15, 16, 17, 18, 19, 0xFF]);
+        network_manager.update();
+        let message = network_manager.next_subscribed_message(id).unwrap();
+        assert_eq!((message.source_address(), message.destination_address()), (REMOTE, Address::GLOBAL));
+        assert_eq!(message.data(), (0..20).collect::<Vec<u8>>());
+        assert_eq!(network_manager.next_subscribed_message(id), None);
+    }
+
+    #[test]
+    fn subscriptions_receive_etp_messages_once_complete() {
+        let (mut network_manager, driver, _) = setup();
+        let pgn = ParameterGroupNumber::ProprietaryA.as_bytes();
+        let data: Vec<u8> = (0..1800).map(|i| i as u8).collect();
+        let id = network_manager.subscribe_queue(ParameterGroupNumber::ProprietaryA, None, None, 8);
+
+        let size = (data.len() as u32).to_le_bytes();
+        receive(&driver, ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement, REMOTE, LOCAL, &[0x14, size[0], size[1], size[2], size[3], pgn[0], pgn[1], pgn[2]]);
+
+        // Answer every CTS with a DPO and the requested window of packets.
+        loop {
+            network_manager.update();
+            let clear_to_send = sent(&driver).into_iter().find(|message| {
+                message.pgn() == ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement && message.get_u8_at(0) == 0x15
+            });
+            let Some(clear_to_send) = clear_to_send else {
+                break;
+            };
+            assert_eq!(network_manager.next_subscribed_message(id), None);
+
+            let number_of_packets = clear_to_send.get_u8_at(1);
+            let offset = (clear_to_send.get_u24_at(2) - 1).to_le_bytes();
+            receive(&driver, ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement, REMOTE, LOCAL, &[0x16, number_of_packets, offset[0], offset[1], offset[2], pgn[0], pgn[1], pgn[2]]);
+            for sequence_number in 1..=number_of_packets {
+                let start = (clear_to_send.get_u24_at(2) as usize + sequence_number as usize - 2) * 7;
+                let mut packet = [0xFF; 8];
+                packet[0] = sequence_number;
+                for (i, byte) in data.iter().skip(start).take(7).enumerate() {
+                    packet[i + 1] = *byte;
+                }
+                receive(&driver, ParameterGroupNumber::ExtendedTransportProtocolDataTransfer, REMOTE, LOCAL, &packet);
+            }
+        }
+
+        let message = network_manager.next_subscribed_message(id).unwrap();
+        assert_eq!((message.source_address(), message.destination_address()), (REMOTE, LOCAL));
+        assert_eq!(message.data(), data);
+        assert_eq!(network_manager.next_subscribed_message(id), None);
+    }
+
+    #[test]
+    fn dm3_and_dm11_are_acknowledged() {
+        let (mut network_manager, driver, handle) = setup();
+        let acknowledgement = |messages: Vec<CanMessage>| {
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].pgn(), ParameterGroupNumber::Acknowledge);
+            assert_eq!(messages[0].get_u8_at(4), REMOTE.0);
+            (messages[0].get_u8_at(0), messages[0].get_pgn_at(5))
+        };
+        let diagnostic_protocol = network_manager.internal_control_function_mut(handle).unwrap().diagnostic_protocol_mut();
+        diagnostic_protocol.set_active(100, 3);
+        diagnostic_protocol.set_active(200, 4);
+        diagnostic_protocol.clear_active(200, 4);
+        diagnostic_protocol.set_clear_hook(|clear| clear != DiagnosticClear::AllActive);
+
+        receive_request(&driver, ParameterGroupNumber::DiagnosticMessage3, LOCAL);
+        network_manager.update();
+        assert_eq!(acknowledgement(sent(&driver)), (AcknowledgementType::Positive as u8, ParameterGroupNumber::DiagnosticMessage3));
+
+        receive_request(&driver, ParameterGroupNumber::DiagnosticMessage11, LOCAL);
+        network_manager.update();
+        assert_eq!(acknowledgement(sent(&driver)), (AcknowledgementType::AccessDenied as u8, ParameterGroupNumber::DiagnosticMessage11));
+
+        let diagnostic_protocol = network_manager.internal_control_function(handle).unwrap().diagnostic_protocol();
+        assert!(diagnostic_protocol.previously_active_dtcs().is_empty());
+        assert_eq!(diagnostic_protocol.active_dtcs().len(), 1);
+    }
+
+    #[test]
+    fn dm13_suspends_periodic_broadcasts() {
+        let (mut network_manager, driver, handle) = setup();
+        let pgn = ParameterGroupNumber::MaintainPower;
+        network_manager.internal_control_function_mut(handle).unwrap()
+            .add_periodic_broadcast(pgn, CanPriority::PriorityDefault6, Duration::ZERO, || Some(alloc::vec![0xFF; 8]));
+
+        network_manager.update();
+        assert_eq!(sent(&driver).len(), 1);
+
+        // Stop broadcast on the current data link, a hold keeps it suspended.
+        receive(&driver, ParameterGroupNumber::DiagnosticMessage13, REMOTE, Address::GLOBAL, &[0xFC, 0xFF, 0xFF, 0xF0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        network_manager.update();
+        receive(&driver, ParameterGroupNumber::DiagnosticMessage13, REMOTE, Address::GLOBAL, &[0xFF, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0xFF]);
+        network_manager.update();
+        assert!(driver.take_written_frames().is_empty());
+        assert!(network_manager.internal_control_function(handle).unwrap().diagnostic_protocol().is_broadcast_suspended());
+
+        // DM1 and the broadcasts resume on start broadcast.
+        receive(&driver, ParameterGroupNumber::DiagnosticMessage13, REMOTE, Address::GLOBAL, &[0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        network_manager.update();
+        let messages: Vec<ParameterGroupNumber> = driver.take_written_frames().into_iter()
+            .map(|frame| CanMessage::from(frame).pgn())
+            .collect();
+        assert_eq!(messages, [ParameterGroupNumber::DiagnosticMessage1, pgn]);
+    }
+}
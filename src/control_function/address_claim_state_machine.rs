@@ -1,16 +1,15 @@
-use core::{time::Duration, borrow::BorrowMut};
+use core::time::Duration;
 
 use crate::{
-    hardware_integration::{TimeDriver, TimeDriverTrait, CanDriverTrait},
+    hardware_integration::{TimeDriver, TimeDriverTrait},
     name::Name,
     Address, CanMessage, CanNetworkManager, ParameterGroupNumber,
 };
 
-use super::{ControlFunction, InternalControlFunction, ControlFunctionHandle, WeakControlFunctionHandle, handle};
-
 /// Defines the state machine states for address claiming
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
 enum State {
+    #[default]
     None,                           //< Address claiming is uninitialized
     WaitForClaim,                   //< State machine is waiting for the random delay time
     SendRequestForClaim,            //< State machine is sending the request for address claim
@@ -19,18 +18,13 @@ enum State {
     ContendForPreferredAddress,     //< State machine is contending the preferred address
     SendArbitraryAddressClaim,      //< State machine is claiming an address
     SendReclaimAddressOnRequest,    //< An ECU requested address claim, inform the bus of our current address
+    SendCannotClaimAddress,         //< State machine lost contention and will announce it after the random delay
     UnableToClaim,                  //< State machine could not claim an address
     AddressClaimingComplete,        //< Addres claiming is complete and we have an address
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 pub struct AddressClaimStateMachine {
-    handle: WeakControlFunctionHandle,  //< A handle to the Internal Control Function this state machine belongs to
+    name: Name,                         //< The NAME of the Internal Control Function this state machine belongs to
     current_state: State,               //< The address claim state machine state
     timestamp: Duration,                //< A timestamp used to find timeouts
     random_claim_delay: Duration,       //< The random delay as required by the ISO11783 standard
@@ -40,15 +34,14 @@ pub struct AddressClaimStateMachine {
 }
 
 impl AddressClaimStateMachine {
-    pub fn new(_handle: WeakControlFunctionHandle, preferred_address: Address) -> Self {
+    pub fn new(name: Name, preferred_address: Address) -> Self {
         let timestamp = TimeDriver::time_elapsed();
         let mut rng = fastrand::Rng::with_seed(timestamp.as_millis() as u64);
         let random_claim_delay = Duration::from_micros(rng.u64(..=255) * 600); // Defined by ISO11783-5
 
         Self {
-            handle,
+            name,
             current_state: State::default(),
-            // name: desired_name,
             timestamp,
             random_claim_delay,
             preferred_address,
@@ -63,9 +56,6 @@ impl AddressClaimStateMachine {
     pub fn disable(&mut self) {
         self.is_enabled = false;
     }
-    pub fn is_enabled(&self) -> bool {
-        self.is_enabled
-    }
 
     pub fn claimed_address(&self) -> Address {
         self.claimed_address
@@ -75,34 +65,63 @@ impl AddressClaimStateMachine {
         self.current_state = State::None;
     }
 
+    /// Re-send our address claim, used when another ECU is using our address.
+    pub fn reclaim_address(&mut self) {
+        if self.current_state == State::AddressClaimingComplete {
+            self.current_state = State::SendReclaimAddressOnRequest;
+        }
+    }
+
     /// Processes a CAN message
     pub fn process_can_message(&mut self, message: &CanMessage) -> bool {
         let mut handled = false;
         match message.pgn() {
-            ParameterGroupNumber::ParameterGroupNumberRequest => {
+            ParameterGroupNumber::ParameterGroupNumberRequest
                 if ParameterGroupNumber::AddressClaim == message.get_pgn_at(0)
-                    && State::AddressClaimingComplete == self.current_state
-                {
-                    self.current_state = State::SendReclaimAddressOnRequest;
-                    handled = true;
-                }
+                    && State::AddressClaimingComplete == self.current_state =>
+            {
+                self.current_state = State::SendReclaimAddressOnRequest;
+                handled = true;
             }
-            ParameterGroupNumber::AddressClaim => {
-                if message.is_address_specific(self.claimed_address()) {
-                    let name = message.get_name(0);
-
-                    // Check to see if another ECU is hijacking our address
-                    // This is not really a needed check, as we can be pretty sure that our address
-                    // has been stolen if we're running this logic. But, you never know, someone could be
-                    // spoofing us I guess, or we could be getting an echo? CAN Bridge from another channel?
-                    // Seemed safest to just confirm.
-                    if name != self.name().unwrap_or_default() {
+            ParameterGroupNumber::AddressClaim
+                if self.claimed_address < Address::NULL && message.source_address() == self.claimed_address =>
+            {
+                let name = message.get_name(0);
+                let our_name = self.name;
+
+                // Check to see if another ECU is hijacking our address
+                // This is not really a needed check, as we can be pretty sure that our address
+                // has been stolen if we're running this logic. But, you never know, someone could be
+                // spoofing us I guess, or we could be getting an echo? CAN Bridge from another channel?
+                // Seemed safest to just confirm.
+                if name != our_name {
+                    if name > our_name {
+                        // We have the higher priority NAME and keep the address, tell the other ECU.
+                        self.current_state = State::SendReclaimAddressOnRequest;
+                    } else if our_name.arbitrary_address_capable() {
                         // Wait for things to shake out a bit, then claim a new address.
+                        self.claimed_address = Address::NULL;
+                        self.timestamp = TimeDriver::time_elapsed();
                         self.current_state = State::WaitForRequestContentionPeriod;
-                        log::warn!("[AC]: Internal control function {} must re-arbitrate its address because it was stolen by another ECU with NAME {name}.", self.name().unwrap_or_default());
-                        handled = true;
+                        log::warn!("[AC]: Internal control function {our_name} must re-arbitrate its address because it was stolen by another ECU with NAME {name}.");
+                    } else {
+                        // We lost our only usable address.
+                        self.claimed_address = Address::NULL;
+                        self.timestamp = TimeDriver::time_elapsed();
+                        self.current_state = State::SendCannotClaimAddress;
+                        log::warn!("[AC]: Internal control function {our_name} lost contention for address {} to ECU with NAME {name}.", message.source_address());
                     }
+                    handled = true;
+                }
+            }
+            // The commanded address message holds the NAME of the commanded CF followed by the new address.
+            ParameterGroupNumber::CommandedAddress if message.len() >= 9 && message.get_name(0) == self.name => {
+                let address = Address(message.get_u8_at(8));
+                if address < Address::NULL {
+                    log::info!("[AC]: Internal control function {} was commanded to address {}", self.name, address);
+                    self.claim_address(address);
                 }
+                handled = true;
             }
             _ => {}
         }
@@ -111,13 +130,10 @@ impl AddressClaimStateMachine {
 
     /// Update based on the current state
     pub fn update(&mut self, network_manager: &mut CanNetworkManager) {
-        let handle = self.handle.upgrade();
-        if !self.is_enabled || handle.is_none() {
+        if !self.is_enabled {
             self.current_state = State::None;
             return;
         }
-        // Handle is always Some()
-        let control_function = network_manager.control_function(handle);
 
         match self.current_state {
             State::None => {
@@ -140,27 +156,25 @@ impl AddressClaimStateMachine {
                     >= Duration::from_millis(250) + self.random_claim_delay
                 {
                     // After the wait, check if our address has been claimed.
-                    let other: Option<ControlFunctionHandle> = network_manager.handle_by_address(self.preferred_address);
-
-                    match other {
-                        Some(handle) => {
-                            let other_name = handle.borrow().name();
+                    match network_manager.name_by_address(self.preferred_address) {
+                        Some(other_name) => {
 
                             // Check if we are arbitrary address capable.
-                            if control_function.name().arbitrary_address_capable() {
+                            if self.name.arbitrary_address_capable() {
                                 // We will move to another address if whoever is in our spot has a lower NAME.
-                                if other_name < control_function.name() {
+                                if other_name < self.name {
                                     self.current_state = State::SendArbitraryAddressClaim;
                                 } else {
                                     self.current_state = State::SendPreferredAddressClaim;
                                 }
                             } else {
-                                if other_name > control_function.name() {
+                                if other_name > self.name {
                                     // Our address is not free, we cannot be at an arbitrary address, and address is contendable.
                                     self.current_state = State::ContendForPreferredAddress;
                                 } else {
                                     // Can't claim because we cannot tolerate an arbitrary address, and the CF at that spot wins contention.
-                                    self.current_state = State::UnableToClaim;
+                                    self.timestamp = TimeDriver::time_elapsed();
+                                    self.current_state = State::SendCannotClaimAddress;
                                 }
                             }
                         }
@@ -172,11 +186,12 @@ impl AddressClaimStateMachine {
                 }
             }
             State::SendPreferredAddressClaim => {
-                self.send_address_claim(network_manager, control_function.handle(), self.preferred_address);
+                network_manager.send_address_claim(self.name, self.preferred_address);
+                self.claimed_address = self.preferred_address;
                 log::debug!(
                     "[AC]: Internal control function {} has claimed address {}",
-                    control_function.name(),
-                    control_function.claimed_address()
+                    self.name,
+                    self.preferred_address
                 );
                 self.current_state = State::AddressClaimingComplete;
             }
@@ -184,44 +199,152 @@ impl AddressClaimStateMachine {
                 // Request a free address from the network manager.
                 match network_manager.next_free_address(self.preferred_address) {
                     Some(address) => {
-                        self.send_address_claim(network_manager, control_function.name(), address);
-                        log::debug!("[AC]: Internal control function {} could not use the preferred address, but has claimed address {}", self.name(), address);
+                        network_manager.send_address_claim(self.name, address);
+                        self.claimed_address = address;
+                        log::debug!("[AC]: Internal control function {} could not use the preferred address, but has claimed address {}", self.name, address);
                         self.current_state = State::AddressClaimingComplete;
                     }
                     None => {
                         log::debug!(
                             "[AC]: Internal control function {} failed to claim an address",
-                            control_function.name()
+                            self.name
                         );
                         self.current_state = State::UnableToClaim;
                     }
                 }
             }
             State::SendReclaimAddressOnRequest => {
-                self.send_address_claim(network_manager, handle, self.preferred_address);
+                network_manager.send_address_claim(self.name, self.claimed_address);
                 self.current_state = State::AddressClaimingComplete;
             }
             State::ContendForPreferredAddress => {
-                // TODO: Non-arbitratable address contention (there is not a good reason to use this, but we should add support anyways)
+                // Our NAME has the higher priority, so we take the address from whoever is using it.
+                // Should an ECU with a higher priority NAME claim it later, we will lose the address again.
+                network_manager.send_address_claim(self.name, self.preferred_address);
+                self.claimed_address = self.preferred_address;
+                log::debug!(
+                    "[AC]: Internal control function {} has contended for address {}",
+                    self.name,
+                    self.preferred_address
+                );
+                self.current_state = State::AddressClaimingComplete;
+            }
+            State::SendCannotClaimAddress => {
+                if TimeDriver::time_elapsed() - self.timestamp >= self.random_claim_delay {
+                    network_manager.send_cannot_claim_address(self.name);
+                    log::warn!("[AC]: Internal control function {} cannot claim an address", self.name);
+                    self.current_state = State::UnableToClaim;
+                }
             }
             State::UnableToClaim => {}
             State::AddressClaimingComplete => {}
         }
     }
+}
+
+#[cfg(all(test, feature = "mock_can_driver"))]
+mod tests {
+    use super::*;
+    use crate::hardware_integration::CanDriver;
+    use crate::CanPriority;
+
+    const PREFERRED_ADDRESS: Address = Address(0x80);
+
+    fn name(arbitrary_address_capable: bool, identity_number: u32) -> Name {
+        Name::builder()
+            .arbitrary_address_capable(arbitrary_address_capable)
+            .identity_number(identity_number)
+            .build()
+    }
+
+    fn address_claim(name: Name, address: Address) -> CanMessage {
+        let data: [u8; 8] = name.into();
+        CanMessage::new(CanPriority::PriorityDefault6, ParameterGroupNumber::AddressClaim, address, Address::GLOBAL, &data)
+    }
+
+    fn setup(name: Name) -> (AddressClaimStateMachine, CanNetworkManager, CanDriver) {
+        let driver = CanDriver::new();
+        let mut state_machine = AddressClaimStateMachine::new(name, PREFERRED_ADDRESS);
+        state_machine.random_claim_delay = Duration::ZERO;
+        state_machine.enable();
+        (state_machine, CanNetworkManager::new(driver.clone()), driver)
+    }
+
+    /// Run the state machine through the request for address claim and the contention period.
+    fn claim(state_machine: &mut AddressClaimStateMachine, network_manager: &mut CanNetworkManager) {
+        for _ in 0..3 {
+            state_machine.update(network_manager);
+        }
+        assert_eq!(state_machine.current_state, State::WaitForRequestContentionPeriod);
+        std::thread::sleep(Duration::from_millis(260));
+        state_machine.update(network_manager);
+        state_machine.update(network_manager);
+    }
+
+    fn sent(driver: &CanDriver) -> Vec<CanMessage> {
+        driver.take_written_frames().into_iter().map(CanMessage::from).collect()
+    }
 
+    #[test]
+    fn lose_contention() {
+        let our_name = name(false, 2);
+        let (mut state_machine, mut network_manager, driver) = setup(our_name);
 
-    fn name(&self) -> Name {
-        self.handle.upgrade().map(|handle| {
-            handle.borrow().name()
-        }).unwrap_or_default()
+        claim(&mut state_machine, &mut network_manager);
+        assert_eq!(state_machine.current_state, State::AddressClaimingComplete);
+        assert_eq!(state_machine.claimed_address(), PREFERRED_ADDRESS);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].pgn(), ParameterGroupNumber::ParameterGroupNumberRequest);
+        assert_eq!(messages[1], address_claim(our_name, PREFERRED_ADDRESS));
+
+        // A lower NAME has the higher priority, we can not move to another address.
+        assert!(state_machine.process_can_message(&address_claim(name(false, 1), PREFERRED_ADDRESS)));
+        assert_eq!(state_machine.current_state, State::SendCannotClaimAddress);
+        assert_eq!(state_machine.claimed_address(), Address::NULL);
+
+        state_machine.update(&mut network_manager);
+        assert_eq!(state_machine.current_state, State::UnableToClaim);
+        assert_eq!(sent(&driver), [address_claim(our_name, Address::NULL)]);
     }
 
-    fn send_address_claim(
-        &mut self,
-        network_manager: &mut CanNetworkManager,
-        handle: ControlFunctionHandle,
-        address: Address,
-    ) {
-        network_manager.send_address_claim(handle, address);
+    #[test]
+    fn win_contention() {
+        let our_name = name(false, 2);
+        let (mut state_machine, mut network_manager, driver) = setup(our_name);
+        claim(&mut state_machine, &mut network_manager);
+        let _ = sent(&driver);
+
+        // We keep the address and tell the other ECU.
+        assert!(state_machine.process_can_message(&address_claim(name(false, 3), PREFERRED_ADDRESS)));
+        state_machine.update(&mut network_manager);
+        assert_eq!(state_machine.claimed_address(), PREFERRED_ADDRESS);
+        assert_eq!(sent(&driver), [address_claim(our_name, PREFERRED_ADDRESS)]);
+    }
+
+    #[test]
+    fn claim_arbitrary_address() {
+        let our_name = name(true, 2);
+        let (mut state_machine, mut network_manager, driver) = setup(our_name);
+
+        // A CF with a higher priority NAME is using our preferred address.
+        network_manager.process_can_message(address_claim(name(true, 1), PREFERRED_ADDRESS));
+
+        claim(&mut state_machine, &mut network_manager);
+        assert_eq!(state_machine.current_state, State::AddressClaimingComplete);
+        assert_eq!(state_machine.claimed_address(), Address(0x81));
+        assert_eq!(sent(&driver).last(), Some(&address_claim(our_name, Address(0x81))));
+
+        // A request for address claim is answered from the claimed address.
+        let request = CanMessage::new(
+            CanPriority::PriorityDefault6,
+            ParameterGroupNumber::ParameterGroupNumberRequest,
+            Address(0x26),
+            Address::GLOBAL,
+            &ParameterGroupNumber::AddressClaim.as_bytes(),
+        );
+        assert!(state_machine.process_can_message(&request));
+        state_machine.update(&mut network_manager);
+        assert_eq!(sent(&driver), [address_claim(our_name, Address(0x81))]);
     }
 }
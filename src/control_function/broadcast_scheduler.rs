@@ -0,0 +1,139 @@
+use core::time::Duration;
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+use crate::{Address, CanPriority, ParameterGroupNumber};
+
+const REPETITION_RATE_NOT_AVAILABLE: u16 = 0xFFFF;
+
+struct Broadcast {
+    priority: CanPriority,
+    default_interval: Duration,                         //< The interval used when nobody requested a repetition rate
+    requested_intervals: BTreeMap<Address, Duration>,   //< Repetition rates requested by other CFs, keyed by their address
+    last_sent: Option<Duration>,                        //< Timestamp of the last time this broadcast was sent
+    producer: Box<dyn FnMut() -> Option<Vec<u8>>>,      //< Provides the data to send, `None` skips this broadcast
+}
+
+impl Broadcast {
+    /// The fastest requested repetition rate wins, without requests we use the default.
+    fn interval(&self) -> Duration {
+        self.requested_intervals.values()
+            .min()
+            .copied()
+            .unwrap_or(self.default_interval)
+    }
+}
+
+/// Sends periodic PGNs at their default interval, or at the repetition rate other CFs requested (ISO11783-7).
+#[derive(Default)]
+pub struct BroadcastScheduler {
+    broadcasts: BTreeMap<ParameterGroupNumber, Broadcast>,
+}
+
+impl BroadcastScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(
+        &mut self,
+        pgn: ParameterGroupNumber,
+        priority: CanPriority,
+        default_interval: Duration,
+        producer: impl FnMut() -> Option<Vec<u8>> + 'static,
+    ) {
+        let _ = self.broadcasts.insert(pgn, Broadcast {
+            priority,
+            default_interval,
+            requested_intervals: BTreeMap::new(),
+            last_sent: None,
+            producer: Box::new(producer),
+        });
+    }
+
+    pub fn remove(&mut self, pgn: ParameterGroupNumber) {
+        let _ = self.broadcasts.remove(&pgn);
+    }
+
+    pub fn interval(&self, pgn: ParameterGroupNumber) -> Option<Duration> {
+        self.broadcasts.get(&pgn).map(|broadcast| broadcast.interval())
+    }
+
+    /// Process a Request for Repetition Rate, a rate of 0xFFFF releases the request.
+    ///
+    /// Returns `false` when we do not broadcast the PGN.
+    pub fn process_repetition_rate_request(&mut self, pgn: ParameterGroupNumber, requester: Address, repetition_rate: u16) -> bool {
+        let Some(broadcast) = self.broadcasts.get_mut(&pgn) else {
+            return false;
+        };
+
+        match repetition_rate {
+            0 => log::warn!("[BS]: Ignoring a repetition rate of 0 ms for PGN {:?} from {}", pgn, requester),
+            REPETITION_RATE_NOT_AVAILABLE => {
+                let _ = broadcast.requested_intervals.remove(&requester);
+            }
+            _ => {
+                let _ = broadcast.requested_intervals.insert(requester, Duration::from_millis(repetition_rate as u64));
+            }
+        }
+        true
+    }
+
+    /// Drop the repetition rates requested by CFs that left the bus.
+    pub fn release_requesters(&mut self, mut is_gone: impl FnMut(Address) -> bool) {
+        for broadcast in self.broadcasts.values_mut() {
+            broadcast.requested_intervals.retain(|&address, _| !is_gone(address));
+        }
+    }
+
+    /// Get the broadcasts that are due at `now`.
+    pub fn update(&mut self, now: Duration) -> Vec<(CanPriority, ParameterGroupNumber, Vec<u8>)> {
+        let mut due = Vec::new();
+        for (&pgn, broadcast) in self.broadcasts.iter_mut() {
+            if broadcast.last_sent.is_none_or(|last_sent| now.saturating_sub(last_sent) >= broadcast.interval()) {
+                broadcast.last_sent = Some(now);
+                if let Some(data) = (broadcast.producer)() {
+                    due.push((broadcast.priority, pgn, data));
+                }
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fastest_requested_rate_wins() {
+        let mut scheduler = BroadcastScheduler::new();
+        scheduler.add(ParameterGroupNumber::MaintainPower, CanPriority::PriorityDefault6, Duration::from_millis(1000), || Some(Vec::new()));
+
+        assert!(scheduler.process_repetition_rate_request(ParameterGroupNumber::MaintainPower, Address(0x80), 200));
+        assert!(scheduler.process_repetition_rate_request(ParameterGroupNumber::MaintainPower, Address(0x81), 100));
+        assert_eq!(scheduler.interval(ParameterGroupNumber::MaintainPower), Some(Duration::from_millis(100)));
+
+        scheduler.release_requesters(|address| address == Address(0x81));
+        assert_eq!(scheduler.interval(ParameterGroupNumber::MaintainPower), Some(Duration::from_millis(200)));
+
+        assert!(scheduler.process_repetition_rate_request(ParameterGroupNumber::MaintainPower, Address(0x80), 0xFFFF));
+        assert_eq!(scheduler.interval(ParameterGroupNumber::MaintainPower), Some(Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn broadcasts_when_due() {
+        let mut scheduler = BroadcastScheduler::new();
+        scheduler.add(ParameterGroupNumber::MaintainPower, CanPriority::PriorityDefault6, Duration::from_millis(100), || Some(Vec::new()));
+
+        assert_eq!(scheduler.update(Duration::from_millis(0)).len(), 1);
+        assert_eq!(scheduler.update(Duration::from_millis(50)).len(), 0);
+        assert_eq!(scheduler.update(Duration::from_millis(100)).len(), 1);
+    }
+
+    #[test]
+    fn unknown_pgn_is_not_accepted() {
+        let mut scheduler = BroadcastScheduler::new();
+        assert!(!scheduler.process_repetition_rate_request(ParameterGroupNumber::MaintainPower, Address(0x80), 100));
+    }
+}
@@ -1,13 +1,20 @@
-use crate::{Name, Address};
+use core::time::Duration;
+
+use crate::{
+    hardware_integration::{TimeDriver, TimeDriverTrait},
+    Name, Address,
+};
 
 /// Represents an External Control Function (ECF)
-/// 
+///
 /// The Name of a ECF is constant and can not change.
 /// The Address however can be updated using `.set_address()`.
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct ExternalControlFunction {
     name: Name,
     address: Address,
+    last_address_claim: Duration,   //< Timestamp of the last address claim we received from this ECF
+    is_timed_out: bool,             //< The ECF did not respond to a request for address claim
 }
 
 impl ExternalControlFunction {
@@ -15,6 +22,8 @@ impl ExternalControlFunction {
         Self {
             name,
             address,
+            last_address_claim: TimeDriver::time_elapsed(),
+            is_timed_out: false,
         }
     }
 
@@ -29,4 +38,25 @@ impl ExternalControlFunction {
     pub fn set_address(&mut self, address: Address) {
         self.address = address;
     }
+
+    pub fn last_address_claim(&self) -> Duration {
+        self.last_address_claim
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.is_timed_out
+    }
+
+    /// Update the ECF with an address claim it sent.
+    pub(crate) fn process_address_claim(&mut self, address: Address, timestamp: Duration) {
+        self.address = address;
+        self.last_address_claim = timestamp;
+        self.is_timed_out = false;
+    }
+
+    /// Mark the ECF as gone from the bus, it no longer holds an address.
+    pub(crate) fn time_out(&mut self) {
+        self.address = Address::NULL;
+        self.is_timed_out = true;
+    }
 }
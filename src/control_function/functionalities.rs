@@ -0,0 +1,177 @@
+use alloc::vec::Vec;
+
+use crate::{PayloadError, PayloadWriter};
+
+/// The ISOBUS functionalities a control function can implement, as defined by ISO11783-12.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Functionality {
+    MinimumControlFunction = 0,
+    UniversalTerminalServer = 1,
+    UniversalTerminalWorkingSet = 2,
+    AuxOInputs = 3,
+    AuxOFunctions = 4,
+    AuxNInputs = 5,
+    AuxNFunctions = 6,
+    TaskControllerBasicServer = 7,
+    TaskControllerBasicClient = 8,
+    TaskControllerGeoServer = 9,
+    TaskControllerGeoClient = 10,
+    TaskControllerSectionControlServer = 11,
+    TaskControllerSectionControlClient = 12,
+    BasicTractorEcuServer = 13,
+    BasicTractorEcuImplementClient = 14,
+    TractorImplementManagementServer = 15,
+    TractorImplementManagementClient = 16,
+    FileServer = 17,
+    FileServerClient = 18,
+}
+
+impl TryFrom<u8> for Functionality {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::MinimumControlFunction),
+            1 => Ok(Self::UniversalTerminalServer),
+            2 => Ok(Self::UniversalTerminalWorkingSet),
+            3 => Ok(Self::AuxOInputs),
+            4 => Ok(Self::AuxOFunctions),
+            5 => Ok(Self::AuxNInputs),
+            6 => Ok(Self::AuxNFunctions),
+            7 => Ok(Self::TaskControllerBasicServer),
+            8 => Ok(Self::TaskControllerBasicClient),
+            9 => Ok(Self::TaskControllerGeoServer),
+            10 => Ok(Self::TaskControllerGeoClient),
+            11 => Ok(Self::TaskControllerSectionControlServer),
+            12 => Ok(Self::TaskControllerSectionControlClient),
+            13 => Ok(Self::BasicTractorEcuServer),
+            14 => Ok(Self::BasicTractorEcuImplementClient),
+            15 => Ok(Self::TractorImplementManagementServer),
+            16 => Ok(Self::TractorImplementManagementClient),
+            17 => Ok(Self::FileServer),
+            18 => Ok(Self::FileServerClient),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A functionality with the generation and the option bytes of the functionality.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SupportedFunctionality {
+    pub functionality: Functionality,
+    pub generation: u8,
+    pub options: Vec<u8>,   //< Functionality specific option bits, see ISO11783-12 annex
+}
+
+/// The Control Function Functionalities message, declaring which functionalities a CF implements.
+///
+/// Every CF implements the minimum control function, so a new list holds it by default.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ControlFunctionFunctionalities {
+    functionalities: Vec<SupportedFunctionality>,
+}
+
+impl Default for ControlFunctionFunctionalities {
+    fn default() -> Self {
+        Self {
+            functionalities: alloc::vec![SupportedFunctionality {
+                functionality: Functionality::MinimumControlFunction,
+                generation: 1,
+                options: alloc::vec![0x00],
+            }],
+        }
+    }
+}
+
+impl ControlFunctionFunctionalities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn functionalities(&self) -> &[SupportedFunctionality] {
+        &self.functionalities
+    }
+    pub fn get(&self, functionality: Functionality) -> Option<&SupportedFunctionality> {
+        self.functionalities.iter().find(|supported| supported.functionality == functionality)
+    }
+    pub fn supports(&self, functionality: Functionality) -> bool {
+        self.get(functionality).is_some()
+    }
+
+    /// Add a functionality, or replace its generation and options when it is already in the list.
+    pub fn set(&mut self, functionality: Functionality, generation: u8, options: &[u8]) {
+        let supported = SupportedFunctionality { functionality, generation, options: options.to_vec() };
+        match self.functionalities.iter_mut().find(|supported| supported.functionality == functionality) {
+            Some(existing) => *existing = supported,
+            None => self.functionalities.push(supported),
+        }
+    }
+    pub fn remove(&mut self, functionality: Functionality) {
+        self.functionalities.retain(|supported| supported.functionality != functionality);
+    }
+
+    /// Byte 1 is reserved and always 0xFF, followed by the number of functionalities.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut writer = PayloadWriter::new();
+        writer.u8(0xFF).u8(self.functionalities.len() as u8);
+        for supported in &self.functionalities {
+            writer
+                .u8(supported.functionality as u8)
+                .u8(supported.generation)
+                .u8(supported.options.len() as u8)
+                .bytes(&supported.options);
+        }
+        writer.pad_to(8).build()
+    }
+}
+
+/// Parse the functionalities another CF reported, functionalities unknown to us are skipped.
+impl TryFrom<&[u8]> for ControlFunctionFunctionalities {
+    type Error = PayloadError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let count = *value.get(1).ok_or(PayloadError::OutOfRange)?;
+
+        let mut functionalities = Vec::new();
+        let mut index = 2;
+        for _ in 0..count {
+            let header = value.get(index..index + 3).ok_or(PayloadError::OutOfRange)?;
+            let options = value.get(index + 3..index + 3 + header[2] as usize).ok_or(PayloadError::OutOfRange)?;
+            index += 3 + options.len();
+
+            match Functionality::try_from(header[0]) {
+                Ok(functionality) => functionalities.push(SupportedFunctionality {
+                    functionality,
+                    generation: header[1],
+                    options: options.to_vec(),
+                }),
+                Err(()) => log::debug!("[CFF]: Skipping unknown functionality {}", header[0]),
+            }
+        }
+        Ok(Self { functionalities })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let mut functionalities = ControlFunctionFunctionalities::new();
+        functionalities.set(Functionality::UniversalTerminalWorkingSet, 4, &[]);
+
+        let data = functionalities.as_bytes();
+        assert_eq!(data, [0xFF, 0x02, 0x00, 0x01, 0x01, 0x00, 0x02, 0x04, 0x00]);
+        assert_eq!(ControlFunctionFunctionalities::try_from(data.as_slice()), Ok(functionalities));
+    }
+
+    #[test]
+    fn truncated_message_is_rejected() {
+        assert_eq!(
+            ControlFunctionFunctionalities::try_from([0xFF, 0x01, 0x00, 0x01, 0x02, 0x00].as_slice()),
+            Err(PayloadError::OutOfRange)
+        );
+    }
+}
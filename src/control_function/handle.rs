@@ -1,34 +1,11 @@
-use core::cell::RefCell;
-
-use alloc::rc::{Rc, Weak};
-
-use super::{ControlFunction, InternalControlFunction};
-
-
-#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
-pub struct ControlFunctionHandle(Rc<RefCell<ControlFunction>>);
+/// Identifies a control function in the table of the `CanNetworkManager`.
+///
+/// Handles are given out by the network manager and stay valid for as long as the control function is known.
+#[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Hash, Debug)]
+pub struct ControlFunctionHandle(usize);
 
 impl ControlFunctionHandle {
-    pub fn new(cf: ControlFunction) -> ControlFunctionHandle {
-        ControlFunctionHandle(Rc::new(RefCell::new(cf)))
+    pub(crate) fn new(id: usize) -> ControlFunctionHandle {
+        ControlFunctionHandle(id)
     }
 }
-
-#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
-pub struct WeakControlFunctionHandle(Weak<RefCell<ControlFunction>>);
-
-
-// pub type ControlFunctionHandle = Rc<RefCell<ControlFunction>>;
-// pub type WeakControlFunctionHandle = Weak<RefCell<ControlFunction>>;
-
-#[derive(Clone)]
-pub struct InternalControlFunctionHandle(Rc<RefCell<InternalControlFunction>>);
-
-// #[derive(Clone)]
-// pub struct ControlFunctionHandle(Rc<RefCell<ControlFunction>>);
-
-// impl From<Name> for ControlFunctionHandle {
-//     fn from(value: Name) -> Self {
-//         ControlFunctionHandle(value)
-//     }
-// }
\ No newline at end of file
@@ -1,39 +1,63 @@
 
-use core::cell::RefCell;
+use core::time::Duration;
 
-use alloc::rc::Rc;
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
 use heapless::HistoryBuffer;
 
 use crate::{
-    name::Name, Address, CanNetworkManager,
-    protocol_managers::{ExtendedTransportProtocolManager, TransportProtocolManager}, ParameterGroupNumber, CanMessage,
+    diagnostics::{DiagnosticProtocol, MAX_OCCURRENCE_COUNT},
+    name::Name, AcknowledgementType, Address, CanNetworkManager, CanMessage, CanPriority, ParameterGroupNumber,
 };
 
-use super::{AddressClaimStateMachine, ControlFunction, ControlFunctionHandle};
+use super::{AddressClaimStateMachine, BroadcastScheduler, ControlFunctionFunctionalities};
+
+/// Builds the response to a request, see [`InternalControlFunction::add_request_responder`].
+type RequestResponder = Box<dyn FnMut(&CanMessage) -> Option<Vec<u8>>>;
+
+/// Raised when another ECU sends from an address claimed by one of our internal control functions.
+///
+/// ISO11783-5 requires this to be reported as a DTC with SPN 2000 + address and FMI 7,
+/// the internal control function makes this DTC active in its diagnostic protocol.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AddressViolationEvent {
+    pub name: Name,             //< The NAME of the internal control function whose address was used
+    pub address: Address,       //< The violated address
+    pub occurrence_count: u8,   //< The number of violations seen by the internal control function
+}
+
+impl AddressViolationEvent {
+    pub fn suspect_parameter_number(&self) -> u32 {
+        2000 + self.address.0 as u32
+    }
+    pub fn failure_mode_indicator(&self) -> u8 {
+        7
+    }
+}
 
 pub struct InternalControlFunction {
     state_machine: AddressClaimStateMachine,
     name: Name,
-
-    tp_manager: TransportProtocolManager,           //< Instance of the transport protocol manager
-    etp_manager: ExtendedTransportProtocolManager,  //< Instance of the extended transport protocol manager
+    address_violation_count: u8,
+    request_responders: BTreeMap<ParameterGroupNumber, RequestResponder>,
+    broadcast_scheduler: BroadcastScheduler,
+    diagnostic_protocol: DiagnosticProtocol,
+    functionalities: ControlFunctionFunctionalities,
 
     pub received_can_message_queue: HistoryBuffer<CanMessage, 32>,
 }
 
 impl InternalControlFunction {
-    pub fn new(name: Name, address: Address) -> ControlFunctionHandle {
-        ControlFunctionHandle(
-            Rc::new_cyclic(|&handle| {
-                RefCell::new(ControlFunction::Internal(InternalControlFunction {
-                    state_machine: AddressClaimStateMachine::new(handle, address),
-                    name,
-                    tp_manager: TransportProtocolManager::new(),
-                    etp_manager: ExtendedTransportProtocolManager::new(),
-                    received_can_message_queue: HistoryBuffer::new(),
-                }))
-            })
-        )
+    pub fn new(name: Name, address: Address) -> InternalControlFunction {
+        InternalControlFunction {
+            state_machine: AddressClaimStateMachine::new(name, address),
+            name,
+            address_violation_count: 0,
+            request_responders: BTreeMap::new(),
+            broadcast_scheduler: BroadcastScheduler::new(),
+            diagnostic_protocol: DiagnosticProtocol::new(),
+            functionalities: ControlFunctionFunctionalities::new(),
+            received_can_message_queue: HistoryBuffer::new(),
+        }
     }
 
     pub fn name(&self) -> Name {
@@ -43,10 +67,120 @@ impl InternalControlFunction {
     pub fn address(&self) -> Address {
         self.state_machine.claimed_address()
     }
-    pub fn claim_address(&self, address: Address) {
+    pub fn claim_address(&mut self, address: Address) {
         self.state_machine.claim_address(address);
     }
 
+    pub fn address_violation_count(&self) -> u8 {
+        self.address_violation_count
+    }
+
+    /// Another ECU used our address, count the violation, raise the DTC and re-claim the address.
+    pub(crate) fn process_address_violation(&mut self) -> AddressViolationEvent {
+        self.address_violation_count = self.address_violation_count.saturating_add(1).min(MAX_OCCURRENCE_COUNT);
+
+        let event = AddressViolationEvent {
+            name: self.name,
+            address: self.address(),
+            occurrence_count: self.address_violation_count,
+        };
+
+        // Every violation is a new occurrence, so an active DTC is cleared first to increment its occurrence count.
+        let (spn, fmi) = (event.suspect_parameter_number(), event.failure_mode_indicator());
+        self.diagnostic_protocol.clear_active(spn, fmi);
+        self.diagnostic_protocol.set_active(spn, fmi);
+
+        self.state_machine.reclaim_address();
+        event
+    }
+
+    /// Answer requests for `pgn` with the data returned by the responder.
+    ///
+    /// The responder gets the request message and returns `None` when it can not respond.
+    pub fn add_request_responder(
+        &mut self,
+        pgn: ParameterGroupNumber,
+        responder: impl FnMut(&CanMessage) -> Option<Vec<u8>> + 'static,
+    ) {
+        let _ = self.request_responders.insert(pgn, Box::new(responder));
+    }
+    pub fn remove_request_responder(&mut self, pgn: ParameterGroupNumber) {
+        let _ = self.request_responders.remove(&pgn);
+    }
+
+    /// Get the response data for a request, or the acknowledgement to send instead.
+    pub(crate) fn respond_to_request(&mut self, pgn: ParameterGroupNumber, request: &CanMessage) -> Result<Vec<u8>, AcknowledgementType> {
+        if let Some(responder) = self.request_responders.get_mut(&pgn) {
+            return responder(request).ok_or(AcknowledgementType::CannotRespond);
+        }
+
+        // The messages the stack can answer by itself, unless the application added its own responder.
+        match pgn {
+            ParameterGroupNumber::DiagnosticMessage1 => Ok(self.diagnostic_protocol.dm1_payload()),
+            ParameterGroupNumber::DiagnosticMessage2 => Ok(self.diagnostic_protocol.dm2_payload()),
+            ParameterGroupNumber::SoftwareIdentification => self.diagnostic_protocol.software_identification()
+                .map(|identification| identification.as_bytes())
+                .ok_or(AcknowledgementType::Negative),
+            ParameterGroupNumber::ECUIdentificationInformation => self.diagnostic_protocol.ecu_identification()
+                .map(|identification| identification.as_bytes())
+                .ok_or(AcknowledgementType::Negative),
+            ParameterGroupNumber::ProductIdentification => self.diagnostic_protocol.product_identification()
+                .map(|identification| identification.as_bytes())
+                .ok_or(AcknowledgementType::Negative),
+            ParameterGroupNumber::DiagnosticProtocolIdentification => {
+                Ok(self.diagnostic_protocol.diagnostic_protocol_identification().as_bytes().to_vec())
+            }
+            ParameterGroupNumber::ControlFunctionFunctionalities => Ok(self.functionalities.as_bytes()),
+            ParameterGroupNumber::DiagnosticMessage3 => match self.diagnostic_protocol.process_dm3() {
+                true => Err(AcknowledgementType::Positive),
+                false => Err(AcknowledgementType::AccessDenied),
+            },
+            ParameterGroupNumber::DiagnosticMessage11 => match self.diagnostic_protocol.process_dm11() {
+                true => Err(AcknowledgementType::Positive),
+                false => Err(AcknowledgementType::AccessDenied),
+            },
+            _ => Err(AcknowledgementType::Negative),
+        }
+    }
+
+    /// Broadcast `pgn` every `default_interval` with the data returned by the producer.
+    ///
+    /// Other CFs can change the interval using a Request for Repetition Rate.
+    pub fn add_periodic_broadcast(
+        &mut self,
+        pgn: ParameterGroupNumber,
+        priority: CanPriority,
+        default_interval: Duration,
+        producer: impl FnMut() -> Option<Vec<u8>> + 'static,
+    ) {
+        self.broadcast_scheduler.add(pgn, priority, default_interval, producer);
+    }
+    pub fn remove_periodic_broadcast(&mut self, pgn: ParameterGroupNumber) {
+        self.broadcast_scheduler.remove(pgn);
+    }
+    pub fn periodic_broadcast_interval(&self, pgn: ParameterGroupNumber) -> Option<Duration> {
+        self.broadcast_scheduler.interval(pgn)
+    }
+
+    pub fn diagnostic_protocol(&self) -> &DiagnosticProtocol {
+        &self.diagnostic_protocol
+    }
+    pub fn diagnostic_protocol_mut(&mut self) -> &mut DiagnosticProtocol {
+        &mut self.diagnostic_protocol
+    }
+
+    /// The functionalities reported when another CF requests our Control Function Functionalities.
+    pub fn functionalities(&self) -> &ControlFunctionFunctionalities {
+        &self.functionalities
+    }
+    pub fn functionalities_mut(&mut self) -> &mut ControlFunctionFunctionalities {
+        &mut self.functionalities
+    }
+
+    pub(crate) fn broadcast_scheduler_mut(&mut self) -> &mut BroadcastScheduler {
+        &mut self.broadcast_scheduler
+    }
+
     pub fn initialize(&mut self) {
         self.state_machine.enable();
     }
@@ -60,7 +194,7 @@ impl InternalControlFunction {
         // network_manager.handle_message(|message| self.state_machine.process_can_message(message));
 
         // Do stuff based on the current internal state.
-        self.state_machine.update(self, network_manager);
+        self.state_machine.update(network_manager);
     }
 
     pub fn process_can_message(&mut self, message: CanMessage) {
@@ -68,21 +202,12 @@ impl InternalControlFunction {
         #[cfg(feature = "log_can_read")]
         log::debug!("Read <-: {}", message);
 
-        // Pass messages to the TP and ETP managers.
-        match message.pgn() {
-            // ParameterGroupNumber::TransportProtocolConnectionManagement |
-            // ParameterGroupNumber::TransportProtocolDataTransfer => {
-                // if let Some(message) = self.tp_manager.process_can_message(message) {
-                //     self.received_can_message_queue.write(message);
-                // }
-            // }
-            ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement |
-            ParameterGroupNumber::ExtendedTransportProtocolDataTransfer => {
-                if let Some(message) = self.etp_manager.process_can_message(&message) {
-                    self.received_can_message_queue.write(message);
-                }
-            }
-            _ => {}
+        // Address claim related messages are handled by the state machine.
+        if self.state_machine.process_can_message(&message) {
+            return;
         }
+
+        // TP and ETP messages are already reassembled by the network manager.
+        self.received_can_message_queue.write(message);
     }
 }
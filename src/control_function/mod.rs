@@ -1,28 +1,41 @@
 
-use core::cell::RefCell;
-
-use alloc::{vec::Vec, rc::Rc};
+use alloc::{boxed::Box, vec::Vec, collections::VecDeque};
 
 use crate::{
     name::{Name, NameFilter},
     Address,
-    CanNetworkManager,
 };
 
 mod address_claim_state_machine;
 use address_claim_state_machine::AddressClaimStateMachine;
 
+mod broadcast_scheduler;
+use broadcast_scheduler::BroadcastScheduler;
+
+mod functionalities;
+pub use functionalities::{ControlFunctionFunctionalities, Functionality, SupportedFunctionality};
+
 mod internal_control_function;
-pub use internal_control_function::InternalControlFunction;
+pub use internal_control_function::{InternalControlFunction, AddressViolationEvent};
 mod external_control_function;
 pub use external_control_function::ExternalControlFunction;
 
 mod handle;
-pub use handle::{ControlFunctionHandle, WeakControlFunctionHandle, InternalControlFunctionHandle};
+pub use handle::ControlFunctionHandle;
+
+const MAX_PARTNER_EVENT_QUEUE_SIZE: usize = 8;
+
+/// Events raised when a partnered control function finds or loses its partner on the bus.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum PartnerEvent {
+    Found(ExternalControlFunction), //< A matching ECF was found and is now our partner
+    Lost(ExternalControlFunction),  //< Our partner left the bus or no longer matches
+}
 
 pub struct PartneredControlFunction {
     external_control_function_cache: Option<ExternalControlFunction>,
     name_filters: Vec<NameFilter>,
+    event_queue: VecDeque<PartnerEvent>,
 }
 
 impl PartneredControlFunction {
@@ -30,6 +43,7 @@ impl PartneredControlFunction {
         PartneredControlFunction {
             external_control_function_cache: None,
             name_filters: filters.to_vec(),
+            event_queue: VecDeque::new(),
         }
     }
 
@@ -45,26 +59,60 @@ impl PartneredControlFunction {
         self.external_control_function_cache.is_some()
     }
 
-    pub fn update(&mut self, network_manager: &mut CanNetworkManager) {
-        // self.external_control_function_cache = network_manager.external_control_functions().into_iter()
-        //     .find(|&ecf|{
-        //         self.name_filters.iter()
-        //             .all(|filter| filter.check_name_matches_filter(ecf.name()))
-        //     })
+    pub fn next_event(&mut self) -> Option<PartnerEvent> {
+        self.event_queue.pop_front()
     }
-}
 
+    /// Bind to the best matching ECF in the table of external control functions on the network.
+    pub(crate) fn update(&mut self, external_control_functions: &[ExternalControlFunction]) {
+        let candidates = external_control_functions.iter()
+            .filter(|ecf| ecf.address() < Address::NULL)
+            .filter(|ecf| {
+                self.name_filters.iter()
+                    .all(|filter| filter.check_name_matches_filter(ecf.name()))
+            });
+
+        // Stay with the current partner while it still matches, otherwise pick the NAME with the highest priority.
+        let current_name = self.name();
+        let best = candidates.clone()
+            .find(|ecf| Some(ecf.name()) == current_name)
+            .or_else(|| candidates.min_by_key(|ecf| ecf.name()))
+            .copied();
+
+        match (self.external_control_function_cache, best) {
+            (Some(old), Some(new)) if old.name() == new.name() => {
+                if old.address() != new.address() {
+                    log::debug!("[PCF]: Partner {} moved from address {} to {}", new.name(), old.address(), new.address());
+                }
+            }
+            (old, new) => {
+                if let Some(old) = old {
+                    log::debug!("[PCF]: Lost partner {}", old.name());
+                    self.event_queue.push_back(PartnerEvent::Lost(old));
+                }
+                if let Some(new) = new {
+                    log::debug!("[PCF]: Found partner {} at address {}", new.name(), new.address());
+                    self.event_queue.push_back(PartnerEvent::Found(new));
+                }
+            }
+        }
+        self.external_control_function_cache = best;
+
+        while self.event_queue.len() > MAX_PARTNER_EVENT_QUEUE_SIZE {
+            self.event_queue.pop_front();
+        }
+    }
+}
 
-#[derive(PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub enum ControlFunction {
-    Internal(InternalControlFunction), //< The control function is part of our stack and can address claim.
+    Internal(Box<InternalControlFunction>), //< The control function is part of our stack and can address claim.
     External(ExternalControlFunction), //< The control function is some other device on the bus.
     Partnered(PartneredControlFunction), //< The control function is some other device on the bus.
 }
 
 impl ControlFunction {
     pub fn new_internal_control_function(name: Name, address: Address) -> ControlFunction {
-        ControlFunction::Internal(InternalControlFunction::new(name, address))
+        ControlFunction::Internal(Box::new(InternalControlFunction::new(name, address)))
     }
     pub fn new_external_control_function(name: Name, address: Address) -> ControlFunction {
         ControlFunction::External(ExternalControlFunction::new(name, address))
@@ -86,14 +134,6 @@ impl ControlFunction {
         }
     }
 
-    pub fn set_address(&self, address: Address) {
-        match self {
-            ControlFunction::Internal(cf) => cf.claim_address(address),
-            ControlFunction::External(cf) => {},
-            ControlFunction::Partnered(cf) => {},
-        }
-    }
-
     pub fn is_address_valid(&self) -> bool {
         self.address() < Address::NULL
     }
@@ -0,0 +1,502 @@
+use core::time::Duration;
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::PayloadWriter;
+
+use super::{
+    DiagnosticProtocolIdentification, DiagnosticTroubleCode, EcuIdentification, LampStatus, ProductIdentification,
+    SoftwareIdentification,
+};
+
+const DM1_INTERVAL: Duration = Duration::from_millis(1000);
+const DM13_TIMEOUT: Duration = Duration::from_millis(6000);   //< Broadcasts resume when no DM13 was received for this long
+
+const DM13_STOP_BROADCAST: u8 = 0b00;
+const DM13_START_BROADCAST: u8 = 0b01;
+const DM13_DONT_CARE: u8 = 0b11;
+
+/// The control byte of a DM22 message.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum Dm22Clear {
+    PreviouslyActive = 0x01,
+    PreviouslyActiveAck = 0x02,
+    PreviouslyActiveNack = 0x03,
+    Active = 0x11,
+    ActiveAck = 0x12,
+    ActiveNack = 0x13,
+}
+
+/// The reason send with a DM22 negative acknowledgement.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum Dm22NackReason {
+    AccessDenied = 0x01,
+    UnknownDtc = 0x02,
+    NoLongerPreviouslyActive = 0x03,
+    NoLongerActive = 0x04,
+}
+
+/// The suspend signal of a DM13 stop broadcast command.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SuspendSignal {
+    Indefinite,         //< Suspend all broadcasts until the start command
+    Partial,            //< Suspend some broadcasts until the start command
+    Temporary,          //< Suspend all broadcasts for the suspend duration
+    PartialTemporary,   //< Suspend some broadcasts for the suspend duration
+    NotAvailable,
+}
+
+impl From<u8> for SuspendSignal {
+    fn from(value: u8) -> Self {
+        match value {
+            0b0000 => SuspendSignal::Indefinite,
+            0b0001 => SuspendSignal::Partial,
+            0b0010 => SuspendSignal::Temporary,
+            0b0011 => SuspendSignal::PartialTemporary,
+            _ => SuspendSignal::NotAvailable,
+        }
+    }
+}
+
+/// A request to clear diagnostic information, passed to the clear hook so the application can veto it.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum DiagnosticClear {
+    AllPreviouslyActive,                        //< DM3, clear all previously active DTCs
+    AllActive,                                  //< DM11, clear all active DTCs
+    PreviouslyActive(DiagnosticTroubleCode),    //< DM22, clear a single previously active DTC
+    Active(DiagnosticTroubleCode),              //< DM22, clear a single active DTC
+}
+
+/// The diagnostic messages of an internal control function, as defined by ISO11783-12 and J1939-73.
+///
+/// Active DTCs are broadcast in DM1 once per second, changes are send right away but at most once per second (J1939-73).
+/// Cleared DTCs are moved to the previously active DTCs, which are reported in DM2 when requested.
+#[derive(Default)]
+pub struct DiagnosticProtocol {
+    active: Vec<DiagnosticTroubleCode>,
+    previously_active: Vec<DiagnosticTroubleCode>,
+    lamp_status: LampStatus,
+    is_dm1_changed: bool,                   //< Send DM1 right away, instead of waiting for the interval
+    last_dm1: Option<Duration>,             //< Timestamp of the last periodic DM1 broadcast
+    last_changed_dm1: Option<Duration>,     //< Timestamp of the last DM1 send because the DTCs changed
+    clear_hook: Option<Box<dyn FnMut(DiagnosticClear) -> bool>>,    //< Returns false to veto a clear
+    suspend_signal: Option<SuspendSignal>,  //< Set while a DM13 suspended our broadcasts
+    suspended_until: Duration,              //< Broadcasts resume at this timestamp, unless a hold extends it
+    software_identification: Option<SoftwareIdentification>,
+    ecu_identification: Option<EcuIdentification>,
+    product_identification: Option<ProductIdentification>,
+    diagnostic_protocol_identification: DiagnosticProtocolIdentification,
+}
+
+impl DiagnosticProtocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lamp_status(&self) -> LampStatus {
+        self.lamp_status
+    }
+    pub fn set_lamp_status(&mut self, lamp_status: LampStatus) {
+        if self.lamp_status != lamp_status {
+            self.lamp_status = lamp_status;
+            self.is_dm1_changed = true;
+        }
+    }
+
+    pub fn active_dtcs(&self) -> &[DiagnosticTroubleCode] {
+        &self.active
+    }
+    pub fn previously_active_dtcs(&self) -> &[DiagnosticTroubleCode] {
+        &self.previously_active
+    }
+
+    /// Requests for an identification that is not set are NACKed.
+    pub fn software_identification(&self) -> Option<&SoftwareIdentification> {
+        self.software_identification.as_ref()
+    }
+    pub fn set_software_identification(&mut self, software_identification: SoftwareIdentification) {
+        self.software_identification = Some(software_identification);
+    }
+    pub fn ecu_identification(&self) -> Option<&EcuIdentification> {
+        self.ecu_identification.as_ref()
+    }
+    pub fn set_ecu_identification(&mut self, ecu_identification: EcuIdentification) {
+        self.ecu_identification = Some(ecu_identification);
+    }
+    pub fn product_identification(&self) -> Option<&ProductIdentification> {
+        self.product_identification.as_ref()
+    }
+    pub fn set_product_identification(&mut self, product_identification: ProductIdentification) {
+        self.product_identification = Some(product_identification);
+    }
+    pub fn diagnostic_protocol_identification(&self) -> DiagnosticProtocolIdentification {
+        self.diagnostic_protocol_identification
+    }
+    pub fn set_diagnostic_protocol_identification(&mut self, identification: DiagnosticProtocolIdentification) {
+        self.diagnostic_protocol_identification = identification;
+    }
+
+    /// Make a DTC active, a previously active DTC returns with an incremented occurrence count.
+    pub fn set_active(&mut self, suspect_parameter_number: u32, failure_mode_indicator: u8) {
+        let dtc = DiagnosticTroubleCode::new(suspect_parameter_number, failure_mode_indicator);
+        if self.active.iter().any(|active| active.matches(dtc.suspect_parameter_number, dtc.failure_mode_indicator)) {
+            return;
+        }
+
+        let dtc = match self.previously_active.iter()
+            .position(|previous| previous.matches(dtc.suspect_parameter_number, dtc.failure_mode_indicator))
+        {
+            Some(index) => {
+                let mut previous = self.previously_active.remove(index);
+                previous.increment_occurrence_count();
+                previous
+            }
+            None => dtc,
+        };
+
+        self.active.push(dtc);
+        self.is_dm1_changed = true;
+    }
+
+    /// Move an active DTC to the previously active DTCs.
+    pub fn clear_active(&mut self, suspect_parameter_number: u32, failure_mode_indicator: u8) {
+        if let Some(index) = self.active.iter()
+            .position(|active| active.matches(suspect_parameter_number, failure_mode_indicator))
+        {
+            let dtc = self.active.remove(index);
+            self.previously_active.push(dtc);
+            self.is_dm1_changed = true;
+        }
+    }
+
+    /// Set the hook that is asked before any DM3, DM11 or DM22 clear, return `false` to veto the clear.
+    pub fn set_clear_hook(&mut self, hook: impl FnMut(DiagnosticClear) -> bool + 'static) {
+        self.clear_hook = Some(Box::new(hook));
+    }
+
+    fn is_clear_allowed(&mut self, clear: DiagnosticClear) -> bool {
+        let is_allowed = self.clear_hook.as_mut().is_none_or(|hook| hook(clear));
+        if !is_allowed {
+            log::info!("[DIAG]: The application vetoed {:?}", clear);
+        }
+        is_allowed
+    }
+
+    /// Process a DM3 request, returns false when the clear was vetoed.
+    pub(crate) fn process_dm3(&mut self) -> bool {
+        if !self.is_clear_allowed(DiagnosticClear::AllPreviouslyActive) {
+            return false;
+        }
+        self.previously_active.clear();
+        true
+    }
+
+    /// Process a DM11 request, returns false when the clear was vetoed.
+    ///
+    /// Faults that are still present should be set active again by the application.
+    pub(crate) fn process_dm11(&mut self) -> bool {
+        if !self.is_clear_allowed(DiagnosticClear::AllActive) {
+            return false;
+        }
+        if !self.active.is_empty() {
+            self.active.clear();
+            self.is_dm1_changed = true;
+        }
+        true
+    }
+
+    /// Process a DM22 individual clear request, returns the DM22 response.
+    pub(crate) fn process_dm22(&mut self, data: &[u8]) -> Option<[u8; 8]> {
+        if data.len() < 8 {
+            return None;
+        }
+        let dtc = DiagnosticTroubleCode::from([data[5], data[6], data[7], 0]);
+        let spn = dtc.suspect_parameter_number;
+        let fmi = dtc.failure_mode_indicator;
+
+        let (ack, nack, result) = match data[0] {
+            control if control == Dm22Clear::PreviouslyActive as u8 => {
+                let result = match self.previously_active.iter().position(|previous| previous.matches(spn, fmi)) {
+                    Some(index) => {
+                        if self.is_clear_allowed(DiagnosticClear::PreviouslyActive(self.previously_active[index])) {
+                            let _ = self.previously_active.remove(index);
+                            Ok(())
+                        } else {
+                            Err(Dm22NackReason::AccessDenied)
+                        }
+                    }
+                    None if self.active.iter().any(|active| active.matches(spn, fmi)) => Err(Dm22NackReason::NoLongerPreviouslyActive),
+                    None => Err(Dm22NackReason::UnknownDtc),
+                };
+                (Dm22Clear::PreviouslyActiveAck, Dm22Clear::PreviouslyActiveNack, result)
+            }
+            control if control == Dm22Clear::Active as u8 => {
+                let result = match self.active.iter().position(|active| active.matches(spn, fmi)) {
+                    Some(index) => {
+                        if self.is_clear_allowed(DiagnosticClear::Active(self.active[index])) {
+                            let _ = self.active.remove(index);
+                            self.is_dm1_changed = true;
+                            Ok(())
+                        } else {
+                            Err(Dm22NackReason::AccessDenied)
+                        }
+                    }
+                    None if self.previously_active.iter().any(|previous| previous.matches(spn, fmi)) => Err(Dm22NackReason::NoLongerActive),
+                    None => Err(Dm22NackReason::UnknownDtc),
+                };
+                (Dm22Clear::ActiveAck, Dm22Clear::ActiveNack, result)
+            }
+            control => {
+                log::debug!("[DIAG]: Ignoring DM22 with control byte 0x{:02X}", control);
+                return None;
+            }
+        };
+
+        let (control, reason) = match result {
+            Ok(()) => (ack as u8, 0xFF),
+            Err(reason) => (nack as u8, reason as u8),
+        };
+        Some([control, reason, 0xFF, 0xFF, 0xFF, data[5], data[6], data[7]])
+    }
+
+    /// The suspend signal of the DM13 that suspended our broadcasts, `None` while broadcasting.
+    pub fn suspend_signal(&self) -> Option<SuspendSignal> {
+        self.suspend_signal
+    }
+    pub fn is_broadcast_suspended(&self) -> bool {
+        self.suspend_signal.is_some()
+    }
+
+    /// Process a DM13 stop/start broadcast command received at `now`.
+    ///
+    /// Partial suspensions also suspend all our broadcasts, the stack does not know which are essential.
+    pub(crate) fn process_dm13(&mut self, data: &[u8], now: Duration) {
+        if data.len() < 8 {
+            return;
+        }
+
+        // The current data link field takes precedence over the J1939 network #1 field.
+        let command = match data[0] & 0b11 {
+            DM13_DONT_CARE => data[0] >> 6,
+            command => command,
+        };
+        // The hold signal is in the high nibble, the suspend signal in the low nibble.
+        let suspend_signal = SuspendSignal::from(data[3] & 0x0F);
+        let hold_signal = data[3] >> 4;
+        let suspend_duration = u16::from_le_bytes([data[4], data[5]]);
+
+        match command {
+            DM13_STOP_BROADCAST => {
+                let timeout = match (suspend_signal, suspend_duration) {
+                    (SuspendSignal::Temporary | SuspendSignal::PartialTemporary, 0..=0xFAFF) => {
+                        Duration::from_secs(suspend_duration as u64)
+                    }
+                    _ => DM13_TIMEOUT,
+                };
+                if self.suspend_signal.is_none() {
+                    log::info!("[DIAG]: Broadcasts suspended by DM13, {:?}", suspend_signal);
+                }
+                self.suspend_signal = Some(suspend_signal);
+                self.suspended_until = now + timeout;
+            }
+            DM13_START_BROADCAST => self.resume_broadcast(),
+            // A hold for all devices (0) or for the modified devices (1) keeps the suspension going.
+            _ if self.suspend_signal.is_some() && hold_signal <= 0b0001 => {
+                self.suspended_until = Duration::max(self.suspended_until, now + DM13_TIMEOUT);
+            }
+            _ => {}
+        }
+    }
+
+    fn resume_broadcast(&mut self) {
+        if self.suspend_signal.take().is_some() {
+            log::info!("[DIAG]: Broadcasts resumed");
+            self.is_dm1_changed = true;
+        }
+    }
+
+    pub fn dm1_payload(&self) -> Vec<u8> {
+        self.payload(&self.active)
+    }
+    pub fn dm2_payload(&self) -> Vec<u8> {
+        self.payload(&self.previously_active)
+    }
+
+    /// Returns the DM1 payload when it is time to broadcast it.
+    pub(crate) fn update(&mut self, now: Duration) -> Option<Vec<u8>> {
+        if self.suspend_signal.is_some() && now >= self.suspended_until {
+            self.resume_broadcast();
+        }
+        if self.suspend_signal.is_some() {
+            return None;
+        }
+
+        let is_due = self.last_dm1.is_none_or(|last_dm1| now.saturating_sub(last_dm1) >= DM1_INTERVAL);
+        if is_due {
+            self.last_dm1 = Some(now);
+        } else if self.is_dm1_changed
+            && self.last_changed_dm1.is_none_or(|last_changed_dm1| now.saturating_sub(last_changed_dm1) >= DM1_INTERVAL)
+        {
+            self.last_changed_dm1 = Some(now);
+        } else {
+            return None;
+        }
+
+        self.is_dm1_changed = false;
+        Some(self.dm1_payload())
+    }
+
+    /// The lamp status followed by the DTCs, more than one DTC does not fit a single frame and is send using TP.
+    fn payload(&self, dtcs: &[DiagnosticTroubleCode]) -> Vec<u8> {
+        let mut writer = PayloadWriter::new();
+        writer.bytes(&self.lamp_status.as_bytes());
+
+        // Without DTCs an empty DTC (all zeros) is send.
+        if dtcs.is_empty() {
+            writer.u32(0);
+        }
+        for dtc in dtcs {
+            writer.bytes(&dtc.as_bytes());
+        }
+
+        writer.pad_to(8).build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STOP_TEMPORARY: [u8; 8] = [0xFC, 0xFF, 0xFF, 0xF2, 0x0A, 0x00, 0xFF, 0xFF];   //< Stop for 10 s on the current data link
+    const STOP_INDEFINITE: [u8; 8] = [0x3F, 0xFF, 0xFF, 0xF0, 0xFF, 0xFF, 0xFF, 0xFF];  //< Stop on J1939 network #1
+    const HOLD: [u8; 8] = [0xFF, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0xFF];            //< Hold for all devices
+    const START: [u8; 8] = [0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+    fn seconds(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn dm1_on_change_at_most_once_per_second() {
+        let mut diagnostic_protocol = DiagnosticProtocol::new();
+        let millis = Duration::from_millis;
+        assert!(diagnostic_protocol.update(millis(0)).is_some());
+
+        diagnostic_protocol.set_active(100, 3);
+        assert!(diagnostic_protocol.update(millis(100)).is_some());
+
+        // The next change waits for a second after the last change.
+        diagnostic_protocol.set_active(101, 3);
+        assert_eq!(diagnostic_protocol.update(millis(200)), None);
+        assert_eq!(diagnostic_protocol.update(millis(900)), None);
+
+        // The periodic broadcast includes the change.
+        assert_eq!(diagnostic_protocol.update(millis(1000)).map(|data| data.len()), Some(10));
+        assert_eq!(diagnostic_protocol.update(millis(1050)), None);
+
+        diagnostic_protocol.clear_active(100, 3);
+        assert_eq!(diagnostic_protocol.update(millis(1050)), None);
+        assert!(diagnostic_protocol.update(millis(1100)).is_some());
+        assert_eq!(diagnostic_protocol.update(millis(1500)), None);
+        assert!(diagnostic_protocol.update(millis(2000)).is_some());
+    }
+
+    #[test]
+    fn dm13_suspend_and_hold() {
+        let mut diagnostic_protocol = DiagnosticProtocol::new();
+        assert!(diagnostic_protocol.update(seconds(0)).is_some());
+
+        diagnostic_protocol.process_dm13(&STOP_TEMPORARY, seconds(1));
+        assert_eq!(diagnostic_protocol.suspend_signal(), Some(SuspendSignal::Temporary));
+        assert_eq!(diagnostic_protocol.update(seconds(5)), None);
+
+        // A hold keeps the broadcasts suspended past the suspend duration.
+        diagnostic_protocol.process_dm13(&HOLD, seconds(9));
+        assert_eq!(diagnostic_protocol.update(seconds(12)), None);
+        assert!(diagnostic_protocol.is_broadcast_suspended());
+        assert!(diagnostic_protocol.update(seconds(15)).is_some());
+        assert!(!diagnostic_protocol.is_broadcast_suspended());
+    }
+
+    #[test]
+    fn dm13_start() {
+        let mut diagnostic_protocol = DiagnosticProtocol::new();
+
+        diagnostic_protocol.process_dm13(&STOP_INDEFINITE, seconds(1));
+        assert_eq!(diagnostic_protocol.suspend_signal(), Some(SuspendSignal::Indefinite));
+        assert_eq!(diagnostic_protocol.update(seconds(2)), None);
+
+        diagnostic_protocol.process_dm13(&START, seconds(3));
+        assert_eq!(diagnostic_protocol.suspend_signal(), None);
+        assert!(diagnostic_protocol.update(seconds(3)).is_some());
+
+        // Without a hold the suspension ends after the DM13 timeout.
+        diagnostic_protocol.process_dm13(&STOP_INDEFINITE, seconds(4));
+        assert_eq!(diagnostic_protocol.update(seconds(9)), None);
+        assert!(diagnostic_protocol.update(seconds(10)).is_some());
+    }
+
+    /// A DM22 request with `control` for the DTC with `spn` and `fmi`.
+    fn dm22(control: Dm22Clear, spn: u32, fmi: u8) -> [u8; 8] {
+        let dtc = DiagnosticTroubleCode::new(spn, fmi).as_bytes();
+        [control as u8, 0xFF, 0xFF, 0xFF, 0xFF, dtc[0], dtc[1], dtc[2]]
+    }
+
+    #[test]
+    fn dm22_clear() {
+        let mut diagnostic_protocol = DiagnosticProtocol::new();
+        diagnostic_protocol.set_active(100, 3);
+        diagnostic_protocol.set_active(200, 4);
+        diagnostic_protocol.clear_active(200, 4);
+
+        let response = diagnostic_protocol.process_dm22(&dm22(Dm22Clear::Active, 100, 3)).unwrap();
+        assert_eq!(response[..2], [Dm22Clear::ActiveAck as u8, 0xFF]);
+        assert_eq!(response[5..], dm22(Dm22Clear::Active, 100, 3)[5..]);
+        assert!(diagnostic_protocol.active_dtcs().is_empty());
+
+        let response = diagnostic_protocol.process_dm22(&dm22(Dm22Clear::PreviouslyActive, 200, 4)).unwrap();
+        assert_eq!(response[..2], [Dm22Clear::PreviouslyActiveAck as u8, 0xFF]);
+        assert!(diagnostic_protocol.previously_active_dtcs().is_empty());
+
+        // Other control bytes, like our own acknowledgements, are ignored.
+        assert_eq!(diagnostic_protocol.process_dm22(&dm22(Dm22Clear::ActiveAck, 100, 3)), None);
+    }
+
+    #[test]
+    fn dm22_nack_reasons() {
+        let mut diagnostic_protocol = DiagnosticProtocol::new();
+        diagnostic_protocol.set_active(100, 3);
+        diagnostic_protocol.set_active(200, 4);
+        diagnostic_protocol.clear_active(200, 4);
+
+        let nack = |diagnostic_protocol: &mut DiagnosticProtocol, control, spn, fmi| {
+            diagnostic_protocol.process_dm22(&dm22(control, spn, fmi)).map(|response| [response[0], response[1]])
+        };
+        assert_eq!(nack(&mut diagnostic_protocol, Dm22Clear::Active, 300, 1), Some([Dm22Clear::ActiveNack as u8, Dm22NackReason::UnknownDtc as u8]));
+        assert_eq!(nack(&mut diagnostic_protocol, Dm22Clear::PreviouslyActive, 300, 1), Some([Dm22Clear::PreviouslyActiveNack as u8, Dm22NackReason::UnknownDtc as u8]));
+        assert_eq!(nack(&mut diagnostic_protocol, Dm22Clear::Active, 200, 4), Some([Dm22Clear::ActiveNack as u8, Dm22NackReason::NoLongerActive as u8]));
+        assert_eq!(nack(&mut diagnostic_protocol, Dm22Clear::PreviouslyActive, 100, 3), Some([Dm22Clear::PreviouslyActiveNack as u8, Dm22NackReason::NoLongerPreviouslyActive as u8]));
+        assert_eq!(diagnostic_protocol.active_dtcs().len(), 1);
+        assert_eq!(diagnostic_protocol.previously_active_dtcs().len(), 1);
+    }
+
+    #[test]
+    fn clear_hook_vetoes() {
+        let mut diagnostic_protocol = DiagnosticProtocol::new();
+        diagnostic_protocol.set_active(100, 3);
+        diagnostic_protocol.set_active(200, 4);
+        diagnostic_protocol.clear_active(200, 4);
+        diagnostic_protocol.set_clear_hook(|clear| !matches!(clear, DiagnosticClear::Active(_) | DiagnosticClear::AllActive));
+
+        let response = diagnostic_protocol.process_dm22(&dm22(Dm22Clear::Active, 100, 3)).unwrap();
+        assert_eq!(response[..2], [Dm22Clear::ActiveNack as u8, Dm22NackReason::AccessDenied as u8]);
+        assert!(!diagnostic_protocol.process_dm11());
+        assert_eq!(diagnostic_protocol.active_dtcs().len(), 1);
+
+        // The hook only vetoes the active DTCs.
+        assert!(diagnostic_protocol.process_dm3());
+        assert!(diagnostic_protocol.previously_active_dtcs().is_empty());
+    }
+}
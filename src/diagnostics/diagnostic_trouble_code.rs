@@ -0,0 +1,146 @@
+pub(crate) const MAX_OCCURRENCE_COUNT: u8 = 126;
+
+/// A Diagnostic Trouble Code (DTC) as defined by J1939-73 and ISO11783-12.
+///
+/// A DTC is identified by its SPN and FMI, the occurrence count tracks how often it became active.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct DiagnosticTroubleCode {
+    pub suspect_parameter_number: u32,  //< The 19 bit SPN of the parameter that has the fault
+    pub failure_mode_indicator: u8,     //< The 5 bit FMI describing the kind of fault
+    pub occurrence_count: u8,           //< The 7 bit number of times the fault became active
+}
+
+impl DiagnosticTroubleCode {
+    pub fn new(suspect_parameter_number: u32, failure_mode_indicator: u8) -> Self {
+        Self {
+            suspect_parameter_number: suspect_parameter_number & 0x7FFFF,
+            failure_mode_indicator: failure_mode_indicator & 0x1F,
+            occurrence_count: 1,
+        }
+    }
+
+    /// Returns true if the DTC has the same SPN and FMI, the occurrence count is ignored.
+    pub fn matches(&self, suspect_parameter_number: u32, failure_mode_indicator: u8) -> bool {
+        self.suspect_parameter_number == suspect_parameter_number
+            && self.failure_mode_indicator == failure_mode_indicator
+    }
+
+    pub(crate) fn increment_occurrence_count(&mut self) {
+        self.occurrence_count = u8::min(self.occurrence_count.saturating_add(1), MAX_OCCURRENCE_COUNT);
+    }
+
+    /// Encode the DTC using SPN conversion method 0.
+    pub fn as_bytes(&self) -> [u8; 4] {
+        [
+            self.suspect_parameter_number as u8,
+            (self.suspect_parameter_number >> 8) as u8,
+            ((self.suspect_parameter_number >> 11) & 0xE0) as u8 | self.failure_mode_indicator,
+            self.occurrence_count & 0x7F,
+        ]
+    }
+}
+
+impl From<[u8; 4]> for DiagnosticTroubleCode {
+    fn from(value: [u8; 4]) -> Self {
+        Self {
+            suspect_parameter_number: value[0] as u32 | (value[1] as u32) << 8 | ((value[2] & 0xE0) as u32) << 11,
+            failure_mode_indicator: value[2] & 0x1F,
+            occurrence_count: value[3] & 0x7F,
+        }
+    }
+}
+
+/// The state of a diagnostic lamp, including its flash mode.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub enum LampState {
+    #[default]
+    Off,
+    On,
+    SlowFlash,  //< Flashing at 1 Hz
+    FastFlash,  //< Flashing at 2 Hz
+}
+
+impl LampState {
+    /// The 2 bit lamp status and flash bits.
+    fn as_bits(&self) -> (u8, u8) {
+        match self {
+            LampState::Off => (0b00, 0b11),
+            LampState::On => (0b01, 0b11),
+            LampState::SlowFlash => (0b01, 0b00),
+            LampState::FastFlash => (0b01, 0b01),
+        }
+    }
+
+    fn from_bits(status: u8, flash: u8) -> Self {
+        match (status, flash) {
+            (0b01, 0b00) => LampState::SlowFlash,
+            (0b01, 0b01) => LampState::FastFlash,
+            (0b01, _) => LampState::On,
+            _ => LampState::Off,
+        }
+    }
+}
+
+/// The lamps reported in DM1 and DM2.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct LampStatus {
+    pub malfunction_indicator: LampState,
+    pub red_stop: LampState,
+    pub amber_warning: LampState,
+    pub protect: LampState,
+}
+
+impl LampStatus {
+    pub fn as_bytes(&self) -> [u8; 2] {
+        let lamps = [self.malfunction_indicator, self.red_stop, self.amber_warning, self.protect];
+
+        let mut bytes = [0u8; 2];
+        for (index, lamp) in lamps.iter().enumerate() {
+            let (status, flash) = lamp.as_bits();
+            let shift = 6 - 2 * index;
+            bytes[0] |= status << shift;
+            bytes[1] |= flash << shift;
+        }
+        bytes
+    }
+}
+
+impl From<[u8; 2]> for LampStatus {
+    fn from(value: [u8; 2]) -> Self {
+        let lamp = |shift: usize| LampState::from_bits((value[0] >> shift) & 0b11, (value[1] >> shift) & 0b11);
+        Self {
+            malfunction_indicator: lamp(6),
+            red_stop: lamp(4),
+            amber_warning: lamp(2),
+            protect: lamp(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dtc_encoding() {
+        let dtc = DiagnosticTroubleCode {
+            suspect_parameter_number: 0x7FFFF,
+            failure_mode_indicator: 7,
+            occurrence_count: 3,
+        };
+        assert_eq!(dtc.as_bytes(), [0xFF, 0xFF, 0xE7, 0x03]);
+        assert_eq!(DiagnosticTroubleCode::from(dtc.as_bytes()), dtc);
+    }
+
+    #[test]
+    fn lamp_encoding() {
+        let lamps = LampStatus {
+            malfunction_indicator: LampState::On,
+            red_stop: LampState::Off,
+            amber_warning: LampState::FastFlash,
+            protect: LampState::SlowFlash,
+        };
+        assert_eq!(lamps.as_bytes(), [0b01000101, 0b11110100]);
+        assert_eq!(LampStatus::from(lamps.as_bytes()), lamps);
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::PayloadWriter;
+
+const FIELD_DELIMITER: u8 = b'*';
+
+/// Encode the fields each followed by the delimiter, padded to a full frame.
+fn delimited(mut writer: PayloadWriter, fields: &[&str]) -> Vec<u8> {
+    for field in fields {
+        writer.bytes(field.as_bytes()).u8(FIELD_DELIMITER);
+    }
+    writer.pad_to(8).build()
+}
+
+/// The Software Identification, one field per software component.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub struct SoftwareIdentification {
+    pub fields: Vec<String>,
+}
+
+impl SoftwareIdentification {
+    /// The number of fields, followed by the `*` delimited fields.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let fields: Vec<&str> = self.fields.iter().map(String::as_str).collect();
+        let mut writer = PayloadWriter::new();
+        writer.u8(fields.len() as u8);
+        delimited(writer, &fields)
+    }
+}
+
+/// The ECU Identification Information as defined by ISO11783-12.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub struct EcuIdentification {
+    pub part_number: String,
+    pub serial_number: String,
+    pub location: String,           //< Where the ECU is mounted, e.g. "Cab" or "Boom"
+    pub ecu_type: String,
+    pub manufacturer_name: String,
+    pub hardware_id: String,
+}
+
+impl EcuIdentification {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        delimited(PayloadWriter::new(), &[
+            &self.part_number,
+            &self.serial_number,
+            &self.location,
+            &self.ecu_type,
+            &self.manufacturer_name,
+            &self.hardware_id,
+        ])
+    }
+}
+
+/// The Product Identification of the machine the ECU is part of.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub struct ProductIdentification {
+    pub code: String,
+    pub brand: String,
+    pub model: String,
+}
+
+impl ProductIdentification {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        delimited(PayloadWriter::new(), &[&self.code, &self.brand, &self.model])
+    }
+}
+
+/// The diagnostic protocols supported by the ECU, besides the ISO11783-12 diagnostics.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DiagnosticProtocolIdentification {
+    pub j1939_73: bool,
+    pub iso_14230: bool,    //< KWP2000
+    pub iso_15765_3: bool,  //< UDS on CAN
+    pub iso_13400: bool,    //< DoIP
+}
+
+impl Default for DiagnosticProtocolIdentification {
+    fn default() -> Self {
+        Self {
+            j1939_73: true,
+            iso_14230: false,
+            iso_15765_3: false,
+            iso_13400: false,
+        }
+    }
+}
+
+impl DiagnosticProtocolIdentification {
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0] = self.j1939_73 as u8
+            | (self.iso_14230 as u8) << 1
+            | (self.iso_15765_3 as u8) << 2
+            | (self.iso_13400 as u8) << 3;
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn software_identification_encoding() {
+        let software = SoftwareIdentification {
+            fields: alloc::vec![String::from("1.0"), String::from("2.1")],
+        };
+        assert_eq!(software.as_bytes(), b"\x021.0*2.1*");
+    }
+
+    #[test]
+    fn short_identification_is_padded() {
+        let product = ProductIdentification {
+            code: String::from("A"),
+            brand: String::new(),
+            model: String::from("B"),
+        };
+        assert_eq!(product.as_bytes(), [b'A', b'*', b'*', b'B', b'*', 0xFF, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,9 @@
+mod diagnostic_trouble_code;
+pub use diagnostic_trouble_code::{DiagnosticTroubleCode, LampState, LampStatus};
+pub(crate) use diagnostic_trouble_code::MAX_OCCURRENCE_COUNT;
+
+mod identification;
+pub use identification::{DiagnosticProtocolIdentification, EcuIdentification, ProductIdentification, SoftwareIdentification};
+
+mod diagnostic_protocol;
+pub use diagnostic_protocol::{DiagnosticClear, DiagnosticProtocol, SuspendSignal};
@@ -1,9 +1,16 @@
 use crate::CanFrame;
 
+/// The reason a CAN driver could not write a frame.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CanWriteError {
+    NotOpen,    //< The driver is not opened
+    BusError,   //< The hardware could not send the frame
+}
+
 pub trait CanDriverTrait {
     fn is_valid(&mut self) -> bool;
     fn open(&mut self);
     fn close(&mut self);
     fn read(&mut self) -> Option<CanFrame>;
-    fn write(&mut self, frame: &CanFrame) -> Result<(), ()>;
+    fn write(&mut self, frame: &CanFrame) -> Result<(), CanWriteError>;
 }
@@ -1,11 +1,33 @@
-use super::CanDriverTrait;
+use core::cell::RefCell;
+
+use alloc::{collections::VecDeque, rc::Rc, vec::Vec};
+
+use super::{CanDriverTrait, CanWriteError};
 use crate::CanFrame;
 
-pub struct MockCanDriver {}
+/// A CAN driver without hardware, for testing.
+///
+/// Clones share their frame buffers, so a clone can be kept to feed and inspect the driver
+/// after it is moved into the network manager.
+#[derive(Clone, Default)]
+pub struct MockCanDriver {
+    received_frames: Rc<RefCell<VecDeque<CanFrame>>>,  //< The frames returned by `read`
+    written_frames: Rc<RefCell<Vec<CanFrame>>>,         //< The frames passed to `write`
+}
 
 impl MockCanDriver {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Queue a frame to be read, as if it was received from the bus.
+    pub fn receive(&self, frame: CanFrame) {
+        self.received_frames.borrow_mut().push_back(frame);
+    }
+
+    /// Take all the frames written since the last call.
+    pub fn take_written_frames(&self) -> Vec<CanFrame> {
+        core::mem::take(&mut *self.written_frames.borrow_mut())
     }
 }
 
@@ -19,10 +41,11 @@ impl CanDriverTrait for MockCanDriver {
     fn close(&mut self) {}
 
     fn read(&mut self) -> Option<CanFrame> {
-        None
+        self.received_frames.borrow_mut().pop_front()
     }
 
-    fn write(&mut self, _frame: &CanFrame) -> Result<(), ()> {
+    fn write(&mut self, frame: &CanFrame) -> Result<(), CanWriteError> {
+        self.written_frames.borrow_mut().push(*frame);
         Ok(())
     }
 }
@@ -1,5 +1,5 @@
 mod can_driver_trait;
-pub use can_driver_trait::CanDriverTrait;
+pub use can_driver_trait::{CanDriverTrait, CanWriteError};
 mod time_driver_trait;
 pub use time_driver_trait::TimeDriverTrait;
 
@@ -1,5 +1,5 @@
 
-use super::CanDriverTrait;
+use super::{CanDriverTrait, CanWriteError};
 use crate::{CanFrame, ExtendedId, Id, StandardId};
 
 use pcan_basic::{
@@ -90,16 +90,16 @@ impl CanDriverTrait for PeakCanDriver {
         }
     }
 
-    fn write(&mut self, frame: &CanFrame) -> Result<(), ()> {
+    fn write(&mut self, frame: &CanFrame) -> Result<(), CanWriteError> {
         let socket = match self.socket() {
             Some(socket) => socket,
-            None => return Err(()),
+            None => return Err(CanWriteError::NotOpen),
         };
 
         if let Err(e) = socket.send(frame.into()) {
             self.log_can_error("Unable to write CAN frame", Some(e));
             // self.close();
-            Err(())
+            Err(CanWriteError::BusError)
         } else {
             Ok(())
         }
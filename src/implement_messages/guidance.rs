@@ -0,0 +1,336 @@
+use core::time::Duration;
+
+use crate::payload::check_j1939_u16;
+use crate::{
+    control_function::ControlFunctionHandle, Address, CanMessage, CanNetworkManager, CanPriority, ParameterGroupNumber,
+    PayloadError,
+};
+
+use super::{state_at, state_bits, LimitStatus};
+
+const GUIDANCE_INTERVAL: Duration = Duration::from_millis(100); //< The interval of both guidance messages
+const GUIDANCE_TIMEOUT: Duration = Duration::from_millis(300);  //< A guidance message older than this is considered lost
+
+const CURVATURE_RESOLUTION: f32 = 0.25;     //< km^-1 per bit
+const CURVATURE_OFFSET: f32 = -8032.0;      //< km^-1
+
+/// Convert a curvature in km^-1 to its raw value, positive curvature steers to the right.
+pub fn curvature_to_raw(curvature: f32) -> u16 {
+    let raw = (curvature - CURVATURE_OFFSET) / CURVATURE_RESOLUTION + 0.5;
+    (raw as i32).clamp(0, 0xFAFF) as u16
+}
+
+/// Convert a raw curvature to km^-1.
+pub fn curvature_from_raw(raw: u16) -> f32 {
+    raw as f32 * CURVATURE_RESOLUTION + CURVATURE_OFFSET
+}
+
+/// The Agricultural Guidance Machine Info, send by the steering controller of the machine.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct GuidanceMachineInfo {
+    pub estimated_curvature: Option<u16>,           //< Raw, see `curvature_from_raw`
+    pub mechanical_system_lockout: Option<bool>,    //< The operator locked out the steering mechanically
+    pub steering_system_ready: Option<bool>,
+    pub steering_input_position_ok: Option<bool>,   //< The steering input is in the position required to steer
+    pub request_reset_command: Option<bool>,        //< The guidance system must send "not intended to steer" before steering again
+    pub limit_status: Option<LimitStatus>,
+    pub exit_reason_code: Option<u8>,
+    pub remote_engage_switch: Option<bool>,
+}
+
+impl GuidanceMachineInfo {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::AgriculturalGuidanceMachineInfo
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0..2].copy_from_slice(&self.estimated_curvature.unwrap_or(0xFFFF).to_le_bytes());
+        data[2] = state_bits(self.request_reset_command) << 6
+            | state_bits(self.steering_input_position_ok) << 4
+            | state_bits(self.steering_system_ready) << 2
+            | state_bits(self.mechanical_system_lockout);
+        data[3] = self.limit_status.map_or(0b111, |status| status as u8) << 5 | 0x1F;
+        data[4] = state_bits(self.remote_engage_switch) << 6 | self.exit_reason_code.unwrap_or(0x3F);
+        data
+    }
+}
+
+impl TryFrom<&CanMessage> for GuidanceMachineInfo {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::AgriculturalGuidanceMachineInfo {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        if value.len() < 8 {
+            return Err(PayloadError::OutOfRange);
+        }
+        Ok(Self {
+            estimated_curvature: check_j1939_u16(u16::from_le_bytes([value[0], value[1]])).ok(),
+            mechanical_system_lockout: state_at(value[2], 0),
+            steering_system_ready: state_at(value[2], 2),
+            steering_input_position_ok: state_at(value[2], 4),
+            request_reset_command: state_at(value[2], 6),
+            limit_status: LimitStatus::try_from(value[3] >> 5).ok(),
+            exit_reason_code: Some(value[4] & 0x3F).filter(|&code| code != 0x3F),
+            remote_engage_switch: state_at(value[4], 6),
+        })
+    }
+}
+
+/// The Agricultural Guidance System Command, send by the guidance system to steer the machine.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct GuidanceSystemCommand {
+    pub curvature: Option<u16>,         //< Raw, see `curvature_to_raw`
+    pub intended_to_steer: Option<bool>,
+}
+
+impl GuidanceSystemCommand {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::AgriculturalGuidanceSystemCommand
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0..2].copy_from_slice(&self.curvature.unwrap_or(0xFFFF).to_le_bytes());
+        data[2] = 0xFC | state_bits(self.intended_to_steer);
+        data
+    }
+}
+
+impl TryFrom<&CanMessage> for GuidanceSystemCommand {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::AgriculturalGuidanceSystemCommand {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        if value.len() < 8 {
+            return Err(PayloadError::OutOfRange);
+        }
+        Ok(Self {
+            curvature: check_j1939_u16(u16::from_le_bytes([value[0], value[1]])).ok(),
+            intended_to_steer: state_at(value[2], 0),
+        })
+    }
+}
+
+/// The guidance system side, commands the curvature every 100 ms and keeps the latest machine info.
+pub struct GuidanceSystemInterface {
+    internal_control_function: ControlFunctionHandle,   //< The handle to the internal control function the commands are send from
+    destination: Address,                               //< The steering controller, or global
+    command: Option<GuidanceSystemCommand>,
+    last_sent: Option<Duration>,
+    machine_info: Option<(GuidanceMachineInfo, Duration)>,
+}
+
+impl GuidanceSystemInterface {
+    pub fn new(internal_control_function: ControlFunctionHandle, destination: Address) -> Self {
+        Self {
+            internal_control_function,
+            destination,
+            command: None,
+            last_sent: None,
+            machine_info: None,
+        }
+    }
+
+    /// Set the command send on every update, `None` stops sending commands.
+    pub fn set_command(&mut self, command: Option<GuidanceSystemCommand>) {
+        self.command = command;
+    }
+    pub fn command(&self) -> Option<GuidanceSystemCommand> {
+        self.command
+    }
+
+    /// The latest machine info, `None` when it was not received for 300 ms.
+    pub fn machine_info(&self, now: Duration) -> Option<GuidanceMachineInfo> {
+        self.machine_info
+            .filter(|(_, timestamp)| now.saturating_sub(*timestamp) < GUIDANCE_TIMEOUT)
+            .map(|(machine_info, _)| machine_info)
+    }
+
+    pub fn update(&mut self, network_manager: &mut CanNetworkManager, now: Duration) {
+        let Some(command) = self.command else {
+            return;
+        };
+
+        if self.last_sent.is_some_and(|last_sent| now.saturating_sub(last_sent) < GUIDANCE_INTERVAL) {
+            return;
+        }
+        let result = network_manager.send_from(
+            self.internal_control_function,
+            CanPriority::Priority3,
+            ParameterGroupNumber::AgriculturalGuidanceSystemCommand,
+            self.destination,
+            &command.as_bytes(),
+        );
+        if result.is_ok() {
+            self.last_sent = Some(now);
+        }
+    }
+
+    /// Process a received message, returns true if it was the machine info.
+    pub fn process_can_message(&mut self, message: &CanMessage, now: Duration) -> bool {
+        if message.pgn() != ParameterGroupNumber::AgriculturalGuidanceMachineInfo {
+            return false;
+        }
+        match GuidanceMachineInfo::try_from(message) {
+            Ok(machine_info) => self.machine_info = Some((machine_info, now)),
+            Err(error) => log::warn!("[GD]: Invalid machine info from {}: {:?}", message.source_address(), error),
+        }
+        true
+    }
+}
+
+/// The machine side, reports the machine info every 100 ms and keeps the latest guidance command.
+pub struct GuidanceMachineInterface {
+    internal_control_function: ControlFunctionHandle,   //< The handle to the internal control function the machine info is send from
+    machine_info: GuidanceMachineInfo,
+    last_sent: Option<Duration>,
+    command: Option<(GuidanceSystemCommand, Duration)>,
+}
+
+impl GuidanceMachineInterface {
+    pub fn new(internal_control_function: ControlFunctionHandle) -> Self {
+        Self {
+            internal_control_function,
+            machine_info: GuidanceMachineInfo::default(),
+            last_sent: None,
+            command: None,
+        }
+    }
+
+    pub fn machine_info(&self) -> GuidanceMachineInfo {
+        self.machine_info
+    }
+    pub fn set_machine_info(&mut self, machine_info: GuidanceMachineInfo) {
+        self.machine_info = machine_info;
+    }
+
+    /// The latest command, `None` when it was not received for 300 ms and the machine should stop steering.
+    pub fn command(&self, now: Duration) -> Option<GuidanceSystemCommand> {
+        self.command
+            .filter(|(_, timestamp)| now.saturating_sub(*timestamp) < GUIDANCE_TIMEOUT)
+            .map(|(command, _)| command)
+    }
+
+    pub fn update(&mut self, network_manager: &mut CanNetworkManager, now: Duration) {
+        if self.last_sent.is_some_and(|last_sent| now.saturating_sub(last_sent) < GUIDANCE_INTERVAL) {
+            return;
+        }
+        let result = network_manager.send_from(
+            self.internal_control_function,
+            CanPriority::Priority3,
+            ParameterGroupNumber::AgriculturalGuidanceMachineInfo,
+            Address::GLOBAL,
+            &self.machine_info.as_bytes(),
+        );
+        if result.is_ok() {
+            self.last_sent = Some(now);
+        }
+    }
+
+    /// Process a received message, returns true if it was a guidance command.
+    pub fn process_can_message(&mut self, message: &CanMessage, now: Duration) -> bool {
+        if message.pgn() != ParameterGroupNumber::AgriculturalGuidanceSystemCommand {
+            return false;
+        }
+        match GuidanceSystemCommand::try_from(message) {
+            Ok(command) => self.command = Some((command, now)),
+            Err(error) => log::warn!("[GD]: Invalid guidance command from {}: {:?}", message.source_address(), error),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implement_messages::test_helpers::{message, sent, setup};
+
+    #[test]
+    fn curvature_scaling() {
+        assert_eq!(curvature_to_raw(0.0), 32128);
+        assert_eq!(curvature_from_raw(32128), 0.0);
+        assert_eq!(curvature_to_raw(-10.25), 32087);
+        assert_eq!(curvature_to_raw(10000.0), 0xFAFF);
+    }
+
+    #[test]
+    fn encoding() {
+        let machine_info = GuidanceMachineInfo {
+            estimated_curvature: Some(curvature_to_raw(2.5)),
+            mechanical_system_lockout: Some(true),
+            steering_system_ready: Some(true),
+            steering_input_position_ok: Some(false),
+            request_reset_command: None,
+            limit_status: Some(LimitStatus::NotLimited),
+            exit_reason_code: Some(5),
+            remote_engage_switch: Some(true),
+        };
+        let data = machine_info.as_bytes();
+        // From bit 1 up: lockout, readiness, steering input position and request reset, 2 bits each.
+        assert_eq!(data[2], 0b11_00_01_01);
+        // The exit reason code in bits 1 to 6 and the remote engage switch in bits 7 and 8 of byte 5.
+        assert_eq!(data[4], 0b01_000101);
+        assert_eq!(data[5], 0xFF);
+        assert_eq!(GuidanceMachineInfo::try_from(&message(machine_info.pgn(), &data)), Ok(machine_info));
+
+        let command = GuidanceSystemCommand {
+            curvature: Some(curvature_to_raw(-2.5)),
+            intended_to_steer: Some(true),
+        };
+        assert_eq!(GuidanceSystemCommand::try_from(&message(command.pgn(), &command.as_bytes())), Ok(command));
+
+        // The PGN is checked, so the machine info can not be mistaken for a command.
+        assert_eq!(GuidanceSystemCommand::try_from(&message(machine_info.pgn(), &data)), Err(PayloadError::OutOfRange));
+    }
+
+    #[test]
+    fn system_interface() {
+        let (mut network_manager, driver, handle) = setup();
+        let mut interface = GuidanceSystemInterface::new(handle, Address(0x1C));
+
+        // Nothing is send without a command.
+        interface.update(&mut network_manager, Duration::from_millis(0));
+        assert!(sent(&driver).is_empty());
+
+        // The command is send every 100 ms.
+        interface.set_command(Some(GuidanceSystemCommand { curvature: Some(curvature_to_raw(0.0)), intended_to_steer: Some(true) }));
+        interface.update(&mut network_manager, Duration::from_millis(1000));
+        interface.update(&mut network_manager, Duration::from_millis(1099));
+        assert_eq!(sent(&driver), [ParameterGroupNumber::AgriculturalGuidanceSystemCommand]);
+        interface.update(&mut network_manager, Duration::from_millis(1100));
+        assert_eq!(sent(&driver), [ParameterGroupNumber::AgriculturalGuidanceSystemCommand]);
+
+        // The machine info is lost after 300 ms.
+        let machine_info = GuidanceMachineInfo { steering_system_ready: Some(true), ..Default::default() };
+        assert!(interface.process_can_message(&message(machine_info.pgn(), &machine_info.as_bytes()), Duration::from_millis(1000)));
+        assert_eq!(interface.machine_info(Duration::from_millis(1299)), Some(machine_info));
+        assert_eq!(interface.machine_info(Duration::from_millis(1300)), None);
+    }
+
+    #[test]
+    fn machine_interface() {
+        let (mut network_manager, driver, handle) = setup();
+        let mut interface = GuidanceMachineInterface::new(handle);
+
+        // The machine info is send every 100 ms.
+        interface.update(&mut network_manager, Duration::from_millis(1000));
+        interface.update(&mut network_manager, Duration::from_millis(1099));
+        assert_eq!(sent(&driver), [ParameterGroupNumber::AgriculturalGuidanceMachineInfo]);
+        interface.update(&mut network_manager, Duration::from_millis(1100));
+        assert_eq!(sent(&driver), [ParameterGroupNumber::AgriculturalGuidanceMachineInfo]);
+
+        // The machine stops steering when no command was received for 300 ms.
+        let command = GuidanceSystemCommand { curvature: Some(curvature_to_raw(1.0)), intended_to_steer: Some(true) };
+        assert!(interface.process_can_message(&message(command.pgn(), &command.as_bytes()), Duration::from_millis(1000)));
+        assert!(!interface.process_can_message(&message(ParameterGroupNumber::AgriculturalGuidanceMachineInfo, &[0xFF; 8]), Duration::from_millis(1000)));
+        assert_eq!(interface.command(Duration::from_millis(1299)), Some(command));
+        assert_eq!(interface.command(Duration::from_millis(1300)), None);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{CanMessage, ParameterGroupNumber, PayloadError};
+
+/// The symbol used to separate the integer from the fractional part of a number.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum DecimalSymbol {
+    Comma = 0,
+    Point = 1,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum TimeFormat {
+    TwentyFourHour = 0,
+    TwelveHour = 1, //< With am/pm
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum DateFormat {
+    DayMonthYear = 0,
+    DayYearMonth = 1,
+    MonthYearDay = 2,
+    MonthDayYear = 3,
+    YearMonthDay = 4,
+    YearDayMonth = 5,
+}
+
+impl TryFrom<u8> for DateFormat {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::DayMonthYear),
+            1 => Ok(Self::DayYearMonth),
+            2 => Ok(Self::MonthYearDay),
+            3 => Ok(Self::MonthDayYear),
+            4 => Ok(Self::YearMonthDay),
+            5 => Ok(Self::YearDayMonth),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The unit system of a kind of unit.
+///
+/// Distance, area, temperature, pressure and force do not tell imperial and US apart, they use `Imperial` for both
+/// and 0b10 is reserved.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum UnitSystem {
+    Metric = 0,
+    Imperial = 1,
+    Us = 2,
+}
+
+impl UnitSystem {
+    /// Decode the 2 bits of a kind of unit, `has_us` tells if it has a separate US value in 0b10.
+    fn from_bits(value: u8, has_us: bool) -> Option<Self> {
+        match value {
+            0 => Some(Self::Metric),
+            1 => Some(Self::Imperial),
+            2 if has_us => Some(Self::Us),
+            _ => None,
+        }
+    }
+
+    /// Imperial and US share a value for the kinds of unit without a separate US value.
+    fn without_us(value: Option<Self>) -> Option<Self> {
+        value.map(|value| if value == Self::Us { Self::Imperial } else { value })
+    }
+}
+
+fn bits_of<T: Copy + Into<u8>>(value: Option<T>) -> u8 {
+    value.map_or(0b11, Into::into)
+}
+
+impl From<DecimalSymbol> for u8 {
+    fn from(value: DecimalSymbol) -> Self {
+        value as u8
+    }
+}
+impl From<TimeFormat> for u8 {
+    fn from(value: TimeFormat) -> Self {
+        value as u8
+    }
+}
+impl From<UnitSystem> for u8 {
+    fn from(value: UnitSystem) -> Self {
+        value as u8
+    }
+}
+
+/// The Language Command, the language, formats and units the operator wants to see.
+///
+/// Settings that are not available are `None`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct LanguageCommand {
+    pub language_code: [u8; 2],     //< The two letter ISO 639 language code, e.g. `*b"en"`
+    pub decimal_symbol: Option<DecimalSymbol>,
+    pub time_format: Option<TimeFormat>,
+    pub date_format: Option<DateFormat>,
+    pub distance_units: Option<UnitSystem>,
+    pub area_units: Option<UnitSystem>,
+    pub volume_units: Option<UnitSystem>,
+    pub mass_units: Option<UnitSystem>,
+    pub temperature_units: Option<UnitSystem>,
+    pub pressure_units: Option<UnitSystem>,
+    pub force_units: Option<UnitSystem>,
+    pub generic_units: Option<UnitSystem>,
+}
+
+impl Default for LanguageCommand {
+    fn default() -> Self {
+        Self {
+            language_code: *b"en",
+            decimal_symbol: Some(DecimalSymbol::Point),
+            time_format: Some(TimeFormat::TwentyFourHour),
+            date_format: Some(DateFormat::YearMonthDay),
+            distance_units: Some(UnitSystem::Metric),
+            area_units: Some(UnitSystem::Metric),
+            volume_units: Some(UnitSystem::Metric),
+            mass_units: Some(UnitSystem::Metric),
+            temperature_units: Some(UnitSystem::Metric),
+            pressure_units: Some(UnitSystem::Metric),
+            force_units: Some(UnitSystem::Metric),
+            generic_units: Some(UnitSystem::Metric),
+        }
+    }
+}
+
+impl LanguageCommand {
+    /// The language code as a string, `None` if it is not ASCII.
+    pub fn language(&self) -> Option<&str> {
+        core::str::from_utf8(&self.language_code).ok().filter(|code| code.is_ascii())
+    }
+
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::LanguageCommand
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        [
+            self.language_code[0],
+            self.language_code[1],
+            bits_of(self.decimal_symbol) << 6 | bits_of(self.time_format) << 4 | 0x0F,
+            self.date_format.map_or(0xFF, |format| format as u8),
+            bits_of(UnitSystem::without_us(self.distance_units)) << 6
+                | bits_of(UnitSystem::without_us(self.area_units)) << 4
+                | bits_of(self.volume_units) << 2
+                | bits_of(self.mass_units),
+            bits_of(UnitSystem::without_us(self.temperature_units)) << 6
+                | bits_of(UnitSystem::without_us(self.pressure_units)) << 4
+                | bits_of(UnitSystem::without_us(self.force_units)) << 2
+                | bits_of(self.generic_units),
+            0xFF,
+            0xFF,
+        ]
+    }
+}
+
+impl TryFrom<&CanMessage> for LanguageCommand {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::LanguageCommand {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        if value.len() < 6 {
+            return Err(PayloadError::OutOfRange);
+        }
+        let units = |byte: u8, shift: u8, has_us: bool| UnitSystem::from_bits((byte >> shift) & 0b11, has_us);
+
+        Ok(Self {
+            language_code: [value[0], value[1]],
+            decimal_symbol: match (value[2] >> 6) & 0b11 {
+                0 => Some(DecimalSymbol::Comma),
+                1 => Some(DecimalSymbol::Point),
+                _ => None,
+            },
+            time_format: match (value[2] >> 4) & 0b11 {
+                0 => Some(TimeFormat::TwentyFourHour),
+                1 => Some(TimeFormat::TwelveHour),
+                _ => None,
+            },
+            date_format: DateFormat::try_from(value[3]).ok(),
+            distance_units: units(value[4], 6, false),
+            area_units: units(value[4], 4, false),
+            volume_units: units(value[4], 2, true),
+            mass_units: units(value[4], 0, true),
+            temperature_units: units(value[5], 6, false),
+            pressure_units: units(value[5], 4, false),
+            force_units: units(value[5], 2, false),
+            generic_units: units(value[5], 0, true),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implement_messages::test_helpers::message;
+
+    #[test]
+    fn encoding() {
+        let language_command = LanguageCommand {
+            language_code: *b"de",
+            decimal_symbol: Some(DecimalSymbol::Comma),
+            time_format: None,
+            date_format: Some(DateFormat::DayMonthYear),
+            volume_units: Some(UnitSystem::Us),
+            ..Default::default()
+        };
+        let data = language_command.as_bytes();
+        assert_eq!(data, [b'd', b'e', 0b00111111, 0x00, 0b00001000, 0x00, 0xFF, 0xFF]);
+        assert_eq!(LanguageCommand::try_from(&message(language_command.pgn(), &data)), Ok(language_command));
+        assert_eq!(language_command.language(), Some("de"));
+    }
+
+    #[test]
+    fn us_units() {
+        // 0b10 is US for volume, mass and generic units, but reserved for the others.
+        let data = [b'e', b'n', 0xFF, 0xFF, 0b10101010, 0b10101010, 0xFF, 0xFF];
+        let language_command = LanguageCommand::try_from(&message(ParameterGroupNumber::LanguageCommand, &data)).unwrap();
+        assert_eq!(language_command.distance_units, None);
+        assert_eq!(language_command.area_units, None);
+        assert_eq!(language_command.volume_units, Some(UnitSystem::Us));
+        assert_eq!(language_command.mass_units, Some(UnitSystem::Us));
+        assert_eq!(language_command.temperature_units, None);
+        assert_eq!(language_command.pressure_units, None);
+        assert_eq!(language_command.force_units, None);
+        assert_eq!(language_command.generic_units, Some(UnitSystem::Us));
+
+        // US distance units are send as imperial.
+        let language_command = LanguageCommand {
+            distance_units: Some(UnitSystem::Us),
+            force_units: Some(UnitSystem::Us),
+            ..Default::default()
+        };
+        assert_eq!(language_command.as_bytes()[4..6], [0b01000000, 0b00000100]);
+    }
+}
@@ -0,0 +1,250 @@
+use core::time::Duration;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::{
+    control_function::ControlFunctionHandle, Address, CanMessage, CanNetworkManager, CanPriority, ParameterGroupNumber,
+    PayloadError,
+};
+
+use super::{state_at, state_bits};
+
+const MAINTAIN_POWER_INTERVAL: Duration = Duration::from_millis(1000);  //< The interval of maintain power requests while power is needed
+const POWER_DOWN_DELAY: Duration = Duration::from_millis(2000);         //< Power is kept this long after the key switch or the last request
+
+/// The Maintain Power message, send by an implement that needs power after the key switch was turned off.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct MaintainPower {
+    pub maintain_ecu_power: bool,       //< Keep ECU_PWR on for another 2 seconds
+    pub maintain_actuator_power: bool,  //< Keep PWR on for another 2 seconds
+    pub in_transport: Option<bool>,
+    pub ready_to_park: Option<bool>,    //< The implement may be disconnected
+    pub ready_to_work: Option<bool>,
+    pub in_work: Option<bool>,
+}
+
+impl MaintainPower {
+    pub fn is_power_required(&self) -> bool {
+        self.maintain_ecu_power || self.maintain_actuator_power
+    }
+
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::MaintainPower
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0] = (self.maintain_ecu_power as u8) << 6 | (self.maintain_actuator_power as u8) << 4 | 0x0F;
+        data[1] = state_bits(self.in_transport) << 6
+            | state_bits(self.ready_to_park) << 4
+            | state_bits(self.ready_to_work) << 2
+            | state_bits(self.in_work);
+        data
+    }
+}
+
+impl TryFrom<&CanMessage> for MaintainPower {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::MaintainPower {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        if value.len() < 2 {
+            return Err(PayloadError::OutOfRange);
+        }
+        Ok(Self {
+            maintain_ecu_power: state_at(value[0], 6) == Some(true),
+            maintain_actuator_power: state_at(value[0], 4) == Some(true),
+            in_transport: state_at(value[1], 6),
+            ready_to_park: state_at(value[1], 4),
+            ready_to_work: state_at(value[1], 2),
+            in_work: state_at(value[1], 0),
+        })
+    }
+}
+
+/// Sends Maintain Power requests on behalf of an implement.
+///
+/// The request is send every second while ECU or actuator power is required,
+/// clear both once the sections are closed and the data is stored to let the tractor power down.
+pub struct MaintainPowerClient {
+    internal_control_function: ControlFunctionHandle,   //< The handle to the internal control function the requests are send from
+    maintain_power: MaintainPower,
+    last_sent: Option<Duration>,
+}
+
+impl MaintainPowerClient {
+    pub fn new(internal_control_function: ControlFunctionHandle) -> Self {
+        Self {
+            internal_control_function,
+            maintain_power: MaintainPower::default(),
+            last_sent: None,
+        }
+    }
+
+    pub fn maintain_power(&self) -> MaintainPower {
+        self.maintain_power
+    }
+    pub fn set_maintain_power(&mut self, maintain_power: MaintainPower) {
+        self.maintain_power = maintain_power;
+    }
+
+    pub fn update(&mut self, network_manager: &mut CanNetworkManager, now: Duration) {
+        if !self.maintain_power.is_power_required() {
+            self.last_sent = None;
+            return;
+        }
+
+        if self.last_sent.is_some_and(|last_sent| now.saturating_sub(last_sent) < MAINTAIN_POWER_INTERVAL) {
+            return;
+        }
+
+        let result = network_manager.send_from(
+            self.internal_control_function,
+            CanPriority::PriorityDefault6,
+            ParameterGroupNumber::MaintainPower,
+            Address::GLOBAL,
+            &self.maintain_power.as_bytes(),
+        );
+        if result.is_ok() {
+            self.last_sent = Some(now);
+        }
+    }
+}
+
+/// Tracks the Maintain Power requests on the tractor side (ISO11783-7 and ISO11783-9).
+///
+/// After the key switch is turned off ECU_PWR and PWR stay on for at least 2 seconds,
+/// and for 2 seconds after the last request that asked to maintain them.
+#[derive(Default)]
+pub struct MaintainPowerMonitor {
+    key_switch_off_since: Option<Duration>,
+    requests: BTreeMap<Address, (MaintainPower, Duration)>,   //< The last request of every implement, with its timestamp
+}
+
+impl MaintainPowerMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_key_switch_state(&mut self, is_on: bool, now: Duration) {
+        match (is_on, self.key_switch_off_since) {
+            (true, _) => self.key_switch_off_since = None,
+            (false, None) => self.key_switch_off_since = Some(now),
+            (false, Some(_)) => {}
+        }
+    }
+    pub fn is_key_switch_on(&self) -> bool {
+        self.key_switch_off_since.is_none()
+    }
+
+    /// Process a received message, returns true if it was a Maintain Power request.
+    pub fn process_can_message(&mut self, message: &CanMessage, now: Duration) -> bool {
+        if message.pgn() != ParameterGroupNumber::MaintainPower {
+            return false;
+        }
+        match MaintainPower::try_from(message) {
+            Ok(maintain_power) => {
+                let _ = self.requests.insert(message.source_address(), (maintain_power, now));
+            }
+            Err(error) => log::warn!("[MP]: Invalid maintain power from {}: {:?}", message.source_address(), error),
+        }
+        true
+    }
+
+    /// The requests received in the last 2 seconds.
+    pub fn requests(&self, now: Duration) -> Vec<(Address, MaintainPower)> {
+        self.requests.iter()
+            .filter(|(_, (_, timestamp))| now.saturating_sub(*timestamp) < POWER_DOWN_DELAY)
+            .map(|(&address, &(maintain_power, _))| (address, maintain_power))
+            .collect()
+    }
+
+    pub fn is_ecu_power_required(&self, now: Duration) -> bool {
+        self.is_power_required(now, |maintain_power| maintain_power.maintain_ecu_power)
+    }
+    pub fn is_actuator_power_required(&self, now: Duration) -> bool {
+        self.is_power_required(now, |maintain_power| maintain_power.maintain_actuator_power)
+    }
+
+    fn is_power_required(&self, now: Duration, is_requested: impl Fn(&MaintainPower) -> bool) -> bool {
+        let Some(key_switch_off_since) = self.key_switch_off_since else {
+            return true;
+        };
+        if now.saturating_sub(key_switch_off_since) < POWER_DOWN_DELAY {
+            return true;
+        }
+
+        // Only requests received after the key switch was turned off keep the power on.
+        self.requests.values().any(|(maintain_power, timestamp)| {
+            *timestamp >= key_switch_off_since && now.saturating_sub(*timestamp) < POWER_DOWN_DELAY && is_requested(maintain_power)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implement_messages::test_helpers::{message, sent, setup};
+
+    #[test]
+    fn encoding() {
+        let maintain_power = MaintainPower {
+            maintain_ecu_power: true,
+            maintain_actuator_power: false,
+            in_transport: Some(false),
+            ready_to_park: None,
+            ready_to_work: Some(true),
+            in_work: Some(false),
+        };
+        let data = maintain_power.as_bytes();
+        assert_eq!(data[..2], [0b01001111, 0b00110100]);
+        assert_eq!(MaintainPower::try_from(&message(maintain_power.pgn(), &data)), Ok(maintain_power));
+    }
+
+    #[test]
+    fn power_down_timing() {
+        let mut monitor = MaintainPowerMonitor::new();
+        let request = MaintainPower {
+            maintain_ecu_power: true,
+            ..Default::default()
+        };
+        let message = message(request.pgn(), &request.as_bytes());
+
+        monitor.set_key_switch_state(false, Duration::from_millis(1000));
+        assert!(monitor.is_ecu_power_required(Duration::from_millis(2500)));
+        assert!(!monitor.is_ecu_power_required(Duration::from_millis(3000)));
+
+        assert!(monitor.process_can_message(&message, Duration::from_millis(3500)));
+        assert!(monitor.is_ecu_power_required(Duration::from_millis(5000)));
+        assert!(!monitor.is_actuator_power_required(Duration::from_millis(5000)));
+        assert!(!monitor.is_ecu_power_required(Duration::from_millis(5500)));
+    }
+
+    #[test]
+    fn client_resends_every_second() {
+        let (mut network_manager, driver, handle) = setup();
+        let mut client = MaintainPowerClient::new(handle);
+
+        // Nothing is send while no power is required.
+        client.update(&mut network_manager, Duration::from_millis(0));
+        assert!(sent(&driver).is_empty());
+
+        client.set_maintain_power(MaintainPower { maintain_actuator_power: true, ..Default::default() });
+        client.update(&mut network_manager, Duration::from_millis(1000));
+        client.update(&mut network_manager, Duration::from_millis(1999));
+        assert_eq!(sent(&driver), [ParameterGroupNumber::MaintainPower]);
+        client.update(&mut network_manager, Duration::from_millis(2000));
+        assert_eq!(sent(&driver), [ParameterGroupNumber::MaintainPower]);
+
+        // The requests stop once no power is needed, a new request is send right away.
+        client.set_maintain_power(MaintainPower::default());
+        client.update(&mut network_manager, Duration::from_millis(3000));
+        assert!(sent(&driver).is_empty());
+        client.set_maintain_power(MaintainPower { maintain_ecu_power: true, ..Default::default() });
+        client.update(&mut network_manager, Duration::from_millis(3100));
+        assert_eq!(sent(&driver), [ParameterGroupNumber::MaintainPower]);
+    }
+}
@@ -0,0 +1,78 @@
+//! The implement messages application layer, as defined by ISO11783-7.
+
+use crate::payload::check_j1939_bits;
+
+/// Decode a 2 bit state, `None` when it is not available or in error.
+fn state_at(byte: u8, shift: u8) -> Option<bool> {
+    check_j1939_bits((byte >> shift) & 0b11, 2).ok().map(|state| state == 0b01)
+}
+
+fn state_bits(state: Option<bool>) -> u8 {
+    state.map_or(0b11, |state| state as u8)
+}
+
+#[cfg(test)]
+mod test_helpers {
+    use alloc::vec::Vec;
+    use core::time::Duration;
+
+    use crate::control_function::ControlFunctionHandle;
+    use crate::hardware_integration::CanDriver;
+    use crate::name::Name;
+    use crate::{Address, CanMessage, CanNetworkManager, CanPriority, ParameterGroupNumber};
+
+    /// A message from address 0x80 to global.
+    pub fn message(pgn: ParameterGroupNumber, data: &[u8]) -> CanMessage {
+        CanMessage::new(CanPriority::PriorityDefault6, pgn, Address(0x80), Address::GLOBAL, data)
+    }
+
+    /// Create a network manager with an internal control function that claimed address 0x80.
+    pub fn setup() -> (CanNetworkManager, CanDriver, ControlFunctionHandle) {
+        let driver = CanDriver::new();
+        let mut network_manager = CanNetworkManager::new(driver.clone());
+        let handle = network_manager.new_internal_control_function(Name::default(), Address(0x80));
+        network_manager.internal_control_function_mut(handle).unwrap().initialize();
+        for _ in 0..100 {
+            network_manager.update();
+            if network_manager.is_address_internaly_claimed(Address(0x80)) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = driver.take_written_frames();
+        (network_manager, driver, handle)
+    }
+
+    /// The PGNs send since the last call, without the DM1 broadcasts.
+    pub fn sent(driver: &CanDriver) -> Vec<ParameterGroupNumber> {
+        driver.take_written_frames().into_iter()
+            .map(|frame| CanMessage::from(frame).pgn())
+            .filter(|&pgn| pgn != ParameterGroupNumber::DiagnosticMessage1)
+            .collect()
+    }
+}
+
+mod guidance;
+pub use guidance::{
+    curvature_from_raw, curvature_to_raw, GuidanceMachineInfo, GuidanceMachineInterface, GuidanceSystemCommand,
+    GuidanceSystemInterface,
+};
+
+mod language_command;
+pub use language_command::{DateFormat, DecimalSymbol, LanguageCommand, TimeFormat, UnitSystem};
+
+mod maintain_power;
+pub use maintain_power::{MaintainPower, MaintainPowerClient, MaintainPowerMonitor};
+
+mod speed_and_distance;
+pub use speed_and_distance::{
+    GroundBasedSpeedAndDistance, LimitStatus, MachineDirection, MachineSelectedSpeed, MachineSelectedSpeedCommand,
+    SpeedListener, SpeedSource, WheelBasedSpeedAndDistance,
+};
+
+mod tractor_facilities;
+pub use tractor_facilities::{
+    AuxiliaryValveCommand, AuxiliaryValveEstimatedFlow, AuxiliaryValveMeasuredPosition, FailSafeMode, HitchCommand,
+    HitchStatus, Location, PtoCommand, PtoMode, PtoStatus, TractorFacilities, TractorFacilitiesListener,
+    TractorFacility, ValveState,
+};
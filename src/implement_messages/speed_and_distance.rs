@@ -0,0 +1,397 @@
+use core::time::Duration;
+
+use crate::payload::{check_j1939_bits, check_j1939_u16, check_j1939_u32, check_j1939_u8};
+use crate::{CanMessage, ParameterGroupNumber, PayloadError};
+
+use super::{state_at, state_bits};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(300);    //< Three missed messages at the usual 100 ms interval
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum MachineDirection {
+    Reverse = 0,
+    Forward = 1,
+}
+
+impl MachineDirection {
+    fn from_bits(value: u8) -> Option<Self> {
+        match check_j1939_bits(value & 0b11, 2) {
+            Ok(0) => Some(Self::Reverse),
+            Ok(_) => Some(Self::Forward),
+            Err(_) => None,
+        }
+    }
+}
+
+/// The source of the machine selected speed.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SpeedSource {
+    WheelBased = 0,
+    GroundBased = 1,
+    NavigationBased = 2,
+    Blended = 3,
+    Simulated = 4,
+}
+
+impl TryFrom<u8> for SpeedSource {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::WheelBased),
+            1 => Ok(Self::GroundBased),
+            2 => Ok(Self::NavigationBased),
+            3 => Ok(Self::Blended),
+            4 => Ok(Self::Simulated),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Why the machine selected speed is not the speed the operator asked for.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum LimitStatus {
+    NotLimited = 0,
+    OperatorLimited = 1,        //< The operator is controlling the speed
+    LimitedHigh = 2,            //< The speed can not go higher
+    LimitedLow = 3,             //< The speed can not go lower
+    NonRecoverableFault = 6,
+}
+
+impl TryFrom<u8> for LimitStatus {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::NotLimited),
+            1 => Ok(Self::OperatorLimited),
+            2 => Ok(Self::LimitedHigh),
+            3 => Ok(Self::LimitedLow),
+            6 => Ok(Self::NonRecoverableFault),
+            _ => Err(()),
+        }
+    }
+}
+
+fn direction_bits(direction: Option<MachineDirection>) -> u8 {
+    direction.map_or(0b11, |direction| direction as u8)
+}
+
+/// The speed (mm/s) and distance (mm) at the start of the messages, `None` when not available.
+fn speed_and_distance(value: &[u8]) -> Result<(Option<u16>, Option<u32>), PayloadError> {
+    if value.len() < 8 {
+        return Err(PayloadError::OutOfRange);
+    }
+    let speed = check_j1939_u16(u16::from_le_bytes([value[0], value[1]])).ok();
+    let distance = check_j1939_u32(u32::from_le_bytes([value[2], value[3], value[4], value[5]])).ok();
+    Ok((speed, distance))
+}
+
+fn speed_and_distance_bytes(speed: Option<u16>, distance: Option<u32>) -> [u8; 8] {
+    let mut data = [0xFF; 8];
+    data[0..2].copy_from_slice(&speed.unwrap_or(0xFFFF).to_le_bytes());
+    data[2..6].copy_from_slice(&distance.unwrap_or(0xFFFFFFFF).to_le_bytes());
+    data
+}
+
+/// The Wheel-based Speed and Distance, measured at the wheels or transmission of the tractor.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct WheelBasedSpeedAndDistance {
+    pub speed: Option<u16>,                     //< mm/s
+    pub distance: Option<u32>,                  //< mm, wraps around
+    pub maximum_power_time: Option<u8>,         //< Minutes the tractor keeps power after the key switch is turned off
+    pub direction: Option<MachineDirection>,
+    pub key_switch_not_off: Option<bool>,
+    pub implement_operations_started: Option<bool>,
+    pub operator_direction_reversed: Option<bool>,
+}
+
+impl WheelBasedSpeedAndDistance {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::WheelBasedSpeedAndDistance
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = speed_and_distance_bytes(self.speed, self.distance);
+        data[6] = self.maximum_power_time.unwrap_or(0xFF);
+        data[7] = state_bits(self.operator_direction_reversed) << 6
+            | state_bits(self.implement_operations_started) << 4
+            | state_bits(self.key_switch_not_off) << 2
+            | direction_bits(self.direction);
+        data
+    }
+}
+
+impl TryFrom<&CanMessage> for WheelBasedSpeedAndDistance {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::WheelBasedSpeedAndDistance {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        let (speed, distance) = speed_and_distance(value)?;
+        Ok(Self {
+            speed,
+            distance,
+            maximum_power_time: check_j1939_u8(value[6]).ok(),
+            direction: MachineDirection::from_bits(value[7]),
+            key_switch_not_off: state_at(value[7], 2),
+            implement_operations_started: state_at(value[7], 4),
+            operator_direction_reversed: state_at(value[7], 6),
+        })
+    }
+}
+
+/// The Ground-based Speed and Distance, measured by a sensor like a radar.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct GroundBasedSpeedAndDistance {
+    pub speed: Option<u16>,         //< mm/s
+    pub distance: Option<u32>,      //< mm, wraps around
+    pub direction: Option<MachineDirection>,
+}
+
+impl GroundBasedSpeedAndDistance {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::GroundBasedSpeedAndDistance
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = speed_and_distance_bytes(self.speed, self.distance);
+        data[7] = 0xFC | direction_bits(self.direction);
+        data
+    }
+}
+
+impl TryFrom<&CanMessage> for GroundBasedSpeedAndDistance {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::GroundBasedSpeedAndDistance {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        let (speed, distance) = speed_and_distance(value)?;
+        Ok(Self {
+            speed,
+            distance,
+            direction: MachineDirection::from_bits(value[7]),
+        })
+    }
+}
+
+/// The Machine Selected Speed, the speed the tractor considers the best available.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct MachineSelectedSpeed {
+    pub speed: Option<u16>,                 //< mm/s
+    pub distance: Option<u32>,              //< mm, wraps around
+    pub exit_reason_code: Option<u8>,       //< The 6 bit reason the tractor left automatic control
+    pub limit_status: Option<LimitStatus>,
+    pub source: Option<SpeedSource>,
+    pub direction: Option<MachineDirection>,
+}
+
+impl MachineSelectedSpeed {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::MachineSelectedSpeed
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = speed_and_distance_bytes(self.speed, self.distance);
+        data[6] = 0xC0 | self.exit_reason_code.unwrap_or(0x3F);
+        data[7] = self.limit_status.map_or(0b111, |status| status as u8) << 5
+            | self.source.map_or(0b111, |source| source as u8) << 2
+            | direction_bits(self.direction);
+        data
+    }
+}
+
+impl TryFrom<&CanMessage> for MachineSelectedSpeed {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::MachineSelectedSpeed {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        let (speed, distance) = speed_and_distance(value)?;
+        Ok(Self {
+            speed,
+            distance,
+            exit_reason_code: Some(value[6] & 0x3F).filter(|&code| code != 0x3F),
+            limit_status: LimitStatus::try_from(value[7] >> 5).ok(),
+            source: SpeedSource::try_from((value[7] >> 2) & 0b111).ok(),
+            direction: MachineDirection::from_bits(value[7]),
+        })
+    }
+}
+
+/// The Machine Selected Speed Command, send by an implement that controls the speed of the tractor.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct MachineSelectedSpeedCommand {
+    pub speed_setpoint: Option<u16>,    //< mm/s
+    pub maximum_speed: Option<u16>,     //< mm/s
+    pub direction: Option<MachineDirection>,
+}
+
+impl MachineSelectedSpeedCommand {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::MachineSelectedSpeedCommand
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0..2].copy_from_slice(&self.speed_setpoint.unwrap_or(0xFFFF).to_le_bytes());
+        data[2..4].copy_from_slice(&self.maximum_speed.unwrap_or(0xFFFF).to_le_bytes());
+        data[7] = 0xFC | direction_bits(self.direction);
+        data
+    }
+}
+
+impl TryFrom<&CanMessage> for MachineSelectedSpeedCommand {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::MachineSelectedSpeedCommand {
+            return Err(PayloadError::OutOfRange);
+        }
+        let value = message.data();
+        if value.len() < 8 {
+            return Err(PayloadError::OutOfRange);
+        }
+        Ok(Self {
+            speed_setpoint: check_j1939_u16(u16::from_le_bytes([value[0], value[1]])).ok(),
+            maximum_speed: check_j1939_u16(u16::from_le_bytes([value[2], value[3]])).ok(),
+            direction: MachineDirection::from_bits(value[7]),
+        })
+    }
+}
+
+/// Keeps the latest speed messages, a message older than the timeout is considered lost.
+pub struct SpeedListener {
+    timeout: Duration,
+    wheel_based: Option<(WheelBasedSpeedAndDistance, Duration)>,
+    ground_based: Option<(GroundBasedSpeedAndDistance, Duration)>,
+    machine_selected: Option<(MachineSelectedSpeed, Duration)>,
+    machine_selected_command: Option<(MachineSelectedSpeedCommand, Duration)>,
+}
+
+impl Default for SpeedListener {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT)
+    }
+}
+
+impl SpeedListener {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            wheel_based: None,
+            ground_based: None,
+            machine_selected: None,
+            machine_selected_command: None,
+        }
+    }
+
+    /// Process a received message, returns true if it was one of the speed messages.
+    pub fn process_can_message(&mut self, message: &CanMessage, now: Duration) -> bool {
+        let result = match message.pgn() {
+            ParameterGroupNumber::WheelBasedSpeedAndDistance => WheelBasedSpeedAndDistance::try_from(message)
+                .map(|speed| self.wheel_based = Some((speed, now))),
+            ParameterGroupNumber::GroundBasedSpeedAndDistance => GroundBasedSpeedAndDistance::try_from(message)
+                .map(|speed| self.ground_based = Some((speed, now))),
+            ParameterGroupNumber::MachineSelectedSpeed => MachineSelectedSpeed::try_from(message)
+                .map(|speed| self.machine_selected = Some((speed, now))),
+            ParameterGroupNumber::MachineSelectedSpeedCommand => MachineSelectedSpeedCommand::try_from(message)
+                .map(|command| self.machine_selected_command = Some((command, now))),
+            _ => return false,
+        };
+
+        if let Err(error) = result {
+            log::warn!("[SPD]: Invalid {:?} from {}: {:?}", message.pgn(), message.source_address(), error);
+        }
+        true
+    }
+
+    pub fn wheel_based(&self, now: Duration) -> Option<WheelBasedSpeedAndDistance> {
+        self.fresh(self.wheel_based, now)
+    }
+    pub fn ground_based(&self, now: Duration) -> Option<GroundBasedSpeedAndDistance> {
+        self.fresh(self.ground_based, now)
+    }
+    pub fn machine_selected(&self, now: Duration) -> Option<MachineSelectedSpeed> {
+        self.fresh(self.machine_selected, now)
+    }
+    pub fn machine_selected_command(&self, now: Duration) -> Option<MachineSelectedSpeedCommand> {
+        self.fresh(self.machine_selected_command, now)
+    }
+
+    /// The best available ground speed in mm/s, machine selected speed is preferred over ground-based over wheel-based.
+    /// The source is `None` when the machine selected speed does not report it.
+    pub fn ground_speed(&self, now: Duration) -> Option<(Option<SpeedSource>, u16)> {
+        let machine_selected = self.machine_selected(now)
+            .and_then(|speed| Some((speed.source, speed.speed?)));
+        let ground_based = self.ground_based(now)
+            .and_then(|speed| Some((Some(SpeedSource::GroundBased), speed.speed?)));
+        let wheel_based = self.wheel_based(now)
+            .and_then(|speed| Some((Some(SpeedSource::WheelBased), speed.speed?)));
+
+        machine_selected.or(ground_based).or(wheel_based)
+    }
+
+    fn fresh<T>(&self, received: Option<(T, Duration)>, now: Duration) -> Option<T> {
+        received
+            .filter(|(_, timestamp)| now.saturating_sub(*timestamp) < self.timeout)
+            .map(|(value, _)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implement_messages::test_helpers::message;
+
+    #[test]
+    fn encoding() {
+        let speed = MachineSelectedSpeed {
+            speed: Some(2500),
+            distance: Some(1_000_000),
+            exit_reason_code: None,
+            limit_status: Some(LimitStatus::NotLimited),
+            source: Some(SpeedSource::GroundBased),
+            direction: Some(MachineDirection::Forward),
+        };
+        let data = speed.as_bytes();
+        assert_eq!(data, [0xC4, 0x09, 0x40, 0x42, 0x0F, 0x00, 0xFF, 0b00000101]);
+        assert_eq!(MachineSelectedSpeed::try_from(&message(speed.pgn(), &data)), Ok(speed));
+
+        let speed = WheelBasedSpeedAndDistance {
+            key_switch_not_off: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(WheelBasedSpeedAndDistance::try_from(&message(speed.pgn(), &speed.as_bytes())), Ok(speed));
+    }
+
+    #[test]
+    fn stale_speed_is_dropped() {
+        let mut listener = SpeedListener::default();
+        let speed = GroundBasedSpeedAndDistance {
+            speed: Some(1000),
+            ..Default::default()
+        };
+        assert!(listener.process_can_message(&message(speed.pgn(), &speed.as_bytes()), Duration::from_millis(100)));
+        assert_eq!(listener.ground_speed(Duration::from_millis(200)), Some((Some(SpeedSource::GroundBased), 1000)));
+        assert_eq!(listener.ground_speed(Duration::from_millis(400)), None);
+    }
+
+    #[test]
+    fn unknown_machine_selected_source() {
+        let mut listener = SpeedListener::default();
+        let speed = MachineSelectedSpeed {
+            speed: Some(1500),
+            ..Default::default()
+        };
+        assert!(listener.process_can_message(&message(speed.pgn(), &speed.as_bytes()), Duration::from_millis(100)));
+        assert_eq!(listener.ground_speed(Duration::from_millis(200)), Some((None, 1500)));
+    }
+}
@@ -0,0 +1,801 @@
+use core::time::Duration;
+
+use alloc::collections::BTreeMap;
+
+use crate::payload::{check_j1939_bits, check_j1939_u16, check_j1939_u8};
+use crate::{
+    control_function::ControlFunctionHandle, Address, CanMessage, CanNetworkManager, CanPriority, ParameterGroupNumber,
+    PayloadError, SendError,
+};
+
+use super::{state_at, state_bits, LimitStatus};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(300);    //< Three missed messages at the usual 100 ms interval
+const NUMBER_OF_AUXILIARY_VALVES: u8 = 16;
+
+/// The hitch or PTO a message is about.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Location {
+    Front,
+    Rear,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum PtoMode {
+    Rpm540 = 0,
+    Rpm1000 = 1,
+}
+
+/// The state of an auxiliary valve, seen from the implement cylinder.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ValveState {
+    Blocked = 0,
+    Extend = 1,
+    Retract = 2,
+    Floating = 3,
+}
+
+impl TryFrom<u8> for ValveState {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Blocked),
+            1 => Ok(Self::Extend),
+            2 => Ok(Self::Retract),
+            3 => Ok(Self::Floating),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What an auxiliary valve does when the commands stop.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum FailSafeMode {
+    Block = 0,
+    Float = 1,
+}
+
+fn limit_status_bits(limit_status: Option<LimitStatus>) -> u8 {
+    limit_status.map_or(0b111, |status| status as u8)
+}
+
+fn exit_reason_code(byte: u8) -> Option<u8> {
+    Some(byte & 0x3F).filter(|&code| code != 0x3F)
+}
+
+fn u16_at(value: &[u8], index: usize) -> Option<u16> {
+    check_j1939_u16(u16::from_le_bytes([value[index], value[index + 1]])).ok()
+}
+
+fn check_length(message: &CanMessage) -> Result<&[u8], PayloadError> {
+    match message.data() {
+        data if data.len() >= 8 => Ok(data),
+        _ => Err(PayloadError::OutOfRange),
+    }
+}
+
+/// The valve of an auxiliary valve PGN, `None` when the PGN is not in the range of `base`.
+fn valve_of(pgn: ParameterGroupNumber, base: ParameterGroupNumber) -> Option<u8> {
+    pgn.as_u32()
+        .checked_sub(base.as_u32())
+        .filter(|&valve| valve < NUMBER_OF_AUXILIARY_VALVES as u32)
+        .map(|valve| valve as u8)
+}
+
+/// The PGN of auxiliary valve `valve`, counting up from the PGN of valve 0 `base`.
+fn valve_pgn(base: ParameterGroupNumber, valve: u8) -> Result<ParameterGroupNumber, PayloadError> {
+    match valve {
+        valve if valve < NUMBER_OF_AUXILIARY_VALVES => Ok(ParameterGroupNumber::new(base.as_u32() + valve as u32)),
+        _ => Err(PayloadError::OutOfRange),
+    }
+}
+
+/// The Front or Rear Hitch Status, send by the tractor.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HitchStatus {
+    pub location: Location,
+    pub position: Option<u8>,                   //< 0.4 %/bit, 0 is fully lowered and 250 fully raised
+    pub in_work: Option<bool>,
+    pub limit_status: Option<LimitStatus>,
+    pub exit_reason_code: Option<u8>,
+    pub nominal_lower_link_force: Option<u8>,   //< 0.8 %/bit, offset -100 %
+    pub draft: Option<u16>,                     //< 10 N/bit, offset -320000 N
+}
+
+impl HitchStatus {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        match self.location {
+            Location::Front => ParameterGroupNumber::FrontHitchStatus,
+            Location::Rear => ParameterGroupNumber::RearHitchStatus,
+        }
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0] = self.position.unwrap_or(0xFF);
+        data[1] = state_bits(self.in_work) << 6 | limit_status_bits(self.limit_status) << 3 | 0b111;
+        data[2] = self.nominal_lower_link_force.unwrap_or(0xFF);
+        data[3..5].copy_from_slice(&self.draft.unwrap_or(0xFFFF).to_le_bytes());
+        data[5] = 0xC0 | self.exit_reason_code.unwrap_or(0x3F);
+        data
+    }
+
+    /// Broadcast the message from the tractor ECU `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        network_manager.send_from(from, CanPriority::Priority3, self.pgn(), Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for HitchStatus {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        let location = match message.pgn() {
+            ParameterGroupNumber::FrontHitchStatus => Location::Front,
+            ParameterGroupNumber::RearHitchStatus => Location::Rear,
+            _ => return Err(PayloadError::OutOfRange),
+        };
+        let data = check_length(message)?;
+        Ok(Self {
+            location,
+            position: check_j1939_u8(data[0]).ok(),
+            in_work: state_at(data[1], 6),
+            limit_status: LimitStatus::try_from((data[1] >> 3) & 0b111).ok(),
+            exit_reason_code: exit_reason_code(data[5]),
+            nominal_lower_link_force: check_j1939_u8(data[2]).ok(),
+            draft: u16_at(data, 3),
+        })
+    }
+}
+
+/// The Front or Rear Hitch Command, send by an implement that controls the hitch.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HitchCommand {
+    pub location: Location,
+    pub position: Option<u16>,  //< 0.0025 %/bit, 0 is fully lowered and 40000 fully raised
+    pub in_work: Option<bool>,
+}
+
+impl HitchCommand {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        match self.location {
+            Location::Front => ParameterGroupNumber::FrontHitchCommand,
+            Location::Rear => ParameterGroupNumber::RearHitchCommand,
+        }
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0..2].copy_from_slice(&self.position.unwrap_or(0xFFFF).to_le_bytes());
+        data[2] = state_bits(self.in_work) << 6 | 0x3F;
+        data
+    }
+
+    /// Broadcast the message from the implement `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        network_manager.send_from(from, CanPriority::Priority3, self.pgn(), Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for HitchCommand {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        let location = match message.pgn() {
+            ParameterGroupNumber::FrontHitchCommand => Location::Front,
+            ParameterGroupNumber::RearHitchCommand => Location::Rear,
+            _ => return Err(PayloadError::OutOfRange),
+        };
+        let data = check_length(message)?;
+        Ok(Self {
+            location,
+            position: u16_at(data, 0),
+            in_work: state_at(data[2], 6),
+        })
+    }
+}
+
+/// The Front or Rear PTO Output Shaft status, send by the tractor.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PtoStatus {
+    pub location: Location,
+    pub speed: Option<u16>,             //< 0.125 rpm/bit
+    pub speed_setpoint: Option<u16>,    //< 0.125 rpm/bit
+    pub engaged: Option<bool>,
+    pub mode: Option<PtoMode>,
+    pub economy_mode: Option<bool>,
+    pub limit_status: Option<LimitStatus>,
+    pub exit_reason_code: Option<u8>,
+}
+
+impl PtoStatus {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        match self.location {
+            Location::Front => ParameterGroupNumber::FrontPtoOutputShaft,
+            Location::Rear => ParameterGroupNumber::RearPtoOutputShaft,
+        }
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0..2].copy_from_slice(&self.speed.unwrap_or(0xFFFF).to_le_bytes());
+        data[2..4].copy_from_slice(&self.speed_setpoint.unwrap_or(0xFFFF).to_le_bytes());
+        data[4] = pto_bits(self.engaged, self.mode, self.economy_mode);
+        data[5] = 0xC0 | self.exit_reason_code.unwrap_or(0x3F);
+        data[6] = limit_status_bits(self.limit_status) << 5 | 0x1F;
+        data
+    }
+
+    /// Broadcast the message from the tractor ECU `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        network_manager.send_from(from, CanPriority::Priority3, self.pgn(), Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for PtoStatus {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        let location = match message.pgn() {
+            ParameterGroupNumber::FrontPtoOutputShaft => Location::Front,
+            ParameterGroupNumber::RearPtoOutputShaft => Location::Rear,
+            _ => return Err(PayloadError::OutOfRange),
+        };
+        let data = check_length(message)?;
+        Ok(Self {
+            location,
+            speed: u16_at(data, 0),
+            speed_setpoint: u16_at(data, 2),
+            engaged: state_at(data[4], 6),
+            mode: pto_mode_at(data[4]),
+            economy_mode: state_at(data[4], 2),
+            limit_status: LimitStatus::try_from(data[6] >> 5).ok(),
+            exit_reason_code: exit_reason_code(data[5]),
+        })
+    }
+}
+
+/// The Front or Rear PTO Command, send by an implement that controls the PTO.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PtoCommand {
+    pub location: Location,
+    pub speed_setpoint: Option<u16>,    //< 0.125 rpm/bit
+    pub engage: Option<bool>,
+    pub mode: Option<PtoMode>,
+    pub economy_mode: Option<bool>,
+}
+
+impl PtoCommand {
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        match self.location {
+            Location::Front => ParameterGroupNumber::FrontPtoCommand,
+            Location::Rear => ParameterGroupNumber::RearPtoCommand,
+        }
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0..2].copy_from_slice(&self.speed_setpoint.unwrap_or(0xFFFF).to_le_bytes());
+        data[2] = pto_bits(self.engage, self.mode, self.economy_mode);
+        data
+    }
+
+    /// Broadcast the message from the implement `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        network_manager.send_from(from, CanPriority::Priority3, self.pgn(), Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for PtoCommand {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        let location = match message.pgn() {
+            ParameterGroupNumber::FrontPtoCommand => Location::Front,
+            ParameterGroupNumber::RearPtoCommand => Location::Rear,
+            _ => return Err(PayloadError::OutOfRange),
+        };
+        let data = check_length(message)?;
+        Ok(Self {
+            location,
+            speed_setpoint: u16_at(data, 0),
+            engage: state_at(data[2], 6),
+            mode: pto_mode_at(data[2]),
+            economy_mode: state_at(data[2], 2),
+        })
+    }
+}
+
+/// The engagement, mode and economy mode bits shared by the PTO status and command.
+fn pto_bits(engaged: Option<bool>, mode: Option<PtoMode>, economy_mode: Option<bool>) -> u8 {
+    state_bits(engaged) << 6 | mode.map_or(0b11, |mode| mode as u8) << 4 | state_bits(economy_mode) << 2 | 0b11
+}
+
+fn pto_mode_at(byte: u8) -> Option<PtoMode> {
+    match check_j1939_bits((byte >> 4) & 0b11, 2) {
+        Ok(0) => Some(PtoMode::Rpm540),
+        Ok(_) => Some(PtoMode::Rpm1000),
+        Err(_) => None,
+    }
+}
+
+/// The valve state and fail safe mode bits shared by the auxiliary valve messages.
+fn valve_bits(fail_safe_mode: Option<FailSafeMode>, state: Option<ValveState>) -> u8 {
+    fail_safe_mode.map_or(0b11, |mode| mode as u8) << 6 | 0b11 << 4 | state.map_or(0x0F, |state| state as u8)
+}
+
+fn fail_safe_mode_at(byte: u8) -> Option<FailSafeMode> {
+    match check_j1939_bits(byte >> 6, 2) {
+        Ok(0) => Some(FailSafeMode::Block),
+        Ok(_) => Some(FailSafeMode::Float),
+        Err(_) => None,
+    }
+}
+
+/// The Auxiliary Valve Estimated Flow, send by the tractor for each of its 16 valves.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AuxiliaryValveEstimatedFlow {
+    pub valve: u8,
+    pub extend_flow: Option<u8>,    //< 1 %/bit, offset -125 %
+    pub retract_flow: Option<u8>,   //< 1 %/bit, offset -125 %
+    pub fail_safe_mode: Option<FailSafeMode>,
+    pub state: Option<ValveState>,
+    pub limit_status: Option<LimitStatus>,
+    pub exit_reason_code: Option<u8>,
+}
+
+impl AuxiliaryValveEstimatedFlow {
+    /// Returns [`PayloadError::OutOfRange`] when `valve` is not one of the 16 valves.
+    pub fn pgn(&self) -> Result<ParameterGroupNumber, PayloadError> {
+        valve_pgn(ParameterGroupNumber::AuxiliaryValveEstimatedFlow, self.valve)
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0] = self.extend_flow.unwrap_or(0xFF);
+        data[1] = self.retract_flow.unwrap_or(0xFF);
+        data[2] = valve_bits(self.fail_safe_mode, self.state);
+        data[3] = 0xC0 | self.exit_reason_code.unwrap_or(0x3F);
+        data[4] = limit_status_bits(self.limit_status) << 5 | 0x1F;
+        data
+    }
+
+    /// Broadcast the message from the tractor ECU `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        let pgn = self.pgn().map_err(|_| SendError::InvalidMessage)?;
+        network_manager.send_from(from, CanPriority::Priority3, pgn, Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for AuxiliaryValveEstimatedFlow {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        let valve = valve_of(message.pgn(), ParameterGroupNumber::AuxiliaryValveEstimatedFlow)
+            .ok_or(PayloadError::OutOfRange)?;
+        let data = check_length(message)?;
+        Ok(Self {
+            valve,
+            extend_flow: check_j1939_u8(data[0]).ok(),
+            retract_flow: check_j1939_u8(data[1]).ok(),
+            fail_safe_mode: fail_safe_mode_at(data[2]),
+            state: ValveState::try_from(data[2] & 0x0F).ok(),
+            limit_status: LimitStatus::try_from(data[4] >> 5).ok(),
+            exit_reason_code: exit_reason_code(data[3]),
+        })
+    }
+}
+
+/// The Auxiliary Valve Measured Position, send by tractors that measure the spool position of a valve.
+///
+/// Bytes 1 and 2 hold the measured position, the lower nibble of byte 3 the valve state, the rest is reserved.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AuxiliaryValveMeasuredPosition {
+    pub valve: u8,
+    pub position: Option<u16>,  //< 0.0025 %/bit, 0 is fully retract and 40000 fully extend
+    pub state: Option<ValveState>,
+}
+
+impl AuxiliaryValveMeasuredPosition {
+    /// Returns [`PayloadError::OutOfRange`] when `valve` is not one of the 16 valves.
+    pub fn pgn(&self) -> Result<ParameterGroupNumber, PayloadError> {
+        valve_pgn(ParameterGroupNumber::AuxiliaryValveMeasuredPosition, self.valve)
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0..2].copy_from_slice(&self.position.unwrap_or(0xFFFF).to_le_bytes());
+        data[2] = 0xF0 | self.state.map_or(0x0F, |state| state as u8);
+        data
+    }
+
+    /// Broadcast the message from the tractor ECU `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        let pgn = self.pgn().map_err(|_| SendError::InvalidMessage)?;
+        network_manager.send_from(from, CanPriority::Priority3, pgn, Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for AuxiliaryValveMeasuredPosition {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        let valve = valve_of(message.pgn(), ParameterGroupNumber::AuxiliaryValveMeasuredPosition)
+            .ok_or(PayloadError::OutOfRange)?;
+        let data = check_length(message)?;
+        Ok(Self {
+            valve,
+            position: u16_at(data, 0),
+            state: ValveState::try_from(data[2] & 0x0F).ok(),
+        })
+    }
+}
+
+/// The Auxiliary Valve Command, send by an implement that controls a valve.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AuxiliaryValveCommand {
+    pub valve: u8,
+    pub flow: Option<u8>,   //< 0.4 %/bit of the maximum flow
+    pub fail_safe_mode: Option<FailSafeMode>,
+    pub state: Option<ValveState>,
+}
+
+impl AuxiliaryValveCommand {
+    /// Returns [`PayloadError::OutOfRange`] when `valve` is not one of the 16 valves.
+    pub fn pgn(&self) -> Result<ParameterGroupNumber, PayloadError> {
+        valve_pgn(ParameterGroupNumber::AuxiliaryValveCommand, self.valve)
+    }
+
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut data = [0xFF; 8];
+        data[0] = self.flow.unwrap_or(0xFF);
+        data[2] = valve_bits(self.fail_safe_mode, self.state);
+        data
+    }
+
+    /// Broadcast the message from the implement `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        let pgn = self.pgn().map_err(|_| SendError::InvalidMessage)?;
+        network_manager.send_from(from, CanPriority::Priority3, pgn, Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for AuxiliaryValveCommand {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        let valve = valve_of(message.pgn(), ParameterGroupNumber::AuxiliaryValveCommand)
+            .ok_or(PayloadError::OutOfRange)?;
+        let data = check_length(message)?;
+        Ok(Self {
+            valve,
+            flow: check_j1939_u8(data[0]).ok(),
+            fail_safe_mode: fail_safe_mode_at(data[2]),
+            state: ValveState::try_from(data[2] & 0x0F).ok(),
+        })
+    }
+}
+
+/// The facilities a tractor can provide, grouped by their tractor ECU class.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum TractorFacility {
+    // Class 1
+    RearHitchPosition,
+    RearHitchInWork,
+    RearPtoSpeed,
+    RearPtoEngagement,
+    WheelBasedSpeed,
+    GroundBasedSpeed,
+    // Class 2
+    GroundBasedDistance,
+    GroundBasedDirection,
+    WheelBasedDistance,
+    WheelBasedDirection,
+    RearDraft,
+    RearLowerLinkForce,
+    // Class 3
+    RearHitchCommand,
+    RearPtoCommand,
+    AuxiliaryValveCommand,
+    // Front hitch and PTO
+    FrontHitchPosition,
+    FrontHitchInWork,
+    FrontPtoSpeed,
+    FrontPtoEngagement,
+    FrontHitchCommand,
+    FrontPtoCommand,
+}
+
+impl TractorFacility {
+    /// The byte and bit of the facility in the Tractor Facilities Response.
+    fn position(&self) -> (usize, u8) {
+        match self {
+            TractorFacility::RearHitchPosition => (0, 7),
+            TractorFacility::RearHitchInWork => (0, 6),
+            TractorFacility::RearPtoSpeed => (0, 5),
+            TractorFacility::RearPtoEngagement => (0, 4),
+            TractorFacility::WheelBasedSpeed => (0, 3),
+            TractorFacility::GroundBasedSpeed => (0, 2),
+            TractorFacility::GroundBasedDistance => (1, 7),
+            TractorFacility::GroundBasedDirection => (1, 6),
+            TractorFacility::WheelBasedDistance => (1, 5),
+            TractorFacility::WheelBasedDirection => (1, 4),
+            TractorFacility::RearDraft => (1, 3),
+            TractorFacility::RearLowerLinkForce => (1, 2),
+            TractorFacility::RearHitchCommand => (2, 7),
+            TractorFacility::RearPtoCommand => (2, 6),
+            TractorFacility::AuxiliaryValveCommand => (2, 5),
+            TractorFacility::FrontHitchPosition => (3, 7),
+            TractorFacility::FrontHitchInWork => (3, 6),
+            TractorFacility::FrontPtoSpeed => (3, 5),
+            TractorFacility::FrontPtoEngagement => (3, 4),
+            TractorFacility::FrontHitchCommand => (3, 3),
+            TractorFacility::FrontPtoCommand => (3, 2),
+        }
+    }
+}
+
+/// The Tractor Facilities Response, the facilities a tractor ECU provides to the implements.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TractorFacilities {
+    data: [u8; 8],
+}
+
+impl Default for TractorFacilities {
+    fn default() -> Self {
+        Self {
+            data: [0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF],
+        }
+    }
+}
+
+impl TractorFacilities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn supports(&self, facility: TractorFacility) -> bool {
+        let (index, bit) = facility.position();
+        self.data[index] & (1 << bit) != 0
+    }
+    pub fn set(&mut self, facility: TractorFacility, is_supported: bool) {
+        let (index, bit) = facility.position();
+        match is_supported {
+            true => self.data[index] |= 1 << bit,
+            false => self.data[index] &= !(1 << bit),
+        }
+    }
+
+    pub fn pgn(&self) -> ParameterGroupNumber {
+        ParameterGroupNumber::TractorFacilitiesResponse
+    }
+    pub fn as_bytes(&self) -> [u8; 8] {
+        self.data
+    }
+
+    /// Broadcast the message from the tractor ECU `from`.
+    pub fn send(&self, network_manager: &mut CanNetworkManager, from: ControlFunctionHandle) -> Result<(), SendError> {
+        network_manager.send_from(from, CanPriority::PriorityDefault6, self.pgn(), Address::GLOBAL, &self.as_bytes())
+    }
+}
+
+impl TryFrom<&CanMessage> for TractorFacilities {
+    type Error = PayloadError;
+
+    fn try_from(message: &CanMessage) -> Result<Self, Self::Error> {
+        if message.pgn() != ParameterGroupNumber::TractorFacilitiesResponse {
+            return Err(PayloadError::OutOfRange);
+        }
+        let data = check_length(message)?;
+        let mut facilities = Self::default();
+        facilities.data.copy_from_slice(&data[..8]);
+        Ok(facilities)
+    }
+}
+
+/// Keeps the latest hitch, PTO and auxiliary valve messages, a message older than the timeout is considered lost.
+///
+/// Implements use it for the tractor status, tractor ECUs for the commands of the implements.
+pub struct TractorFacilitiesListener {
+    timeout: Duration,
+    hitch_status: BTreeMap<Location, (HitchStatus, Duration)>,
+    hitch_commands: BTreeMap<Location, (HitchCommand, Duration)>,
+    pto_status: BTreeMap<Location, (PtoStatus, Duration)>,
+    pto_commands: BTreeMap<Location, (PtoCommand, Duration)>,
+    valve_estimated_flows: BTreeMap<u8, (AuxiliaryValveEstimatedFlow, Duration)>,
+    valve_measured_positions: BTreeMap<u8, (AuxiliaryValveMeasuredPosition, Duration)>,
+    valve_commands: BTreeMap<u8, (AuxiliaryValveCommand, Duration)>,
+    tractor_facilities: Option<(Address, TractorFacilities)>,   //< The last response, with the address of the tractor ECU
+}
+
+impl Default for TractorFacilitiesListener {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT)
+    }
+}
+
+impl TractorFacilitiesListener {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            hitch_status: BTreeMap::new(),
+            hitch_commands: BTreeMap::new(),
+            pto_status: BTreeMap::new(),
+            pto_commands: BTreeMap::new(),
+            valve_estimated_flows: BTreeMap::new(),
+            valve_measured_positions: BTreeMap::new(),
+            valve_commands: BTreeMap::new(),
+            tractor_facilities: None,
+        }
+    }
+
+    /// Process a received message, returns true if it was one of the tractor facilities messages.
+    pub fn process_can_message(&mut self, message: &CanMessage, now: Duration) -> bool {
+        let pgn = message.pgn();
+        let result = match pgn {
+            ParameterGroupNumber::FrontHitchStatus | ParameterGroupNumber::RearHitchStatus => HitchStatus::try_from(message)
+                .map(|status| { let _ = self.hitch_status.insert(status.location, (status, now)); }),
+            ParameterGroupNumber::FrontHitchCommand | ParameterGroupNumber::RearHitchCommand => HitchCommand::try_from(message)
+                .map(|command| { let _ = self.hitch_commands.insert(command.location, (command, now)); }),
+            ParameterGroupNumber::FrontPtoOutputShaft | ParameterGroupNumber::RearPtoOutputShaft => PtoStatus::try_from(message)
+                .map(|status| { let _ = self.pto_status.insert(status.location, (status, now)); }),
+            ParameterGroupNumber::FrontPtoCommand | ParameterGroupNumber::RearPtoCommand => PtoCommand::try_from(message)
+                .map(|command| { let _ = self.pto_commands.insert(command.location, (command, now)); }),
+            ParameterGroupNumber::TractorFacilitiesResponse => TractorFacilities::try_from(message)
+                .map(|facilities| self.tractor_facilities = Some((message.source_address(), facilities))),
+            _ if valve_of(pgn, ParameterGroupNumber::AuxiliaryValveEstimatedFlow).is_some() => {
+                AuxiliaryValveEstimatedFlow::try_from(message)
+                    .map(|flow| { let _ = self.valve_estimated_flows.insert(flow.valve, (flow, now)); })
+            }
+            _ if valve_of(pgn, ParameterGroupNumber::AuxiliaryValveMeasuredPosition).is_some() => {
+                AuxiliaryValveMeasuredPosition::try_from(message)
+                    .map(|position| { let _ = self.valve_measured_positions.insert(position.valve, (position, now)); })
+            }
+            _ if valve_of(pgn, ParameterGroupNumber::AuxiliaryValveCommand).is_some() => {
+                AuxiliaryValveCommand::try_from(message)
+                    .map(|command| { let _ = self.valve_commands.insert(command.valve, (command, now)); })
+            }
+            _ => return false,
+        };
+
+        if let Err(error) = result {
+            log::warn!("[TF]: Invalid {:?} from {}: {:?}", pgn, message.source_address(), error);
+        }
+        true
+    }
+
+    pub fn hitch_status(&self, location: Location, now: Duration) -> Option<HitchStatus> {
+        self.fresh(self.hitch_status.get(&location), now)
+    }
+    pub fn hitch_command(&self, location: Location, now: Duration) -> Option<HitchCommand> {
+        self.fresh(self.hitch_commands.get(&location), now)
+    }
+    pub fn pto_status(&self, location: Location, now: Duration) -> Option<PtoStatus> {
+        self.fresh(self.pto_status.get(&location), now)
+    }
+    pub fn pto_command(&self, location: Location, now: Duration) -> Option<PtoCommand> {
+        self.fresh(self.pto_commands.get(&location), now)
+    }
+    pub fn valve_estimated_flow(&self, valve: u8, now: Duration) -> Option<AuxiliaryValveEstimatedFlow> {
+        self.fresh(self.valve_estimated_flows.get(&valve), now)
+    }
+    pub fn valve_measured_position(&self, valve: u8, now: Duration) -> Option<AuxiliaryValveMeasuredPosition> {
+        self.fresh(self.valve_measured_positions.get(&valve), now)
+    }
+    pub fn valve_command(&self, valve: u8, now: Duration) -> Option<AuxiliaryValveCommand> {
+        self.fresh(self.valve_commands.get(&valve), now)
+    }
+
+    /// The last Tractor Facilities Response and the address of the tractor ECU that send it.
+    pub fn tractor_facilities(&self) -> Option<(Address, TractorFacilities)> {
+        self.tractor_facilities
+    }
+
+    fn fresh<T: Copy>(&self, received: Option<&(T, Duration)>, now: Duration) -> Option<T> {
+        received
+            .filter(|(_, timestamp)| now.saturating_sub(*timestamp) < self.timeout)
+            .map(|(value, _)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware_integration::CanDriver;
+    use crate::implement_messages::test_helpers::message;
+    use crate::name::Name;
+
+    #[test]
+    fn hitch_and_pto_encoding() {
+        let status = HitchStatus {
+            location: Location::Rear,
+            position: Some(125),
+            in_work: Some(true),
+            limit_status: Some(LimitStatus::NotLimited),
+            exit_reason_code: None,
+            nominal_lower_link_force: None,
+            draft: Some(32000),
+        };
+        let received = message(status.pgn(), &status.as_bytes());
+        assert_eq!(HitchStatus::try_from(&received), Ok(status));
+
+        let command = PtoCommand {
+            location: Location::Front,
+            speed_setpoint: Some(540 * 8),
+            engage: Some(true),
+            mode: Some(PtoMode::Rpm540),
+            economy_mode: Some(false),
+        };
+        assert_eq!(command.as_bytes()[2], 0b01000011);
+        let received = message(command.pgn(), &command.as_bytes());
+        assert_eq!(PtoCommand::try_from(&received), Ok(command));
+    }
+
+    #[test]
+    fn valves_are_indexed_by_pgn() {
+        let command = AuxiliaryValveCommand {
+            valve: 3,
+            flow: Some(250),
+            fail_safe_mode: Some(FailSafeMode::Block),
+            state: Some(ValveState::Extend),
+        };
+        assert_eq!(command.pgn(), Ok(ParameterGroupNumber::new(0xFE33)));
+
+        let mut listener = TractorFacilitiesListener::default();
+        assert!(listener.process_can_message(&message(command.pgn().unwrap(), &command.as_bytes()), Duration::from_millis(0)));
+        assert_eq!(listener.valve_command(3, Duration::from_millis(100)), Some(command));
+        assert_eq!(listener.valve_command(3, Duration::from_millis(300)), None);
+    }
+
+    #[test]
+    fn valve_out_of_range() {
+        let command = AuxiliaryValveCommand {
+            valve: NUMBER_OF_AUXILIARY_VALVES,
+            flow: Some(250),
+            fail_safe_mode: None,
+            state: Some(ValveState::Extend),
+        };
+        assert_eq!(command.pgn(), Err(PayloadError::OutOfRange));
+        assert_eq!(AuxiliaryValveEstimatedFlow {
+            valve: 0xFF,
+            extend_flow: None,
+            retract_flow: None,
+            fail_safe_mode: None,
+            state: None,
+            limit_status: None,
+            exit_reason_code: None,
+        }.pgn(), Err(PayloadError::OutOfRange));
+
+        // Nothing is send for an invalid valve, valve 16 would be the PGN of another message.
+        let driver = CanDriver::new();
+        let mut network_manager = CanNetworkManager::new(driver.clone());
+        let handle = network_manager.new_internal_control_function(Name::default(), Address(0x80));
+        assert_eq!(command.send(&mut network_manager, handle), Err(SendError::InvalidMessage));
+        assert!(driver.take_written_frames().is_empty());
+    }
+
+    #[test]
+    fn valve_measured_position_encoding() {
+        let position = AuxiliaryValveMeasuredPosition {
+            valve: 15,
+            position: Some(20000),
+            state: Some(ValveState::Retract),
+        };
+        assert_eq!(position.pgn(), Ok(ParameterGroupNumber::new(0xFE2F)));
+        assert_eq!(position.as_bytes(), [0x20, 0x4E, 0xF2, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let received = message(position.pgn().unwrap(), &position.as_bytes());
+        assert_eq!(AuxiliaryValveMeasuredPosition::try_from(&received), Ok(position));
+    }
+
+    #[test]
+    fn facilities() {
+        let mut facilities = TractorFacilities::new();
+        facilities.set(TractorFacility::RearHitchPosition, true);
+        facilities.set(TractorFacility::WheelBasedSpeed, true);
+        assert_eq!(facilities.as_bytes()[0], 0b10001000);
+        assert!(facilities.supports(TractorFacility::WheelBasedSpeed));
+        assert!(!facilities.supports(TractorFacility::GroundBasedSpeed));
+    }
+}
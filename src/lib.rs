@@ -1,4 +1,4 @@
-#![cfg_attr(not(std), no_std)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 #[cfg(feature = "std")]
 extern crate std;
@@ -14,7 +14,9 @@ pub use can_frame::CanFrame;
 // Export low level Isobus types
 mod can_message;
 pub use can_message::CanMessage;
-mod name;
+pub mod payload;
+pub use payload::{PayloadError, PayloadWriter};
+pub mod name;
 pub use name::{Name, NameBuilder, NameFilter};
 pub mod control_function;
 mod parameter_group_numbers;
@@ -22,6 +24,10 @@ pub use parameter_group_numbers::ParameterGroupNumber;
 
 pub mod hardware_integration;
 
+pub mod diagnostics;
+
+pub mod implement_messages;
+
 // TODO: Decide if object pool manipulation is needed in de base library
 // Should it work in no_std?
 mod object_pool;
@@ -32,13 +38,37 @@ pub mod virtual_terminal_client;
 pub use virtual_terminal_client::VirtualTerminalClient;
 
 mod can_network_manager;
-pub use can_network_manager::CanNetworkManager;
+pub use can_network_manager::{CanNetworkManager, RequestId, RequestResponse, RequestResult, SendError, SubscriptionId};
 
 mod protocol_managers;
 
-/// Defines all the CAN frame priorities that can be encoded in a frame ID
+/// The types of acknowldegement that can be sent in the Ack PGN
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum AcknowledgementType {
+    Positive = 0,       //< "ACK" Indicates that the request was completed
+    Negative = 1,       //< "NACK" Indicates the request was not completed or we do not support the PGN
+    AccessDenied = 2,   //< Signals to the requestor that their CF is not allowed to request this PGN
+    CannotRespond = 3,  //< Signals to the requestor that we are unable to accept the request for some reason
+}
+
+impl TryFrom<u8> for AcknowledgementType {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Positive),
+            1 => Ok(Self::Negative),
+            2 => Ok(Self::AccessDenied),
+            3 => Ok(Self::CannotRespond),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Defines all the CAN frame priorities that can be encoded in a frame ID
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 pub enum CanPriority {
     PriorityHighest0 = 0, //< Highest CAN priority
     Priority1 = 1,        //< Priority highest - 1
@@ -46,14 +76,10 @@ pub enum CanPriority {
     Priority3 = 3,        //< Priority highest - 3 (Control messages priority)
     Priority4 = 4,        //< Priority highest - 4
     Priority5 = 5,        //< Priority highest - 5
+    #[default]
     PriorityDefault6 = 6, //< The default priority
     PriorityLowest7 = 7,  //< The lowest priority
 }
-impl Default for CanPriority {
-    fn default() -> Self {
-        Self::PriorityDefault6
-    }
-}
 impl core::fmt::Display for CanPriority {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", *self as u8)
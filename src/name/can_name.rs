@@ -236,7 +236,7 @@ mod tests {
     #[test]
     fn name_arbitrary_address_capable() {
         let name = Name::from(0b1000111100000000111111110000011100000000000111111111111111111111);
-        assert_eq!(name.arbitrary_address_capable(), true);
+        assert!(name.arbitrary_address_capable());
     }
 
     #[test]
@@ -10,9 +10,10 @@ use super::IndustryGroup;
 /// assert_eq!(device_class, Into::<DeviceClass>::into((5, Some(IndustryGroup::AgriculturalAndForestryEquipment))));
 /// assert_eq!(Into::<u8>::into(device_class), 5);
 /// ```
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 pub enum DeviceClass {
     // Shared
+    #[default]
     NotAvailable,
     NonSpecificSystem(IndustryGroup),
     Tractor(IndustryGroup),
@@ -82,12 +83,6 @@ pub enum DeviceClass {
     IndustrialProcessControlStationary,
 }
 
-impl Default for DeviceClass {
-    fn default() -> Self {
-        DeviceClass::NotAvailable
-    }
-}
-
 /// Display the Device Class name.
 /// ```rust
 /// use agisostack::name::DeviceClass;
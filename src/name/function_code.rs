@@ -1,9 +1,10 @@
 // TODO: Rewrite like the device class.
 
 /// Enum containing all Function IDs.
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 pub enum FunctionCode {
     // Shared
+    #[default]
     NotAvailable,
 
     // On Highway Equipment
@@ -13,12 +14,6 @@ pub enum FunctionCode {
     VirtualTerminal,
 }
 
-impl Default for FunctionCode {
-    fn default() -> Self {
-        FunctionCode::NotAvailable
-    }
-}
-
 impl core::fmt::Display for FunctionCode {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?}", self)
@@ -7,8 +7,9 @@
 /// assert_eq!(industry_group, Into::<IndustryGroup>::into(2));
 /// assert_eq!(Into::<u8>::into(industry_group), 2);
 /// ```
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 pub enum IndustryGroup {
+    #[default]
     Global = 0,
     OnHighwayEquipment = 1,
     AgriculturalAndForestryEquipment = 2,
@@ -19,12 +20,6 @@ pub enum IndustryGroup {
     ReservedForSAE2 = 7,
 }
 
-impl Default for IndustryGroup {
-    fn default() -> Self {
-        IndustryGroup::Global
-    }
-}
-
 /// Display the Industry Group name.
 /// ```rust
 /// use agisostack::name::IndustryGroup;
@@ -1,6 +1,6 @@
-mod name;
-pub use name::Name;
-pub use name::NameBuilder;
+mod can_name;
+pub use can_name::Name;
+pub use can_name::NameBuilder;
 
 mod name_filter;
 pub use name_filter::NameFilter;
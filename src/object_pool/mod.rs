@@ -5,8 +5,8 @@ use alloc::{string::String, vec::Vec};
 
 use crate::name::Name;
 
-mod object_pool;
-pub use object_pool::ObjectPool;
+mod pool;
+pub use pool::ObjectPool;
 
 pub enum ParseError {
     DataEmpty,
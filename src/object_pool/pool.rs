@@ -2,8 +2,6 @@ use core::cell::Cell;
 
 use alloc::vec::Vec;
 
-use crate::virtual_terminal_client::VTVersion;
-
 use super::*;
 
 #[derive(Debug)]
@@ -11,7 +9,6 @@ pub struct ObjectPool {
     objects: Vec<Object>,
     colour_map: [u8; 256],
     colour_palette: [Colour; 256],
-    supported_vt_version: VTVersion,
 
     size_cache: Cell<Option<usize>>,
 }
@@ -28,7 +25,6 @@ impl ObjectPool {
             objects: Vec::new(),
             colour_map,
             colour_palette: Colour::COLOUR_PALETTE,
-            supported_vt_version: VTVersion::default(),
 
             size_cache: Cell::new(None),
         }
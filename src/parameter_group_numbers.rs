@@ -1,55 +1,75 @@
-/// PGNs commonly used by the CAN stack.
-#[repr(u32)]
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
-pub enum ParameterGroupNumber {
-    Any = 0x000000,
-    AgriculturalGuidanceMachineInfo = 0x00AC00,
-    AgriculturalGuidanceSystemCommand = 0x00AD00,
-    DiagnosticMessage22 = 0x00C300,
-    ExtendedTransportProtocolDataTransfer = 0x00C700,
-    ExtendedTransportProtocolConnectionManagement = 0x00C800,
-    ProcessData = 0x00CB00,
-    RequestForRepetitionRate = 0x00CC00,
-    DiagnosticMessage13 = 0x00DF00,
-    VirtualTerminalToECU = 0x00E600,
-    ECUtoVirtualTerminal = 0x00E700,
-    Acknowledge = 0x00E800,
-    ParameterGroupNumberRequest = 0x00EA00,
-    TransportProtocolData = 0x00EB00,
-    TransportProtocolCommand = 0x00EC00,
-    AddressClaim = 0x00EE00,
-    ProprietaryA = 0x00EF00,
-    MachineSelectedSpeed = 0x00F022,
-    ProductIdentification = 0x00FC8D,
-    ControlFunctionFunctionalities = 0x00FC8E,
-    DiagnosticProtocolIdentification = 0x00FD32,
-    MachineSelectedSpeedCommand = 0x00FD43,
-    WorkingSetMaster = 0x00FE0D,
-    LanguageCommand = 0x00FE0F,
-    MaintainPower = 0x00FE47,
-    WheelBasedSpeedAndDistance = 0x00FE48,
-    GroundBasedSpeedAndDistance = 0x00FE49,
-    ECUIdentificationInformation = 0x00FDC5,
-    DiagnosticMessage1 = 0x00FECA,
-    DiagnosticMessage2 = 0x00FECB,
-    DiagnosticMessage3 = 0x00FECC,
-    DiagnosticMessage11 = 0x00FED3,
-    CommandedAddress = 0x00FED8,
-    SoftwareIdentification = 0x00FEDA,
-    AllImplementsStopOperationsSwitchState = 0x00FD02,
+/// An 18-bit Parameter Group Number (PGN).
+///
+/// Any PGN can be represented, the PGNs commonly used by the CAN stack are available as constants.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ParameterGroupNumber(u32);
+
+#[allow(non_upper_case_globals)]
+impl ParameterGroupNumber {
+    pub const Any: Self = Self(0x000000);
+    pub const AgriculturalGuidanceMachineInfo: Self = Self(0x00AC00);
+    pub const AgriculturalGuidanceSystemCommand: Self = Self(0x00AD00);
+    pub const DiagnosticMessage22: Self = Self(0x00C300);
+    pub const ExtendedTransportProtocolDataTransfer: Self = Self(0x00C700);
+    pub const ExtendedTransportProtocolConnectionManagement: Self = Self(0x00C800);
+    pub const ProcessData: Self = Self(0x00CB00);
+    pub const RequestForRepetitionRate: Self = Self(0x00CC00);
+    pub const DiagnosticMessage13: Self = Self(0x00DF00);
+    pub const VirtualTerminalToECU: Self = Self(0x00E600);
+    pub const ECUtoVirtualTerminal: Self = Self(0x00E700);
+    pub const Acknowledge: Self = Self(0x00E800);
+    pub const ParameterGroupNumberRequest: Self = Self(0x00EA00);
+    pub const TransportProtocolData: Self = Self(0x00EB00);
+    pub const TransportProtocolCommand: Self = Self(0x00EC00);
+    pub const AddressClaim: Self = Self(0x00EE00);
+    pub const ProprietaryA: Self = Self(0x00EF00);
+    pub const MachineSelectedSpeed: Self = Self(0x00F022);
+    pub const ProductIdentification: Self = Self(0x00FC8D);
+    pub const ControlFunctionFunctionalities: Self = Self(0x00FC8E);
+    pub const AllImplementsStopOperationsSwitchState: Self = Self(0x00FD02);
+    pub const DiagnosticProtocolIdentification: Self = Self(0x00FD32);
+    pub const MachineSelectedSpeedCommand: Self = Self(0x00FD43);
+    pub const ECUIdentificationInformation: Self = Self(0x00FDC5);
+    pub const TractorFacilitiesResponse: Self = Self(0x00FE09);
+    pub const WorkingSetMaster: Self = Self(0x00FE0D);
+    pub const LanguageCommand: Self = Self(0x00FE0F);
+    pub const AuxiliaryValveEstimatedFlow: Self = Self(0x00FE10);     //< Valve 0, valves 1 to 15 follow
+    pub const AuxiliaryValveMeasuredPosition: Self = Self(0x00FE20);  //< Valve 0, valves 1 to 15 follow
+    pub const AuxiliaryValveCommand: Self = Self(0x00FE30);           //< Valve 0, valves 1 to 15 follow
+    pub const RearPtoOutputShaft: Self = Self(0x00FE43);
+    pub const FrontPtoOutputShaft: Self = Self(0x00FE44);
+    pub const RearHitchStatus: Self = Self(0x00FE45);
+    pub const FrontHitchStatus: Self = Self(0x00FE46);
+    pub const MaintainPower: Self = Self(0x00FE47);
+    pub const WheelBasedSpeedAndDistance: Self = Self(0x00FE48);
+    pub const GroundBasedSpeedAndDistance: Self = Self(0x00FE49);
+    pub const RearPtoCommand: Self = Self(0x00FE57);
+    pub const FrontPtoCommand: Self = Self(0x00FE58);
+    pub const RearHitchCommand: Self = Self(0x00FE59);
+    pub const FrontHitchCommand: Self = Self(0x00FE5A);
+    pub const DiagnosticMessage1: Self = Self(0x00FECA);
+    pub const DiagnosticMessage2: Self = Self(0x00FECB);
+    pub const DiagnosticMessage3: Self = Self(0x00FECC);
+    pub const DiagnosticMessage11: Self = Self(0x00FED3);
+    pub const CommandedAddress: Self = Self(0x00FED8);
+    pub const SoftwareIdentification: Self = Self(0x00FEDA);
+    pub const ProprietaryB: Self = Self(0x00FF00);  //< The first of the 256 proprietary B PGNs
+    pub const ProprietaryA2: Self = Self(0x01EF00);
 }
 
 impl ParameterGroupNumber {
-    pub fn new(value: u32) -> Self {
-        value.into()
+    pub const MAX: u32 = 0x03FFFF;
+
+    pub const fn new(value: u32) -> Self {
+        Self(value & Self::MAX)
     }
 
-    pub fn from_le_bytes(bytes: &[u8]) -> Self {
+    pub fn from_le_bytes(bytes: [u8; 3]) -> Self {
         Self::new(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
     }
 
     pub fn as_u32(&self) -> u32 {
-        *self as u32
+        self.0
     }
 
     pub fn as_bytes(&self) -> [u8; 3] {
@@ -75,11 +95,78 @@ impl ParameterGroupNumber {
     pub fn is_pdu2(&self) -> bool {
         self.pdu_format() >= 240
     }
+    pub fn is_proprietary_b(&self) -> bool {
+        !self.extended_data_page() && !self.data_page() && self.pdu_format() == 0xFF
+    }
+
+    /// The name of the PGN, if it is one of the known PGNs.
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Self::AgriculturalGuidanceMachineInfo => Some("AgriculturalGuidanceMachineInfo"),
+            Self::AgriculturalGuidanceSystemCommand => Some("AgriculturalGuidanceSystemCommand"),
+            Self::DiagnosticMessage22 => Some("DiagnosticMessage22"),
+            Self::ExtendedTransportProtocolDataTransfer => Some("ExtendedTransportProtocolDataTransfer"),
+            Self::ExtendedTransportProtocolConnectionManagement => Some("ExtendedTransportProtocolConnectionManagement"),
+            Self::ProcessData => Some("ProcessData"),
+            Self::RequestForRepetitionRate => Some("RequestForRepetitionRate"),
+            Self::DiagnosticMessage13 => Some("DiagnosticMessage13"),
+            Self::VirtualTerminalToECU => Some("VirtualTerminalToECU"),
+            Self::ECUtoVirtualTerminal => Some("ECUtoVirtualTerminal"),
+            Self::Acknowledge => Some("Acknowledge"),
+            Self::ParameterGroupNumberRequest => Some("ParameterGroupNumberRequest"),
+            Self::TransportProtocolData => Some("TransportProtocolData"),
+            Self::TransportProtocolCommand => Some("TransportProtocolCommand"),
+            Self::AddressClaim => Some("AddressClaim"),
+            Self::ProprietaryA => Some("ProprietaryA"),
+            Self::MachineSelectedSpeed => Some("MachineSelectedSpeed"),
+            Self::ProductIdentification => Some("ProductIdentification"),
+            Self::ControlFunctionFunctionalities => Some("ControlFunctionFunctionalities"),
+            Self::AllImplementsStopOperationsSwitchState => Some("AllImplementsStopOperationsSwitchState"),
+            Self::DiagnosticProtocolIdentification => Some("DiagnosticProtocolIdentification"),
+            Self::MachineSelectedSpeedCommand => Some("MachineSelectedSpeedCommand"),
+            Self::ECUIdentificationInformation => Some("ECUIdentificationInformation"),
+            Self::TractorFacilitiesResponse => Some("TractorFacilitiesResponse"),
+            Self::WorkingSetMaster => Some("WorkingSetMaster"),
+            Self::LanguageCommand => Some("LanguageCommand"),
+            Self::AuxiliaryValveEstimatedFlow => Some("AuxiliaryValveEstimatedFlow"),
+            Self::AuxiliaryValveMeasuredPosition => Some("AuxiliaryValveMeasuredPosition"),
+            Self::AuxiliaryValveCommand => Some("AuxiliaryValveCommand"),
+            Self::RearPtoOutputShaft => Some("RearPtoOutputShaft"),
+            Self::FrontPtoOutputShaft => Some("FrontPtoOutputShaft"),
+            Self::RearHitchStatus => Some("RearHitchStatus"),
+            Self::FrontHitchStatus => Some("FrontHitchStatus"),
+            Self::MaintainPower => Some("MaintainPower"),
+            Self::WheelBasedSpeedAndDistance => Some("WheelBasedSpeedAndDistance"),
+            Self::GroundBasedSpeedAndDistance => Some("GroundBasedSpeedAndDistance"),
+            Self::RearPtoCommand => Some("RearPtoCommand"),
+            Self::FrontPtoCommand => Some("FrontPtoCommand"),
+            Self::RearHitchCommand => Some("RearHitchCommand"),
+            Self::FrontHitchCommand => Some("FrontHitchCommand"),
+            Self::DiagnosticMessage1 => Some("DiagnosticMessage1"),
+            Self::DiagnosticMessage2 => Some("DiagnosticMessage2"),
+            Self::DiagnosticMessage3 => Some("DiagnosticMessage3"),
+            Self::DiagnosticMessage11 => Some("DiagnosticMessage11"),
+            Self::CommandedAddress => Some("CommandedAddress"),
+            Self::SoftwareIdentification => Some("SoftwareIdentification"),
+            Self::ProprietaryA2 => Some("ProprietaryA2"),
+            _ if self.is_proprietary_b() => Some("ProprietaryB"),
+            _ => None,
+        }
+    }
 }
 
-impl Default for ParameterGroupNumber {
-    fn default() -> Self {
-        Self::Any
+impl core::fmt::Debug for ParameterGroupNumber {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "0x{:05X}", self.0),
+        }
+    }
+}
+
+impl core::fmt::Display for ParameterGroupNumber {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "0x{:05X}", self.0)
     }
 }
 
@@ -101,43 +188,13 @@ impl From<&[u8]> for ParameterGroupNumber {
 
 impl From<u32> for ParameterGroupNumber {
     fn from(val: u32) -> Self {
-        match val {
-            0x00AC00 => Self::AgriculturalGuidanceMachineInfo,
-            0x00AD00 => Self::AgriculturalGuidanceSystemCommand,
-            0x00C300 => Self::DiagnosticMessage22,
-            0x00C700 => Self::ExtendedTransportProtocolDataTransfer,
-            0x00C800 => Self::ExtendedTransportProtocolConnectionManagement,
-            0x00CB00 => Self::ProcessData,
-            0x00CC00 => Self::RequestForRepetitionRate,
-            0x00DF00 => Self::DiagnosticMessage13,
-            0x00E600 => Self::VirtualTerminalToECU,
-            0x00E700 => Self::ECUtoVirtualTerminal,
-            0x00E800 => Self::Acknowledge,
-            0x00EA00 => Self::ParameterGroupNumberRequest,
-            0x00EB00 => Self::TransportProtocolData,
-            0x00EC00 => Self::TransportProtocolCommand,
-            0x00EE00 => Self::AddressClaim,
-            0x00EF00 => Self::ProprietaryA,
-            0x00F022 => Self::MachineSelectedSpeed,
-            0x00FC8D => Self::ProductIdentification,
-            0x00FC8E => Self::ControlFunctionFunctionalities,
-            0x00FD32 => Self::DiagnosticProtocolIdentification,
-            0x00FD43 => Self::MachineSelectedSpeedCommand,
-            0x00FE0D => Self::WorkingSetMaster,
-            0x00FE0F => Self::LanguageCommand,
-            0x00FE47 => Self::MaintainPower,
-            0x00FE48 => Self::WheelBasedSpeedAndDistance,
-            0x00FE49 => Self::GroundBasedSpeedAndDistance,
-            0x00FDC5 => Self::ECUIdentificationInformation,
-            0x00FECA => Self::DiagnosticMessage1,
-            0x00FECB => Self::DiagnosticMessage2,
-            0x00FECC => Self::DiagnosticMessage3,
-            0x00FED3 => Self::DiagnosticMessage11,
-            0x00FED8 => Self::CommandedAddress,
-            0x00FEDA => Self::SoftwareIdentification,
-            0x00FD02 => Self::AllImplementsStopOperationsSwitchState,
-            _ => Self::Any,
-        }
+        Self::new(val)
+    }
+}
+
+impl From<ParameterGroupNumber> for u32 {
+    fn from(val: ParameterGroupNumber) -> Self {
+        val.as_u32()
     }
 }
 
@@ -146,3 +203,41 @@ impl From<ParameterGroupNumber> for [u8; 3] {
         val.as_bytes()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_unknown_pgns() {
+        let pgn = ParameterGroupNumber::from(0x01FF42u32);
+        assert_eq!(pgn.as_u32(), 0x01FF42);
+        assert!(pgn.data_page());
+        assert_eq!(pgn.name(), None);
+    }
+
+    #[test]
+    fn masks_to_18_bits() {
+        assert_eq!(ParameterGroupNumber::new(0xFFFFFFFF).as_u32(), 0x03FFFF);
+    }
+
+    #[test]
+    fn known_pgns_match_constants() {
+        assert_eq!(ParameterGroupNumber::from(0x00EE00u32), ParameterGroupNumber::AddressClaim);
+        assert_eq!(ParameterGroupNumber::AddressClaim.name(), Some("AddressClaim"));
+        assert!(ParameterGroupNumber::new(0x00FF12).is_proprietary_b());
+    }
+
+    #[test]
+    fn proprietary_b_range() {
+        assert_eq!(ParameterGroupNumber::new(0x00FF00).name(), Some("ProprietaryB"));
+        assert_eq!(ParameterGroupNumber::new(0x00FFFF).name(), Some("ProprietaryB"));
+        assert_eq!(ParameterGroupNumber::new(0x01FFFF).name(), None);
+    }
+
+    #[test]
+    fn from_le_bytes() {
+        assert_eq!(ParameterGroupNumber::from_le_bytes([0x00, 0xEE, 0x00]), ParameterGroupNumber::AddressClaim);
+        assert_eq!(ParameterGroupNumber::from_le_bytes([0xFF, 0xFF, 0xFF]).as_u32(), 0x03FFFF);
+    }
+}
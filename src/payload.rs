@@ -0,0 +1,227 @@
+use alloc::vec::Vec;
+
+use crate::{name::Name, ParameterGroupNumber};
+
+/// Errors returned by the checked `CanMessage` accessors.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum PayloadError {
+    OutOfRange,     //< The message is too short for the requested value
+    Reserved,       //< The value is in the range J1939 reserves for parameter specific indicators
+    Error,          //< The value holds the J1939 "error" indicator
+    NotAvailable,   //< The value holds the J1939 "not available" indicator
+}
+
+/// Check a 1 byte J1939 parameter, valid values are 0x00..=0xFA.
+pub fn check_j1939_u8(value: u8) -> Result<u8, PayloadError> {
+    match value {
+        0x00..=0xFA => Ok(value),
+        0xFB..=0xFD => Err(PayloadError::Reserved),
+        0xFE => Err(PayloadError::Error),
+        0xFF => Err(PayloadError::NotAvailable),
+    }
+}
+
+/// Check a 2 byte J1939 parameter, valid values are 0x0000..=0xFAFF.
+pub fn check_j1939_u16(value: u16) -> Result<u16, PayloadError> {
+    match value >> 8 {
+        0x00..=0xFA => Ok(value),
+        0xFB..=0xFD => Err(PayloadError::Reserved),
+        0xFE => Err(PayloadError::Error),
+        _ => Err(PayloadError::NotAvailable),
+    }
+}
+
+/// Check a 4 byte J1939 parameter, valid values are 0x00000000..=0xFAFFFFFF.
+pub fn check_j1939_u32(value: u32) -> Result<u32, PayloadError> {
+    match value >> 24 {
+        0x00..=0xFA => Ok(value),
+        0xFB..=0xFD => Err(PayloadError::Reserved),
+        0xFE => Err(PayloadError::Error),
+        _ => Err(PayloadError::NotAvailable),
+    }
+}
+
+/// Check a discrete J1939 parameter of `length` bits, the two highest values are the error and not available indicators.
+pub fn check_j1939_bits(value: u8, length: u8) -> Result<u8, PayloadError> {
+    let not_available = ((1u16 << length) - 1) as u8;
+    match value {
+        _ if length < 2 => Ok(value),
+        _ if value == not_available => Err(PayloadError::NotAvailable),
+        _ if value == not_available - 1 => Err(PayloadError::Error),
+        _ => Ok(value),
+    }
+}
+
+/// Writes the little endian payload of a `CanMessage`.
+///
+/// Unused bytes and bits are set to 0xFF, as required by J1939.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub struct PayloadWriter {
+    data: Vec<u8>,
+}
+
+impl PayloadWriter {
+    pub fn new() -> PayloadWriter {
+        PayloadWriter::default()
+    }
+
+    /// Create a writer with `length` bytes set to 0xFF, to be filled in using the `_at` methods.
+    pub fn with_length(length: usize) -> PayloadWriter {
+        PayloadWriter {
+            data: alloc::vec![0xFF; length],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn build(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(value);
+        self
+    }
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.bytes(&[value])
+    }
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+    pub fn u24(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_le_bytes()[..3])
+    }
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+    pub fn i8(&mut self, value: i8) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+    pub fn i16(&mut self, value: i16) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+    pub fn i32(&mut self, value: i32) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+    pub fn pgn(&mut self, value: ParameterGroupNumber) -> &mut Self {
+        self.bytes(&value.as_bytes())
+    }
+    pub fn name(&mut self, value: Name) -> &mut Self {
+        self.bytes(&<[u8; 8]>::from(value))
+    }
+
+    /// Append `length` bytes holding the J1939 "not available" indicator.
+    pub fn not_available(&mut self, length: usize) -> &mut Self {
+        self.data.resize(self.data.len() + length, 0xFF);
+        self
+    }
+
+    /// Pad the payload with 0xFF up to `length` bytes, CAN frames are usually padded to 8 bytes.
+    pub fn pad_to(&mut self, length: usize) -> &mut Self {
+        if self.data.len() < length {
+            self.data.resize(length, 0xFF);
+        }
+        self
+    }
+
+    /// Write the lower `length` bits of `value` at bit `bit` of byte `index`, growing the payload if needed.
+    ///
+    /// The bits may not cross a byte boundary, like `CanMessage::try_bits_at`.
+    pub fn bits_at(&mut self, index: usize, bit: u8, length: u8, value: u8) -> Result<&mut Self, PayloadError> {
+        if length == 0 || bit as u16 + length as u16 > 8 {
+            return Err(PayloadError::OutOfRange);
+        }
+        self.pad_to(index + 1);
+
+        let value = value & ((1u16 << length) - 1) as u8;
+        let mask = (((1u16 << length) - 1) << bit) as u8;
+        self.data[index] = (self.data[index] & !mask) | (value << bit);
+        Ok(self)
+    }
+    pub fn bool_at(&mut self, index: usize, bit: u8, value: bool) -> Result<&mut Self, PayloadError> {
+        self.bits_at(index, bit, 1, value as u8)
+    }
+    pub fn u8_at(&mut self, index: usize, value: u8) -> &mut Self {
+        self.bytes_at(index, &[value])
+    }
+    pub fn u16_at(&mut self, index: usize, value: u16) -> &mut Self {
+        self.bytes_at(index, &value.to_le_bytes())
+    }
+    pub fn u32_at(&mut self, index: usize, value: u32) -> &mut Self {
+        self.bytes_at(index, &value.to_le_bytes())
+    }
+    pub fn bytes_at(&mut self, index: usize, value: &[u8]) -> &mut Self {
+        self.pad_to(index + value.len());
+        self.data[index..index + value.len()].copy_from_slice(value);
+        self
+    }
+}
+
+impl From<PayloadWriter> for Vec<u8> {
+    fn from(value: PayloadWriter) -> Self {
+        value.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn j1939_ranges() {
+        assert_eq!(check_j1939_u8(0xFA), Ok(0xFA));
+        assert_eq!(check_j1939_u8(0xFB), Err(PayloadError::Reserved));
+        assert_eq!(check_j1939_u8(0xFE), Err(PayloadError::Error));
+        assert_eq!(check_j1939_u8(0xFF), Err(PayloadError::NotAvailable));
+        assert_eq!(check_j1939_u16(0xFAFF), Ok(0xFAFF));
+        assert_eq!(check_j1939_u16(0xFE12), Err(PayloadError::Error));
+        assert_eq!(check_j1939_u32(0xFF000000), Err(PayloadError::NotAvailable));
+        assert_eq!(check_j1939_bits(0b01, 2), Ok(0b01));
+        assert_eq!(check_j1939_bits(0b10, 2), Err(PayloadError::Error));
+        assert_eq!(check_j1939_bits(0b11, 2), Err(PayloadError::NotAvailable));
+    }
+
+    #[test]
+    fn writer() {
+        let data = PayloadWriter::new()
+            .u8(0x01)
+            .u16(0x0302)
+            .i8(-1)
+            .pad_to(8)
+            .bits_at(7, 2, 2, 0b01).unwrap()
+            .build();
+
+        assert_eq!(data, [0x01, 0x02, 0x03, 0xFF, 0xFF, 0xFF, 0xFF, 0b11110111]);
+    }
+
+    #[test]
+    fn writer_masks_bits() {
+        let data = PayloadWriter::new()
+            .bits_at(0, 2, 2, 0xFF).unwrap()
+            .bits_at(0, 6, 2, 0b100).unwrap()
+            .bits_at(1, 0, 8, 0x5A).unwrap()
+            .bool_at(2, 7, false).unwrap()
+            .build();
+
+        assert_eq!(data, [0b00111111, 0x5A, 0b01111111]);
+    }
+
+    #[test]
+    fn writer_rejects_bits_outside_the_byte() {
+        let mut writer = PayloadWriter::new();
+        assert!(writer.bits_at(0, 6, 4, 0).is_err());
+        assert!(writer.bits_at(0, 0, 0, 0).is_err());
+        assert!(writer.bool_at(0, 8, true).is_err());
+        assert!(writer.is_empty());
+    }
+}
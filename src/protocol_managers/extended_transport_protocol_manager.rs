@@ -1,13 +1,12 @@
-
 use core::time::Duration;
 
 use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 
-use crate::can_message::CanMessageBuilder;
-use crate::hardware_integration::{TimeDriver, TimeDriverTrait, CanDriverTrait};
-use crate::{CanMessage, CanNetworkManager, ParameterGroupNumber, CanPriority, Address};
+use crate::hardware_integration::{TimeDriver, TimeDriverTrait};
+use crate::{Address, CanMessage, CanNetworkManager, CanPriority, ParameterGroupNumber};
 
+use super::{number_of_packets_for, Direction, SessionKey, SessionTable, TransportSession, BYTES_PER_PACKET};
 
 const ETP_TIMEOUT_T1: Duration = Duration::from_millis(750);
 const ETP_TIMEOUT_T2: Duration = Duration::from_millis(1250);
@@ -15,22 +14,19 @@ const ETP_TIMEOUT_T3: Duration = Duration::from_millis(1250);
 const ETP_TIMEOUT_T4: Duration = Duration::from_millis(1050);
 
 const MAX_NUMBER_OF_PACKETS_TO_SEND: u8 = 32;
-
+const MAX_NUMBER_OF_RETRANSMITS: u8 = 2;
+const MIN_MESSAGE_SIZE: usize = 1786;
+const MAX_MESSAGE_SIZE: usize = 117_440_505;
+const DEFAULT_MAX_RECEIVE_MESSAGE_SIZE: usize = 65_536;
+
+/// Handles ISO11783-3 Extended Transport Protocol (ETP) sessions.
+///
+/// Messages of 1786 up to 117.440.505 bytes are send using connection mode data transfer,
+/// where every window of packets is preceded by a data packet offset (DPO) message.
 pub struct ExtendedTransportProtocolManager {
-    current_state: State,
-    message_backlog: VecDeque<CanMessage>,
-    // message_to_send: Option<CanMessage>,
-    next_timeout: Duration,
-
-    receiving_message_builder: CanMessageBuilder,
-    receiving_data_buffer: Vec<u8>,
-    receiving_number_of_packets: u8,
-    // receiving_next_packet_number: u32,
-
-    // receiving_source_address: Option<Address>,
-    // receiving_destination_address: Option<Address>,
-    // receiving_pgn: Option<ParameterGroupNumber>,
-    // receiving_data_buffer: Vec<u8>,
+    sessions: SessionTable<Session>,
+    max_receive_message_size: usize,    //< Larger messages are refused, the buffer is allocated when the session is accepted
+    aborts_to_send: VecDeque<CanMessage>,
 }
 
 impl ExtendedTransportProtocolManager {
@@ -38,374 +34,512 @@ impl ExtendedTransportProtocolManager {
         Self::default()
     }
 
+    /// Sets the maximum number of transmit and receive sessions that can run at the same time.
+    pub fn set_max_sessions(&mut self, max_sessions: usize) {
+        self.sessions.set_max_sessions(max_sessions);
+    }
+
+    /// Sets the size of the largest message we accept, requests to send larger messages are aborted.
+    pub fn set_max_receive_message_size(&mut self, max_message_size: usize) {
+        self.max_receive_message_size = max_message_size;
+    }
+
     pub fn send(&mut self, message: CanMessage) {
-        // Start a new sending connection or if we are already in a connection, store the message in the backlog.
-        if State::WaitingForRequestToSend == self.current_state {
+        if !(MIN_MESSAGE_SIZE..=MAX_MESSAGE_SIZE).contains(&message.len()) {
+            log::error!("[ETP]: Can not send a message of {} bytes", message.len());
+            return;
+        }
+        if message.destination_address() == Address::GLOBAL {
+            log::error!("[ETP]: Can not send a message to global");
+            return;
+        }
+
+        if let Some(message) = self.sessions.admit(message) {
             self.open_connection(message);
-        } else {
-            self.message_backlog.push_back(message);
         }
     }
 
-    pub fn update(&mut self, network_manager: &mut CanNetworkManager) -> Option<CanMessage> {
-        let mut result = None;
+    /// Sends all pending control and data messages and checks the session timeouts.
+    pub fn update(&mut self, network_manager: &mut CanNetworkManager) {
+        while let Some(message) = self.aborts_to_send.pop_front() {
+            network_manager.send_can_message(message);
+        }
+
+        self.sessions.retain_mut(|session| session.update(network_manager));
+
+        // Start sending backlogged messages once their source and destination pair is free again.
+        while let Some(message) = self.sessions.next_from_backlog() {
+            self.open_connection(message);
+        }
+    }
 
-        match self.current_state {
-            State::WaitingForRequestToSend => {
-                if let Some(message) = self.message_backlog.pop_front() {
-                    self.open_connection(message);
+    /// Processes an ETP connection management or data transfer message.
+    ///
+    /// Returns the reassembled message once all the data of a session is received.
+    pub fn process_can_message(&mut self, message: &CanMessage) -> Option<CanMessage> {
+        match message.pgn() {
+            ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement => {
+                if let Ok(control_byte) = message.get_u8_at(0).try_into() {
+                    match control_byte {
+                        EtpCmControlByte::RequestToSend => self.process_request_to_send(message),
+                        EtpCmControlByte::ClearToSend => self.process_clear_to_send(message),
+                        EtpCmControlByte::DataPacketOffset => self.process_data_packet_offset(message),
+                        EtpCmControlByte::EndOfMessageAcknowledgement => {
+                            if let Some(index) = self.sessions.transmit_session_index(message) {
+                                let session = &mut self.sessions[index];
+                                if session.key.pgn != message.get_pgn_at(5)
+                                    || message.get_u32_at(1) as usize != session.message_size
+                                {
+                                    session.state = State::SendConnectionAbort(AbortReason::Other);
+                                } else {
+                                    log::debug!("[ETP]: Send {} bytes to {}", session.message_size, message.source_address());
+                                    self.sessions.remove(index);
+                                }
+                            }
+                        }
+                        EtpCmControlByte::ConnectionAbort => {
+                            let reason = AbortReason::from(message.get_u8_at(1));
+                            let pgn = message.get_pgn_at(5);
+                            if let Some(index) = self.sessions.transmit_session_index(message)
+                                .filter(|&index| self.sessions[index].key.pgn == pgn)
+                            {
+                                log::error!("[ETP]: Transmit session aborted by {}: {:?}", message.source_address(), reason);
+                                self.sessions.remove(index);
+                            } else if let Some(index) = self.sessions.receive_session_index(message)
+                                .filter(|&index| self.sessions[index].key.pgn == pgn)
+                            {
+                                log::error!("[ETP]: Receive session aborted by {}: {:?}", message.source_address(), reason);
+                                self.sessions.remove(index);
+                            }
+                        }
+                    }
                 }
+                None
             }
-            State::SendRequestToSend(message_to_send) => {
-                let mut data: [u8; 8] = [0xFF; 8];
-                data[0] = EtpCmControlByte::RequestToSend as u8;
-                data[1..=4].copy_from_slice(&(message_to_send.len() as u32).to_le_bytes());
-                data[5..=7].copy_from_slice(&message_to_send.pgn().as_bytes());
-    
-                network_manager.send_can_message(CanMessage::new(
-                    CanPriority::PriorityLowest7,
-                    ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
-                    message_to_send.source_address(),
-                    message_to_send.destination_address(),
-                    &data,
-                ));
-    
-                self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T3;
-                self.set_state(State::WaitForClearToSend(message_to_send));
-            }
-            State::WaitForClearToSend(_) => {
-                if TimeDriver::time_elapsed() > self.next_timeout {
-					log::error!("[ETP]: Wait For Clear To Send Timeout");
-                    self.set_state(State::SendConnectionAbort(AbortReason::Timeout));
-				}
-            }
-            State::SendClearToSend => {
-                let message = self.receiving_message_builder.build();
+            ParameterGroupNumber::ExtendedTransportProtocolDataTransfer => self.process_data_transfer(message),
+            _ => None,
+        }
+    }
 
-                let mut data: [u8; 8] = [0xFF; 8];
-                data[0] = EtpCmControlByte::ClearToSend as u8;
-                data[1] = MAX_NUMBER_OF_PACKETS_TO_SEND;
-                data[2..=4].copy_from_slice(&(self.receiving_data_buffer.len()+1).to_le_bytes()[..=2]);
-                data[5..=7].copy_from_slice(&message.pgn().as_bytes());
-    
-                network_manager.send_can_message(CanMessage::new(
-                    CanPriority::PriorityLowest7,
-                    ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
-                    message.source_address(),
-                    message.destination_address(),
-                    &data,
-                ));
+    fn process_request_to_send(&mut self, message: &CanMessage) {
+        if message.is_address_global() {
+            return;
+        }
 
-                self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T2;
-                self.set_state(State::WaitForDataPacketOffset);
-            }
-            State::WaitForDataPacketOffset => {
-                if TimeDriver::time_elapsed() > self.next_timeout {
-					log::error!("[ETP]: Wait For Data Packet Offset Timeout");
-                    self.set_state(State::SendConnectionAbort(AbortReason::Timeout));
-				}
-            }
-            State::SendDataPacketOffset(message_to_send, number_of_packets_to_send, next_packet_number_to_send) => {
-                let mut data: [u8; 8] = [0xFF; 8];
-                data[0] = EtpCmControlByte::DataPacketOffset as u8;
-                data[1] = number_of_packets_to_send;
-                data[2..=4].copy_from_slice(&(next_packet_number_to_send-1 as u32).to_le_bytes());
-                data[5..=7].copy_from_slice(&message_to_send.pgn().as_bytes());
-    
-                network_manager.send_can_message(CanMessage::new(
-                    CanPriority::PriorityLowest7,
-                    ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
-                    message_to_send.source_address(),
-                    message_to_send.destination_address(),
-                    &data,
-                ));
-    
-                self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T1;
-                self.set_state(State::SendDataTransfer(message_to_send, 1, number_of_packets_to_send));
+        let pgn = message.get_pgn_at(5);
+        let message_size = message.get_u32_at(1) as usize;
+
+        // A new RTS from a sender we are already receiving from is not allowed.
+        if self.sessions.receive_session_index(message).is_some() || !self.sessions.is_session_available() {
+            self.send_abort(message.destination_address(), message.source_address(), pgn, AbortReason::AlreadyConnected);
+            return;
+        }
+
+        if !(MIN_MESSAGE_SIZE..=MAX_MESSAGE_SIZE).contains(&message_size) {
+            self.send_abort(message.destination_address(), message.source_address(), pgn, AbortReason::Other);
+            return;
+        }
+        if message_size > self.max_receive_message_size {
+            log::warn!("[ETP]: Refused a message of {} bytes from {}", message_size, message.source_address());
+            self.send_abort(message.destination_address(), message.source_address(), pgn, AbortReason::Terminated);
+            return;
+        }
+
+        self.sessions.push(Session::new_receive(message, pgn, message_size));
+    }
+
+    fn process_clear_to_send(&mut self, message: &CanMessage) {
+        let index = match self.sessions.transmit_session_index(message) {
+            Some(index) => index,
+            None => return,
+        };
+        let session = &mut self.sessions[index];
+
+        if session.key.pgn != message.get_pgn_at(5) {
+            session.state = State::SendConnectionAbort(AbortReason::BadClearToSendPgn);
+            return;
+        }
+
+        match session.state {
+            State::WaitForClearToSend | State::WaitForEndOfMessageAcknowledgement => {
+                let number_of_packets_to_send = message.get_u8_at(1);
+                let next_packet_number = message.get_u24_at(2);
+
+                // A CTS for zero packets means the receiver wants us to hold the connection open.
+                if number_of_packets_to_send == 0 {
+                    session.timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T4;
+                    return;
+                }
+
+                if next_packet_number == 0
+                    || next_packet_number as u64 + number_of_packets_to_send as u64 - 1 > session.number_of_packets as u64
+                {
+                    session.state = State::SendConnectionAbort(AbortReason::ClearToSendExceedsMessageSize);
+                    return;
+                }
+
+                // A CTS for packets we already send is a request to retransmit them.
+                if next_packet_number < session.next_packet_number {
+                    session.retransmit_count += 1;
+                    if session.retransmit_count > MAX_NUMBER_OF_RETRANSMITS {
+                        session.state = State::SendConnectionAbort(AbortReason::RetransmitLimitReached);
+                        return;
+                    }
+                    log::warn!("[ETP]: Retransmit from packet {} requested by {}", next_packet_number, message.source_address());
+                }
+
+                session.next_packet_number = next_packet_number;
+                session.packets_in_window = number_of_packets_to_send;
+                session.state = State::SendDataPacketOffset;
             }
-            State::WaitForDataTransfer => {
-                if TimeDriver::time_elapsed() > self.next_timeout {
-					log::error!("[ETP]: Wait For Data Timeout");
-                    self.set_state(State::SendConnectionAbort(AbortReason::Timeout));
-				}
+            State::SendDataPacketOffset | State::SendDataTransfer => {
+                session.state = State::SendConnectionAbort(AbortReason::DataTransferInProgress);
             }
-            State::SendDataTransfer(message_to_send, sequence_number, number_of_packets_to_send) => {
-                let mut data: [u8; 8] = [0xFF; 8];
-                data[0] = sequence_number;
-
-                // TODO: use real data
-    
-                network_manager.send_can_message(CanMessage::new(
-                    CanPriority::PriorityLowest7,
-                    ParameterGroupNumber::ExtendedTransportProtocolDataTransfer,
-                    message_to_send.source_address(),
-                    message_to_send.destination_address(),
-                    &data,
-                ));
-    
-                if sequence_number < number_of_packets_to_send {
-                    self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T1;
-                    self.set_state(State::SendDataTransfer(message_to_send, sequence_number + 1, number_of_packets_to_send));
+            _ => {}
+        }
+    }
+
+    fn process_data_packet_offset(&mut self, message: &CanMessage) {
+        let index = match self.sessions.receive_session_index(message) {
+            Some(index) => index,
+            None => return,
+        };
+        let session = &mut self.sessions[index];
+
+        if session.state != State::WaitForDataPacketOffset {
+            session.state = State::SendConnectionAbort(AbortReason::UnexpectedDataPacketOffset);
+            return;
+        }
+        if session.key.pgn != message.get_pgn_at(5) {
+            session.state = State::SendConnectionAbort(AbortReason::BadDataPacketOffsetPgn);
+            return;
+        }
+
+        let number_of_packets = message.get_u8_at(1);
+        let packet_offset = message.get_u24_at(2);
+
+        if number_of_packets > session.packets_in_window {
+            session.state = State::SendConnectionAbort(AbortReason::BadDataPacketOffsetNumberOfPackets);
+            return;
+        }
+        if packet_offset + 1 != session.next_packet_number {
+            session.state = State::SendConnectionAbort(AbortReason::BadDataPacketOffsetOffset);
+            return;
+        }
+
+        session.packet_offset = packet_offset;
+        session.packets_in_window = number_of_packets;
+        session.timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T1;
+        session.state = State::WaitForDataTransfer;
+    }
+
+    fn process_data_transfer(&mut self, message: &CanMessage) -> Option<CanMessage> {
+        let index = self.sessions.receive_session_index(message)?;
+        let session = &mut self.sessions[index];
+
+        if session.state != State::WaitForDataTransfer {
+            session.state = State::SendConnectionAbort(AbortReason::UnexpectedDataTransfer);
+            return None;
+        }
+
+        let sequence_number = message.get_u8_at(0);
+        let packet_number = session.packet_offset + sequence_number as u32;
+
+        if packet_number != session.next_packet_number {
+            // Request the missing packets again, until we reach the retransmit limit.
+            session.retransmit_count += 1;
+            session.state = if session.retransmit_count > MAX_NUMBER_OF_RETRANSMITS {
+                if packet_number < session.next_packet_number {
+                    State::SendConnectionAbort(AbortReason::DuplicateSequenceNumber)
                 } else {
-                    self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T3;
-                    self.set_state(State::WaitForClearToSend(message_to_send));
+                    State::SendConnectionAbort(AbortReason::BadSequenceNumber)
                 }
-            }
-            State::SendEndOfMessageAcknowledgement => {
-                let message = self.receiving_message_builder.build();
+            } else {
+                State::SendClearToSend
+            };
+            return None;
+        }
 
-                let mut data: [u8; 8] = [0xFF; 8];
-                data[0] = EtpCmControlByte::ClearToSend as u8;
-                data[1] = MAX_NUMBER_OF_PACKETS_TO_SEND;
-                data[2..=4].copy_from_slice(&(self.receiving_data_buffer.len()+1).to_le_bytes()[..=2]);
-                data[5..=7].copy_from_slice(&message.pgn().as_bytes());
-    
-                network_manager.send_can_message(CanMessage::new(
-                    CanPriority::PriorityLowest7,
-                    ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
-                    message.source_address(),
-                    message.destination_address(),
-                    &data,
-                ));
+        let offset = (packet_number as usize - 1) * BYTES_PER_PACKET;
+        let length = usize::min(BYTES_PER_PACKET, session.message_size - offset);
+        if message.len() <= length {
+            return None;
+        }
+        session.data[offset..offset + length].copy_from_slice(&message.data()[1..=length]);
+        session.next_packet_number += 1;
+        session.timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T1;
 
-                self.close_connection();
+        if packet_number == session.number_of_packets {
+            session.state = State::SendEndOfMessageAcknowledgement;
+            return Some(session.reassembled_message());
+        }
 
-                result = Some(self.receiving_message_builder.data(&self.receiving_data_buffer).build())
-            }
-            
-            State::SendConnectionAbort(abort_reason) => {
-                self.abort_connection(abort_reason, network_manager);
-            }
+        if sequence_number == session.packets_in_window {
+            session.state = State::SendClearToSend;
         }
+        None
+    }
 
-        result
+    fn open_connection(&mut self, message: CanMessage) {
+        self.sessions.push(Session::new_transmit(message));
     }
 
-    pub fn process_can_message(&mut self, message: &CanMessage) -> Option<CanMessage> {
-        match message.pgn() {
-            ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement => {
-                if let Ok(control_byte) = message.get_u8_at(0).try_into() {
-					match control_byte {
-                        EtpCmControlByte::RequestToSend => {
-                            if State::WaitingForRequestToSend == self.current_state {
-                                self.receiving_data_buffer = Vec::with_capacity(message.get_u32_at(1) as usize);
-                                let pgn = message.get_pgn_at(5);
+    fn send_abort(&mut self, source: Address, destination: Address, pgn: ParameterGroupNumber, abort_reason: AbortReason) {
+        self.aborts_to_send.push_back(abort_message(source, destination, pgn, abort_reason));
+    }
+}
 
-                                self.receiving_message_builder
-                                    .pgn(pgn)
-                                    .source_address(message.source_address())
-                                    .destination_address(message.destination_address());
+impl Default for ExtendedTransportProtocolManager {
+    fn default() -> Self {
+        Self {
+            sessions: SessionTable::new(),
+            max_receive_message_size: DEFAULT_MAX_RECEIVE_MESSAGE_SIZE,
+            aborts_to_send: VecDeque::new(),
+        }
+    }
+}
 
-                                self.set_state(State::SendClearToSend)
-                            } else {
+struct Session {
+    direction: Direction,
+    state: State,
+    timeout: Duration,
+
+    key: SessionKey,
+    priority: CanPriority,
+    data: Vec<u8>,
+    message_size: usize,
+
+    number_of_packets: u32,
+    next_packet_number: u32,
+    packet_offset: u32,
+    packets_in_window: u8,
+    retransmit_count: u8,
+}
 
-                            }
-                        }
-                        EtpCmControlByte::ClearToSend => {
-                            if let State::WaitForClearToSend(message_to_send) = self.current_state {
-                                if message_to_send.pgn() != message.get_pgn_at(5) {
-                                    self.set_state(State::SendConnectionAbort(AbortReason::BadClearToSendPgn));
-                                    return None;
-                                }
+impl TransportSession for Session {
+    fn key(&self) -> SessionKey {
+        self.key
+    }
 
-                                let number_of_packets_to_send = message.get_u8_at(1);
-                                let next_packet_number_to_send = message.get_u24_at(2);
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+}
 
-                                if number_of_packets_to_send == 0 {
-                                    self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T4;
-                                    return None;
-                                }
+impl Session {
+    fn new_transmit(message: CanMessage) -> Self {
+        Self {
+            direction: Direction::Transmit,
+            state: State::SendRequestToSend,
+            timeout: Duration::MAX,
+            key: SessionKey::new(message.source_address(), message.destination_address(), message.pgn()),
+            priority: message.priority(),
+            data: message.data().into(),
+            message_size: message.len(),
+            number_of_packets: number_of_packets_for(message.len()) as u32,
+            next_packet_number: 1,
+            packet_offset: 0,
+            packets_in_window: 0,
+            retransmit_count: 0,
+        }
+    }
 
-                                self.set_state(State::SendDataPacketOffset(
-                                    message_to_send,
-                                    number_of_packets_to_send,
-                                    next_packet_number_to_send,
-                                ))
-                            }
-                            None
-                        }
-                        EtpCmControlByte::DataPacketOffset => {
-                            if State::WaitForDataPacketOffset == self.current_state {
-                                // self.receiving_number_of_bytes = message.get_u32_at(1);
-                                // let pgn = message.get_pgn_at(5);
+    fn new_receive(message: &CanMessage, pgn: ParameterGroupNumber, message_size: usize) -> Self {
+        Self {
+            direction: Direction::Receive,
+            state: State::SendClearToSend,
+            timeout: Duration::MAX,
+            key: SessionKey::new(message.source_address(), message.destination_address(), pgn),
+            priority: message.priority(),
+            data: alloc::vec![0xFF; message_size],
+            message_size,
+            number_of_packets: number_of_packets_for(message_size) as u32,
+            next_packet_number: 1,
+            packet_offset: 0,
+            packets_in_window: 0,
+            retransmit_count: 0,
+        }
+    }
 
-                                // self.receiving_message_builder
-                                //     .pgn(pgn)
-                                //     .source_address(message.source_address())
-                                //     .destination_address(message.destination_address());
+    /// Our own address in this session.
+    fn local_address(&self) -> Address {
+        match self.direction {
+            Direction::Transmit => self.key.source,
+            Direction::Receive => self.key.destination,
+        }
+    }
 
-                                self.receiving_number_of_packets = message.get_u8_at(1);
-                                // self.receiving_next_packet_number = message.get_u24_at(2);
+    /// The address of the other control function in this session.
+    fn remote_address(&self) -> Address {
+        match self.direction {
+            Direction::Transmit => self.key.destination,
+            Direction::Receive => self.key.source,
+        }
+    }
 
-                                self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T1;
-                                self.set_state(State::WaitForDataTransfer)
-                            }
-                            None
-                        }
-                        EtpCmControlByte::EndOfMessageAcknowledgement => {
-                            if let State::WaitForClearToSend(message_to_send) = self.current_state {
-                                if message_to_send.pgn() != message.get_pgn_at(5) {
-                                    self.set_state(State::SendConnectionAbort(AbortReason::Other));
-                                    return None;
-                                }
+    fn reassembled_message(&self) -> CanMessage {
+        CanMessage::new(self.priority, self.key.pgn, self.key.source, self.key.destination, &self.data)
+    }
 
-                                let number_of_bytes_transferred = message.get_u32_at(1);
+    /// Update based on the current state, returns false when the session is finished.
+    fn update(&mut self, network_manager: &mut CanNetworkManager) -> bool {
+        match self.state {
+            State::SendRequestToSend => {
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = EtpCmControlByte::RequestToSend as u8;
+                data[1..=4].copy_from_slice(&(self.message_size as u32).to_le_bytes());
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
 
-                                if number_of_bytes_transferred != message_to_send.len() as u32 {
-                                    self.set_state(State::SendConnectionAbort(AbortReason::Other));
-                                    return None;
-                                }
+                self.timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T3;
+                self.state = State::WaitForClearToSend;
+            }
+            State::WaitForClearToSend => {
+                if TimeDriver::time_elapsed() > self.timeout {
+                    log::error!("[ETP]: Wait For Clear To Send Timeout");
+                    self.state = State::SendConnectionAbort(AbortReason::Timeout);
+                }
+            }
+            State::SendDataPacketOffset => {
+                self.packet_offset = self.next_packet_number - 1;
 
-                                self.close_connection();
-                            }
-                            None
-                        }
-                        EtpCmControlByte::ConnectionAbort => {
-                            None
-                        }
-                    }
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = EtpCmControlByte::DataPacketOffset as u8;
+                data[1] = self.packets_in_window;
+                data[2..=4].copy_from_slice(&self.packet_offset.to_le_bytes()[..=2]);
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
+
+                self.state = State::SendDataTransfer;
+            }
+            State::SendDataTransfer => {
+                for sequence_number in 1..=self.packets_in_window {
+                    self.send_data_transfer(network_manager, sequence_number);
+                }
+                self.next_packet_number = self.packet_offset + self.packets_in_window as u32 + 1;
+
+                self.timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T3;
+                self.state = if self.next_packet_number > self.number_of_packets {
+                    State::WaitForEndOfMessageAcknowledgement
                 } else {
-                    None
+                    State::WaitForClearToSend
+                };
+            }
+            State::WaitForEndOfMessageAcknowledgement => {
+                if TimeDriver::time_elapsed() > self.timeout {
+                    log::error!("[ETP]: Wait For End Of Message Acknowledgement Timeout");
+                    self.state = State::SendConnectionAbort(AbortReason::Timeout);
                 }
             }
-            ParameterGroupNumber::ExtendedTransportProtocolDataTransfer => {
-                if State::WaitForDataTransfer == self.current_state {
-                    let sequence_number = message.get_u8_at(0);
-                    // let offset = self.receiving_next_packet_number + sequence_number as u32;
-
-                    // TODO: implement end edge case, len < 7
-                    self.receiving_data_buffer.extend_from_slice(&message.data()[1..=7]);
-
-                    // Have we filled the entire buffer
-                    if self.receiving_data_buffer.len() == self.receiving_data_buffer.capacity() {
-                        self.set_state(State::SendEndOfMessageAcknowledgement);
-                        self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T1;
-                    } else {
-                        self.next_timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T1;
-                    }
+            State::SendClearToSend => {
+                let remaining_packets = self.number_of_packets - self.next_packet_number + 1;
+                self.packets_in_window = u32::min(MAX_NUMBER_OF_PACKETS_TO_SEND as u32, remaining_packets) as u8;
+
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = EtpCmControlByte::ClearToSend as u8;
+                data[1] = self.packets_in_window;
+                data[2..=4].copy_from_slice(&self.next_packet_number.to_le_bytes()[..=2]);
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
+
+                self.timeout = TimeDriver::time_elapsed() + ETP_TIMEOUT_T2;
+                self.state = State::WaitForDataPacketOffset;
+            }
+            State::WaitForDataPacketOffset => {
+                if TimeDriver::time_elapsed() > self.timeout {
+                    log::error!("[ETP]: Wait For Data Packet Offset Timeout");
+                    self.state = State::SendConnectionAbort(AbortReason::Timeout);
                 }
-                None
             }
-            _ => { None }
-        }
-    }
+            State::WaitForDataTransfer => {
+                if TimeDriver::time_elapsed() > self.timeout {
+                    log::error!("[ETP]: Wait For Data Timeout");
+                    self.state = State::SendConnectionAbort(AbortReason::Timeout);
+                }
+            }
+            State::SendEndOfMessageAcknowledgement => {
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = EtpCmControlByte::EndOfMessageAcknowledgement as u8;
+                data[1..=4].copy_from_slice(&(self.message_size as u32).to_le_bytes());
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
 
+                return false;
+            }
+            State::SendConnectionAbort(abort_reason) => {
+                network_manager.send_can_message(abort_message(
+                    self.local_address(),
+                    self.remote_address(),
+                    self.key.pgn,
+                    abort_reason,
+                ));
 
-    fn open_connection(&mut self, message: CanMessage) {
-        self.set_state(State::SendRequestToSend(message));
+                return false;
+            }
+        }
+        true
     }
 
-    fn close_connection(&mut self) {
-        self.next_timeout = Duration::MAX;
-        // self.message_to_send = None;
-        // self.receive_buffer.clear();
-        // self.receive_pgn = None;
-        // self.receive_nr_of_packets = 0;
-        self.set_state(State::WaitingForRequestToSend);
+    fn send_connection_management(&self, network_manager: &mut CanNetworkManager, data: &[u8; 8]) {
+        network_manager.send_can_message(CanMessage::new(
+            CanPriority::PriorityLowest7,
+            ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
+            self.local_address(),
+            self.remote_address(),
+            data,
+        ));
     }
 
-    fn abort_connection(&mut self, abort_reason: AbortReason, network_manager: &mut CanNetworkManager) {
-        let message = if self.message_to_send.is_some() {
-            self.message_to_send.unwrap()
-        } else {
-            self.receiving_message_builder.build()
-        };
+    /// Sends a data packet, the sequence number is relative to the last data packet offset.
+    fn send_data_transfer(&self, network_manager: &mut CanNetworkManager, sequence_number: u8) {
+        let packet_number = self.packet_offset + sequence_number as u32;
+        let offset = (packet_number as usize - 1) * BYTES_PER_PACKET;
+        let length = usize::min(BYTES_PER_PACKET, self.message_size - offset);
 
         let mut data: [u8; 8] = [0xFF; 8];
-        data[0] = EtpCmControlByte::ConnectionAbort as u8;
-        data[1] = abort_reason as u8;
-        data[5..=7].copy_from_slice(&message.pgn().as_bytes());
-    
+        data[0] = sequence_number;
+        data[1..=length].copy_from_slice(&self.data[offset..offset + length]);
+
         network_manager.send_can_message(CanMessage::new(
             CanPriority::PriorityLowest7,
-            ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
-            message.source_address(),
-            message.destination_address(),
+            ParameterGroupNumber::ExtendedTransportProtocolDataTransfer,
+            self.local_address(),
+            self.remote_address(),
             &data,
         ));
-
-        self.close_connection();
-    }
-
-
-    // fn send_data(
-    //     &self,
-    //     can: &mut Box<dyn CanDriverTrait>,
-    //     next_packet: u32,
-    //     number_of_packets: u8,
-    //     da: IsobusAddress,
-    //     sa: IsobusAddress,
-    // ) {
-    //     if let Some(pdu_to_send) = &self.message_to_send {
-    //         can.write(
-    //             PDU::new_etp_data_packet_offset(
-    //                 number_of_packets,
-    //                 next_packet - 1,
-    //                 pdu_to_send.pgn(),
-    //                 da,
-    //                 sa,
-    //             )
-    //             .into(),
-    //         );
-
-    //         let chunks: Vec<&[u8]> = pdu_to_send.data_raw().chunks(7).collect();
-    //         for i in 0..number_of_packets {
-    //             can.write(
-    //                 PDU::new_etp_data_transfer(
-    //                     i + 1,
-    //                     chunks[(next_packet - 1 + i as u32) as usize],
-    //                     da,
-    //                     sa,
-    //                 )
-    //                 .into(),
-    //             );
-    //         }
-    //     }
-    // }
-
-    fn set_state(&mut self, state: State) {
-        self.current_state = state;
     }
 }
 
-impl Default for ExtendedTransportProtocolManager {
-    fn default() -> Self {
-        Self {
-            current_state: State::default(),
-            message_backlog: VecDeque::new(),
-            message_to_send: None,
-            next_timeout: Duration::MAX,
-            receiving_message_builder: CanMessage::builder(),
-            receiving_number_of_bytes: 0,
-            receiving_number_of_packets: 0,
-            receiving_next_packet_number: 1,
-            // _receive_buffer: Vec::new(),
-            // receive_pgn: None,
-            // _receive_nr_of_packets: 0,
-        }
-    }
+fn abort_message(source: Address, destination: Address, pgn: ParameterGroupNumber, abort_reason: AbortReason) -> CanMessage {
+    let mut data: [u8; 8] = [0xFF; 8];
+    data[0] = EtpCmControlByte::ConnectionAbort as u8;
+    data[1] = abort_reason as u8;
+    data[5..=7].copy_from_slice(&pgn.as_bytes());
+
+    CanMessage::new(
+        CanPriority::PriorityLowest7,
+        ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
+        source,
+        destination,
+        &data,
+    )
 }
 
-
-
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum State {
-    WaitingForRequestToSend,
-    SendRequestToSend(CanMessage),
-	WaitForClearToSend(CanMessage),
+    // Transmit states
+    SendRequestToSend,
+    WaitForClearToSend,
+    SendDataPacketOffset,
+    SendDataTransfer,
+    WaitForEndOfMessageAcknowledgement,
+    // Receive states
     SendClearToSend,
     WaitForDataPacketOffset,
-    SendDataPacketOffset(CanMessage, u8, u32),
     WaitForDataTransfer,
-    SendDataTransfer(CanMessage, u8, u8),
     SendEndOfMessageAcknowledgement,
-    SendConnectionAbort(AbortReason),
-}
 
-impl Default for State {
-    fn default() -> Self {
-        Self::WaitingForRequestToSend
-    }
+    SendConnectionAbort(AbortReason),
 }
 
 /// Enumerates the multiplexor byte values for EtpCm Control Byte
@@ -436,7 +570,7 @@ impl TryFrom<u8> for EtpCmControlByte {
 
 
 #[repr(u8)]
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum AbortReason {
     AlreadyConnected = 0x01,                    //< Already in one or more connection-managed sessions and cannot support another
     Terminated = 0x02,                          //< System resources were needed for another task so this connection managed session was terminated
@@ -451,7 +585,7 @@ enum AbortReason {
     BadDataPacketOffsetNumberOfPackets = 0x0B,  //< EDPO number of packets is greater than CTS
     BadDataPacketOffsetOffset = 0x0C,           //< Bad EDPO offset
     BadClearToSendPgn = 0x0E,                   //< Unexpected ECTS PGN (PGN in ECTS is bad)
-    //  = 0x0F,                                    //< ECTS requested packets exceeds message size
+    ClearToSendExceedsMessageSize = 0x0F,       //< ECTS requested packets exceeds message size
     Other = 0xFA,                               //< Any other reason
 }
 
@@ -470,7 +604,274 @@ impl From<u8> for AbortReason {
             0x0A => Self::BadDataPacketOffsetPgn,
             0x0B => Self::BadDataPacketOffsetNumberOfPackets,
             0x0C => Self::BadDataPacketOffsetOffset,
+            0x0E => Self::BadClearToSendPgn,
+            0x0F => Self::ClearToSendExceedsMessageSize,
             _ => Self::Other,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, feature = "mock_can_driver"))]
+mod tests {
+    use super::*;
+    use crate::hardware_integration::CanDriver;
+
+    const LOCAL: Address = Address(0x80);
+    const REMOTE: Address = Address(0x26);
+    const PGN: ParameterGroupNumber = ParameterGroupNumber::ECUtoVirtualTerminal;
+
+    fn setup() -> (ExtendedTransportProtocolManager, CanNetworkManager, CanDriver) {
+        let driver = CanDriver::new();
+        (ExtendedTransportProtocolManager::new(), CanNetworkManager::new(driver.clone()), driver)
+    }
+
+    fn sent(driver: &CanDriver) -> Vec<CanMessage> {
+        driver.take_written_frames().into_iter().map(CanMessage::from).collect()
+    }
+
+    fn payload(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    fn control(source: Address, destination: Address, control_byte: EtpCmControlByte, value: u8, number: u32) -> CanMessage {
+        let number = number.to_le_bytes();
+        let pgn = PGN.as_bytes();
+        let data = [control_byte as u8, value, number[0], number[1], number[2], pgn[0], pgn[1], pgn[2]];
+        CanMessage::new(CanPriority::PriorityLowest7, ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement, source, destination, &data)
+    }
+
+    fn request_to_send(source: Address, destination: Address, message_size: u32) -> CanMessage {
+        let size = message_size.to_le_bytes();
+        let pgn = PGN.as_bytes();
+        let data = [EtpCmControlByte::RequestToSend as u8, size[0], size[1], size[2], size[3], pgn[0], pgn[1], pgn[2]];
+        CanMessage::new(CanPriority::PriorityLowest7, ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement, source, destination, &data)
+    }
+
+    fn data_transfer(source: Address, destination: Address, sequence_number: u8, packet_number: u32, data: &[u8]) -> CanMessage {
+        let offset = (packet_number as usize - 1) * BYTES_PER_PACKET;
+        let mut packet = [0xFF; 8];
+        packet[0] = sequence_number;
+        for (i, byte) in data.iter().skip(offset).take(BYTES_PER_PACKET).enumerate() {
+            packet[i + 1] = *byte;
+        }
+        CanMessage::new(CanPriority::PriorityLowest7, ParameterGroupNumber::ExtendedTransportProtocolDataTransfer, source, destination, &packet)
+    }
+
+    fn assert_abort(message: &CanMessage, reason: AbortReason) {
+        assert_eq!(message.pgn(), ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement);
+        assert_eq!(message.get_u8_at(0), EtpCmControlByte::ConnectionAbort as u8);
+        assert_eq!(message.get_u8_at(1), reason as u8);
+        assert_eq!(message.get_pgn_at(5), PGN);
+    }
+
+    #[test]
+    fn transmit_with_data_packet_offset() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(1800);
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &data));
+
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data()[..5], [0x14, 0x08, 0x07, 0x00, 0x00]);
+
+        // Every window of data is preceded by a DPO holding the offset of the window.
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::ClearToSend, 3, 1));
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0].data()[..5], [0x16, 3, 0, 0, 0]);
+        assert_eq!(messages[1].data(), [1, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(messages[3].data(), [3, 14, 15, 16, 17, 18, 19, 20]);
+
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::ClearToSend, 2, 4));
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages[0].data()[..5], [0x16, 2, 3, 0, 0]);
+        assert_eq!(messages[1].data(), [1, 21, 22, 23, 24, 25, 26, 27]);
+        assert_eq!(messages[2].data()[0], 2);
+
+        // The last packet is padded, the session ends with the end of message acknowledgement.
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::ClearToSend, 2, 257));
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages[0].data()[..5], [0x16, 2, 0, 1, 0]);
+        assert_eq!(messages[2].data(), [2, (1799 % 256) as u8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        manager.process_can_message(&CanMessage::new(
+            CanPriority::PriorityLowest7,
+            ParameterGroupNumber::ExtendedTransportProtocolConnectionManagement,
+            REMOTE,
+            LOCAL,
+            &[0x17, 0x08, 0x07, 0x00, 0x00, PGN.as_bytes()[0], PGN.as_bytes()[1], PGN.as_bytes()[2]],
+        ));
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn retransmit() {
+        let (mut manager, mut network_manager, driver) = setup();
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &payload(1800)));
+        manager.update(&mut network_manager);
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::ClearToSend, 3, 1));
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+
+        // A CTS for packets that were already send retransmits them.
+        for _ in 0..MAX_NUMBER_OF_RETRANSMITS {
+            manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::ClearToSend, 2, 2));
+            manager.update(&mut network_manager);
+            manager.update(&mut network_manager);
+            let messages = sent(&driver);
+            assert_eq!(messages.len(), 3);
+            assert_eq!(messages[0].data()[..5], [0x16, 2, 1, 0, 0]);
+            assert_eq!(messages[1].data(), [1, 7, 8, 9, 10, 11, 12, 13]);
+        }
+
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::ClearToSend, 2, 2));
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_abort(&messages[0], AbortReason::RetransmitLimitReached);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn receive() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(1786);
+
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, 1786));
+        let mut next_packet_number = 1;
+        while next_packet_number <= 256 {
+            manager.update(&mut network_manager);
+            let messages = sent(&driver);
+            assert_eq!(messages.len(), 1);
+            let window = messages[0].get_u8_at(1);
+            assert_eq!(messages[0].get_u8_at(0), EtpCmControlByte::ClearToSend as u8);
+            assert_eq!(messages[0].get_u24_at(2), next_packet_number);
+
+            manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::DataPacketOffset, window, next_packet_number - 1));
+            for sequence_number in 1..=window {
+                let message = manager.process_can_message(&data_transfer(REMOTE, LOCAL, sequence_number, next_packet_number, &data));
+                next_packet_number += 1;
+                if next_packet_number > 256 {
+                    assert_eq!(message, Some(CanMessage::new(CanPriority::PriorityLowest7, PGN, REMOTE, LOCAL, &data)));
+                } else {
+                    assert_eq!(message, None);
+                }
+            }
+        }
+
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver)[0].data()[..5], [0x17, 0xFA, 0x06, 0x00, 0x00]);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn packet_numbers_use_24_bits() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let message_size = 0x010203 * BYTES_PER_PACKET;
+        manager.set_max_receive_message_size(message_size);
+
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, message_size as u32));
+        manager.sessions[0].next_packet_number = 0x010201;
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver)[0].data()[..5], [0x15, 3, 0x01, 0x02, 0x01]);
+
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::DataPacketOffset, 3, 0x010200));
+        for sequence_number in 1..=3 {
+            let packet = [sequence_number, 1, 2, 3, 4, 5, 6, 7];
+            let message = manager.process_can_message(&CanMessage::new(
+                CanPriority::PriorityLowest7,
+                ParameterGroupNumber::ExtendedTransportProtocolDataTransfer,
+                REMOTE,
+                LOCAL,
+                &packet,
+            ));
+            assert_eq!(message.is_some(), sequence_number == 3);
+        }
+        assert_eq!(manager.sessions[0].data[message_size - 7..], [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn data_packet_offset_errors() {
+        let (mut manager, mut network_manager, driver) = setup();
+
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, 1786));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::DataPacketOffset, 32, 5));
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::BadDataPacketOffsetOffset);
+
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, 1786));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::DataPacketOffset, 33, 0));
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::BadDataPacketOffsetNumberOfPackets);
+
+        // Data before the DPO is unexpected.
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, 1786));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&data_transfer(REMOTE, LOCAL, 1, 1, &payload(1786)));
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::UnexpectedDataTransfer);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn missing_packets_are_requested_again() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(1786);
+
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, 1786));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&control(REMOTE, LOCAL, EtpCmControlByte::DataPacketOffset, 32, 0));
+        manager.process_can_message(&data_transfer(REMOTE, LOCAL, 1, 1, &data));
+        manager.process_can_message(&data_transfer(REMOTE, LOCAL, 3, 3, &data));
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages[0].get_u8_at(0), EtpCmControlByte::ClearToSend as u8);
+        assert_eq!(messages[0].get_u24_at(2), 2);
+    }
+
+    #[test]
+    fn refuses_messages_larger_than_the_limit() {
+        let (mut manager, mut network_manager, driver) = setup();
+        manager.set_max_receive_message_size(4096);
+
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, 4097));
+        assert!(manager.sessions.keys().is_empty());
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].destination_address(), REMOTE);
+        assert_abort(&messages[0], AbortReason::Terminated);
+
+        // The default limit is far below the ETP maximum.
+        let (mut manager, _, _) = setup();
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, MAX_MESSAGE_SIZE as u32));
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn timeouts() {
+        let (mut manager, mut network_manager, driver) = setup();
+        manager.process_can_message(&request_to_send(REMOTE, LOCAL, 1786));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+
+        manager.sessions[0].timeout = Duration::ZERO;
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::Timeout);
+        assert!(manager.sessions.keys().is_empty());
+    }
+}
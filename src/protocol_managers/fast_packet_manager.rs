@@ -0,0 +1,294 @@
+use core::time::Duration;
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec::Vec;
+
+use crate::hardware_integration::{TimeDriver, TimeDriverTrait};
+use crate::{Address, CanMessage, CanNetworkManager, ParameterGroupNumber};
+
+const FAST_PACKET_TIMEOUT: Duration = Duration::from_millis(750);   //< The max time between two frames of a message
+
+const MAX_MESSAGE_SIZE: usize = 223;
+const BYTES_IN_FIRST_FRAME: usize = 6;
+const BYTES_PER_FRAME: usize = 7;
+const NUMBER_OF_SEQUENCES: u8 = 8;
+
+/// Handles NMEA2000 Fast Packet messages.
+///
+/// A fast packet message of up to 223 bytes is send in frames of 8 bytes with the same PGN.
+/// The first byte of every frame holds a 3 bit sequence counter and a 5 bit frame counter,
+/// the first frame also holds the total message size.
+/// There is no way to tell fast packet frames from single frame messages, so the fast packet PGNs must be configured.
+#[derive(Default)]
+pub struct FastPacketManager {
+    fast_packet_pgns: BTreeSet<ParameterGroupNumber>,
+    sessions: Vec<Session>,
+    sequence_counters: BTreeMap<(Address, ParameterGroupNumber), u8>,
+    frames_to_send: VecDeque<CanMessage>,
+}
+
+impl FastPacketManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_pgn(&mut self, pgn: ParameterGroupNumber) {
+        let _ = self.fast_packet_pgns.insert(pgn);
+    }
+    pub fn remove_pgn(&mut self, pgn: ParameterGroupNumber) {
+        let _ = self.fast_packet_pgns.remove(&pgn);
+    }
+    pub fn is_fast_packet(&self, pgn: ParameterGroupNumber) -> bool {
+        self.fast_packet_pgns.contains(&pgn)
+    }
+
+    /// Split a message into fast packet frames, they are send on the next update.
+    pub fn send(&mut self, message: CanMessage) {
+        if message.len() > MAX_MESSAGE_SIZE {
+            log::error!("[FP]: Can not send a message of {} bytes", message.len());
+            return;
+        }
+
+        // Every message of a source and PGN pair uses the next sequence counter.
+        let counter = self.sequence_counters
+            .entry((message.source_address(), message.pgn()))
+            .or_default();
+        let sequence = *counter;
+        *counter = (*counter + 1) % NUMBER_OF_SEQUENCES;
+
+        let data = message.data();
+        let first_length = usize::min(data.len(), BYTES_IN_FIRST_FRAME);
+
+        let mut frame: [u8; 8] = [0xFF; 8];
+        frame[0] = sequence << 5;
+        frame[1] = data.len() as u8;
+        frame[2..2 + first_length].copy_from_slice(&data[..first_length]);
+        self.push_frame(&message, frame);
+
+        for (index, chunk) in data[first_length..].chunks(BYTES_PER_FRAME).enumerate() {
+            let mut frame: [u8; 8] = [0xFF; 8];
+            frame[0] = sequence << 5 | (index as u8 + 1);
+            frame[1..1 + chunk.len()].copy_from_slice(chunk);
+            self.push_frame(&message, frame);
+        }
+    }
+
+    /// Sends the pending frames and drops incomplete messages that timed out.
+    pub fn update(&mut self, network_manager: &mut CanNetworkManager) {
+        // The frames are send directly, they would be split again by `send_can_message`.
+        while let Some(message) = self.frames_to_send.pop_front() {
+            if let Some(frame) = message.as_can_frame() {
+                network_manager.send_can_frame(frame);
+            }
+        }
+
+        let now = TimeDriver::time_elapsed();
+        self.sessions.retain(|session| {
+            if now > session.timeout {
+                log::warn!("[FP]: Timeout receiving PGN {:?} from {}", session.pgn, session.source);
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Processes a fast packet frame, returns the message once all its frames are received.
+    pub fn process_can_message(&mut self, message: &CanMessage) -> Option<CanMessage> {
+        if message.len() < 2 {
+            return None;
+        }
+        let sequence = message.get_u8_at(0) >> 5;
+        let frame = message.get_u8_at(0) & 0x1F;
+
+        let index = self.sessions.iter()
+            .position(|session| session.source == message.source_address() && session.pgn == message.pgn());
+
+        if frame == 0 {
+            let length = message.get_u8_at(1) as usize;
+            if length > MAX_MESSAGE_SIZE {
+                log::warn!("[FP]: Ignoring PGN {:?} from {} of {} bytes", message.pgn(), message.source_address(), length);
+                return None;
+            }
+
+            // A new first frame replaces any message still being received.
+            if let Some(index) = index {
+                log::warn!("[FP]: Incomplete PGN {:?} from {} replaced by a new message", message.pgn(), message.source_address());
+                self.sessions.swap_remove(index);
+            }
+
+            let mut session = Session {
+                source: message.source_address(),
+                destination: message.destination_address(),
+                pgn: message.pgn(),
+                sequence,
+                next_frame: 1,
+                length,
+                data: Vec::with_capacity(length),
+                timeout: TimeDriver::time_elapsed() + FAST_PACKET_TIMEOUT,
+            };
+            session.append(&message.data()[2..]);
+
+            if session.is_complete() {
+                return Some(session.into_message(message));
+            }
+            self.sessions.push(session);
+            return None;
+        }
+
+        // Frames of a message we did not see the start of are ignored.
+        let index = index?;
+        let session = &mut self.sessions[index];
+        if sequence != session.sequence || frame != session.next_frame {
+            log::warn!("[FP]: Frame {} out of order for PGN {:?} from {}, aborting", frame, session.pgn, session.source);
+            self.sessions.swap_remove(index);
+            return None;
+        }
+
+        session.append(&message.data()[1..]);
+        session.next_frame += 1;
+        session.timeout = TimeDriver::time_elapsed() + FAST_PACKET_TIMEOUT;
+
+        if session.is_complete() {
+            let session = self.sessions.swap_remove(index);
+            return Some(session.into_message(message));
+        }
+        None
+    }
+
+    fn push_frame(&mut self, message: &CanMessage, frame: [u8; 8]) {
+        self.frames_to_send.push_back(CanMessage::new(
+            message.priority(),
+            message.pgn(),
+            message.source_address(),
+            message.destination_address(),
+            &frame,
+        ));
+    }
+}
+
+struct Session {
+    source: Address,
+    destination: Address,
+    pgn: ParameterGroupNumber,
+    sequence: u8,       //< The sequence counter of the message being received
+    next_frame: u8,     //< The frame counter we expect next
+    length: usize,      //< The total size of the message
+    data: Vec<u8>,
+    timeout: Duration,
+}
+
+impl Session {
+    fn append(&mut self, data: &[u8]) {
+        let remaining = self.length - self.data.len();
+        self.data.extend_from_slice(&data[..usize::min(data.len(), remaining)]);
+    }
+
+    fn is_complete(&self) -> bool {
+        self.data.len() >= self.length
+    }
+
+    fn into_message(self, last_frame: &CanMessage) -> CanMessage {
+        CanMessage::new(last_frame.priority(), self.pgn, self.source, self.destination, &self.data)
+    }
+}
+
+#[cfg(all(test, feature = "mock_can_driver"))]
+mod tests {
+    use super::*;
+    use crate::hardware_integration::CanDriver;
+    use crate::CanPriority;
+
+    const SOURCE: Address = Address(0x23);
+    const PGN: ParameterGroupNumber = ParameterGroupNumber::new(0x01F805);  //< NMEA2000 GNSS Position Data
+
+    fn payload(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    fn frame(data: [u8; 8]) -> CanMessage {
+        CanMessage::new(CanPriority::PriorityDefault6, PGN, SOURCE, Address::GLOBAL, &data)
+    }
+
+    /// Split a message into frames using the manager.
+    fn frames(manager: &mut FastPacketManager, data: &[u8]) -> Vec<CanMessage> {
+        let driver = CanDriver::new();
+        let mut network_manager = CanNetworkManager::new(driver.clone());
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, SOURCE, Address::GLOBAL, data));
+        manager.update(&mut network_manager);
+        driver.take_written_frames().into_iter().map(CanMessage::from).collect()
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let mut manager = FastPacketManager::new();
+        let data = payload(20);
+
+        let sent = frames(&mut manager, &data);
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].data(), [0x00, 20, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(sent[1].data(), [0x01, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(sent[2].data(), [0x02, 13, 14, 15, 16, 17, 18, 19]);
+
+        let mut receiver = FastPacketManager::new();
+        assert_eq!(receiver.process_can_message(&sent[0]), None);
+        assert_eq!(receiver.process_can_message(&sent[1]), None);
+        let message = receiver.process_can_message(&sent[2]).unwrap();
+        assert_eq!(message.data(), data);
+        assert_eq!(message.source_address(), SOURCE);
+        assert!(receiver.sessions.is_empty());
+    }
+
+    #[test]
+    fn sequence_counters() {
+        let mut manager = FastPacketManager::new();
+
+        // The counter is kept per source and PGN, and wraps after 8 messages.
+        for sequence in 0..10u8 {
+            let sent = frames(&mut manager, &payload(10));
+            assert_eq!(sent[0].get_u8_at(0), (sequence % 8) << 5);
+            assert_eq!(sent[1].get_u8_at(0), (sequence % 8) << 5 | 1);
+        }
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, Address(0x24), Address::GLOBAL, &payload(10)));
+        assert_eq!(manager.frames_to_send.front().map(|frame| frame.get_u8_at(0)), Some(0));
+
+        // Frames of another sequence do not belong to the message being received.
+        let mut receiver = FastPacketManager::new();
+        receiver.process_can_message(&frame([0x20, 10, 0, 1, 2, 3, 4, 5]));
+        assert_eq!(receiver.process_can_message(&frame([0x41, 6, 7, 8, 9, 0xFF, 0xFF, 0xFF])), None);
+        assert!(receiver.sessions.is_empty());
+    }
+
+    #[test]
+    fn out_of_order_frames() {
+        let mut receiver = FastPacketManager::new();
+
+        // A skipped frame aborts the message.
+        receiver.process_can_message(&frame([0x00, 20, 0, 1, 2, 3, 4, 5]));
+        assert_eq!(receiver.process_can_message(&frame([0x02, 13, 14, 15, 16, 17, 18, 19])), None);
+        assert!(receiver.sessions.is_empty());
+        assert_eq!(receiver.process_can_message(&frame([0x01, 6, 7, 8, 9, 10, 11, 12])), None);
+
+        // A new first frame replaces the incomplete message.
+        receiver.process_can_message(&frame([0x00, 20, 0, 1, 2, 3, 4, 5]));
+        receiver.process_can_message(&frame([0x20, 9, 9, 8, 7, 6, 5, 4]));
+        assert_eq!(receiver.sessions.len(), 1);
+        let message = receiver.process_can_message(&frame([0x21, 3, 2, 1, 0xFF, 0xFF, 0xFF, 0xFF]));
+        assert_eq!(message.map(|message| message.data().to_vec()), Some(alloc::vec![9, 8, 7, 6, 5, 4, 3, 2, 1]));
+    }
+
+    #[test]
+    fn timeouts() {
+        let driver = CanDriver::new();
+        let mut network_manager = CanNetworkManager::new(driver);
+        let mut receiver = FastPacketManager::new();
+
+        receiver.process_can_message(&frame([0x00, 20, 0, 1, 2, 3, 4, 5]));
+        receiver.update(&mut network_manager);
+        assert_eq!(receiver.sessions.len(), 1);
+
+        receiver.sessions[0].timeout = Duration::ZERO;
+        receiver.update(&mut network_manager);
+        assert!(receiver.sessions.is_empty());
+        assert_eq!(receiver.process_can_message(&frame([0x01, 6, 7, 8, 9, 10, 11, 12])), None);
+    }
+}
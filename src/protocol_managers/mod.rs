@@ -1,5 +1,12 @@
+mod session;
+pub use session::{Direction, SessionKey};
+use session::{number_of_packets_for, SessionTable, TransportSession, BYTES_PER_PACKET};
+
 mod transport_protocol_manager;
 pub use transport_protocol_manager::TransportProtocolManager;
 
 mod extended_transport_protocol_manager;
-pub use extended_transport_protocol_manager::ExtendedTransportProtocolManager;
\ No newline at end of file
+pub use extended_transport_protocol_manager::ExtendedTransportProtocolManager;
+
+mod fast_packet_manager;
+pub use fast_packet_manager::FastPacketManager;
@@ -0,0 +1,283 @@
+use core::ops::{Index, IndexMut};
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::{Address, CanMessage, ParameterGroupNumber};
+
+/// The default number of TP or ETP sessions a manager will run at the same time.
+pub const DEFAULT_MAX_SESSIONS: usize = 8;
+
+/// The number of data bytes in a TP or ETP data transfer packet.
+pub const BYTES_PER_PACKET: usize = 7;
+
+/// Returns the number of data transfer packets needed to send a message of `message_size` bytes.
+pub fn number_of_packets_for(message_size: usize) -> usize {
+    message_size.div_ceil(BYTES_PER_PACKET)
+}
+
+/// Identifies a transport session by the source, destination and PGN of the message being transferred.
+///
+/// ISO11783-3 only allows a single session per source and destination pair per direction,
+/// the PGN is used to match the control messages of the other control function to the session.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SessionKey {
+    pub source: Address,
+    pub destination: Address,
+    pub pgn: ParameterGroupNumber,
+}
+
+impl SessionKey {
+    pub fn new(source: Address, destination: Address, pgn: ParameterGroupNumber) -> Self {
+        Self {
+            source,
+            destination,
+            pgn,
+        }
+    }
+
+    /// Returns true if the session is between the given source and destination.
+    pub fn is_between(&self, source: Address, destination: Address) -> bool {
+        self.source == source && self.destination == destination
+    }
+}
+
+/// The direction of a session, seen from the internal control function.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Direction {
+    Transmit,
+    Receive,
+}
+
+/// A TP or ETP session stored in a [`SessionTable`].
+pub trait TransportSession {
+    fn key(&self) -> SessionKey;
+    fn direction(&self) -> Direction;
+}
+
+/// The running sessions of a transport protocol manager.
+///
+/// Limits the number of sessions running at the same time and keeps the messages that can not be send yet
+/// in a backlog, until a session is available and their source and destination pair is free again.
+pub struct SessionTable<S> {
+    sessions: Vec<S>,
+    max_sessions: usize,
+    backlog: VecDeque<CanMessage>,
+}
+
+impl<S: TransportSession> SessionTable<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of transmit and receive sessions that can run at the same time.
+    pub fn set_max_sessions(&mut self, max_sessions: usize) {
+        self.max_sessions = max_sessions;
+    }
+
+    /// Returns the keys and directions of all the running sessions.
+    #[cfg(test)]
+    pub fn keys(&self) -> Vec<(SessionKey, Direction)> {
+        self.sessions.iter()
+            .map(|session| (session.key(), session.direction()))
+            .collect()
+    }
+
+    /// Returns the number of messages waiting in the backlog.
+    #[cfg(test)]
+    pub fn backlog_len(&self) -> usize {
+        self.backlog.len()
+    }
+
+    pub fn is_session_available(&self) -> bool {
+        self.sessions.len() < self.max_sessions
+    }
+
+    pub fn is_transmitting(&self, source: Address, destination: Address) -> bool {
+        self.sessions.iter().any(|session| {
+            session.direction() == Direction::Transmit && session.key().is_between(source, destination)
+        })
+    }
+
+    pub fn push(&mut self, session: S) {
+        self.sessions.push(session);
+    }
+
+    pub fn remove(&mut self, index: usize) -> S {
+        self.sessions.remove(index)
+    }
+
+    /// Keeps only the sessions for which `f` returns true.
+    pub fn retain_mut(&mut self, f: impl FnMut(&mut S) -> bool) {
+        self.sessions.retain_mut(f);
+    }
+
+    /// Finds the transmit session a received control message answers to.
+    pub fn transmit_session_index(&self, message: &CanMessage) -> Option<usize> {
+        self.sessions.iter().position(|session| {
+            session.direction() == Direction::Transmit
+                && session.key().is_between(message.destination_address(), message.source_address())
+        })
+    }
+
+    /// Finds the receive session a received message belongs to.
+    pub fn receive_session_index(&self, message: &CanMessage) -> Option<usize> {
+        self.sessions.iter().position(|session| {
+            session.direction() == Direction::Receive
+                && session.key().is_between(message.source_address(), message.destination_address())
+        })
+    }
+
+    /// Returns the message if a session can be opened for it right away, otherwise stores it in the backlog.
+    ///
+    /// Only one session per source and destination pair is allowed.
+    pub fn admit(&mut self, message: CanMessage) -> Option<CanMessage> {
+        if self.is_transmitting(message.source_address(), message.destination_address())
+            || !self.is_session_available()
+        {
+            self.backlog.push_back(message);
+            None
+        } else {
+            Some(message)
+        }
+    }
+
+    /// Takes the first backlogged message that a session can be opened for.
+    pub fn next_from_backlog(&mut self) -> Option<CanMessage> {
+        if !self.is_session_available() {
+            return None;
+        }
+        let index = self.backlog.iter().position(|message| {
+            !self.is_transmitting(message.source_address(), message.destination_address())
+        })?;
+        self.backlog.remove(index)
+    }
+}
+
+impl<S> Default for SessionTable<S> {
+    fn default() -> Self {
+        Self {
+            sessions: Vec::new(),
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            backlog: VecDeque::new(),
+        }
+    }
+}
+
+impl<S> Index<usize> for SessionTable<S> {
+    type Output = S;
+
+    fn index(&self, index: usize) -> &S {
+        &self.sessions[index]
+    }
+}
+
+impl<S> IndexMut<usize> for SessionTable<S> {
+    fn index_mut(&mut self, index: usize) -> &mut S {
+        &mut self.sessions[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanPriority;
+
+    const LOCAL: Address = Address(0x80);
+    const REMOTE: Address = Address(0x26);
+
+    struct TestSession {
+        key: SessionKey,
+        direction: Direction,
+    }
+
+    impl TransportSession for TestSession {
+        fn key(&self) -> SessionKey {
+            self.key
+        }
+
+        fn direction(&self) -> Direction {
+            self.direction
+        }
+    }
+
+    fn message(source: Address, destination: Address) -> CanMessage {
+        CanMessage::new(CanPriority::PriorityDefault6, ParameterGroupNumber::ProprietaryA, source, destination, &[0; 20])
+    }
+
+    fn open(table: &mut SessionTable<TestSession>, message: &CanMessage) {
+        table.push(TestSession {
+            key: SessionKey::new(message.source_address(), message.destination_address(), message.pgn()),
+            direction: Direction::Transmit,
+        });
+    }
+
+    #[test]
+    fn number_of_packets() {
+        assert_eq!(number_of_packets_for(9), 2);
+        assert_eq!(number_of_packets_for(14), 2);
+        assert_eq!(number_of_packets_for(15), 3);
+        assert_eq!(number_of_packets_for(1785), 255);
+    }
+
+    #[test]
+    fn session_limit() {
+        let mut table = SessionTable::new();
+        table.set_max_sessions(2);
+
+        for destination in [Address(0x26), Address(0x27)] {
+            let message = table.admit(message(LOCAL, destination)).unwrap();
+            open(&mut table, &message);
+        }
+        assert!(!table.is_session_available());
+        assert!(table.admit(message(LOCAL, Address(0x28))).is_none());
+        assert_eq!(table.backlog_len(), 1);
+
+        // The backlog is only drained once a session is available again.
+        assert!(table.next_from_backlog().is_none());
+        table.remove(0);
+        assert_eq!(table.next_from_backlog().unwrap().destination_address(), Address(0x28));
+        assert_eq!(table.backlog_len(), 0);
+    }
+
+    #[test]
+    fn backlog_per_destination() {
+        let mut table = SessionTable::new();
+        let first = table.admit(message(LOCAL, REMOTE)).unwrap();
+        open(&mut table, &first);
+
+        // A second message to the same destination waits, one to another destination does not.
+        assert!(table.admit(message(LOCAL, REMOTE)).is_none());
+        assert!(table.admit(message(LOCAL, Address(0x27))).is_some());
+        assert!(table.next_from_backlog().is_none());
+
+        table.retain_mut(|session| !session.key.is_between(LOCAL, REMOTE));
+        assert_eq!(table.next_from_backlog().unwrap().destination_address(), REMOTE);
+        assert!(table.next_from_backlog().is_none());
+    }
+
+    #[test]
+    fn session_lookup() {
+        let mut table = SessionTable::new();
+        open(&mut table, &message(LOCAL, REMOTE));
+        table.push(TestSession {
+            key: SessionKey::new(REMOTE, LOCAL, ParameterGroupNumber::ProprietaryA),
+            direction: Direction::Receive,
+        });
+
+        // Control messages from the remote answer the transmit session, data from the remote belongs to the receive session.
+        let from_remote = message(REMOTE, LOCAL);
+        assert_eq!(table.transmit_session_index(&from_remote), Some(0));
+        assert_eq!(table.receive_session_index(&from_remote), Some(1));
+        assert_eq!(table.transmit_session_index(&message(Address(0x27), LOCAL)), None);
+        assert_eq!(table.keys()[1], (SessionKey::new(REMOTE, LOCAL, ParameterGroupNumber::ProprietaryA), Direction::Receive));
+    }
+
+    #[test]
+    fn session_key() {
+        let key = SessionKey::new(Address(0x80), Address(0x26), ParameterGroupNumber::ProprietaryA);
+        assert!(key.is_between(Address(0x80), Address(0x26)));
+        assert!(!key.is_between(Address(0x26), Address(0x80)));
+        assert_ne!(key, SessionKey::new(Address(0x80), Address(0x26), ParameterGroupNumber::AddressClaim));
+    }
+}
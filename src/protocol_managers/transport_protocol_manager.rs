@@ -1,29 +1,32 @@
+use core::time::Duration;
 
-use crate::{drivers::CanDriverTrait, isobus::IsobusAddress};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 
-use super::{TpAbortReasons, PDU, PGN};
+use crate::hardware_integration::{TimeDriver, TimeDriverTrait};
+use crate::{Address, CanMessage, CanNetworkManager, CanPriority, ParameterGroupNumber};
 
-const BACKLOG_SIZE: usize = 8;
+use super::{number_of_packets_for, Direction, SessionKey, SessionTable, TransportSession, BYTES_PER_PACKET};
 
-const TP_TIMEOUT_T1: u64 = 750;
-const TP_TIMEOUT_T2: u64 = 1250;
-const TP_TIMEOUT_T3: u64 = 1750;
-const TP_TIMEOUT_T4: u64 = 1050;
+const TP_TIMEOUT_T1: Duration = Duration::from_millis(750);
+const TP_TIMEOUT_T2: Duration = Duration::from_millis(1250);
+const TP_TIMEOUT_T3: Duration = Duration::from_millis(1250);
+const TP_TIMEOUT_T4: Duration = Duration::from_millis(1050);
+const TP_BAM_PACKET_DELAY: Duration = Duration::from_millis(50); //< ISO11783-3 requires 50 to 200 ms between BAM data packets
 
-#[derive(Debug, PartialEq)]
-enum State {
-    Idle,
-    Sending,
-    Receiving,
-}
+const MAX_NUMBER_OF_PACKETS_TO_SEND: u8 = 16;
+const MAX_NUMBER_OF_RETRANSMITS: u8 = 2;
+const MIN_MESSAGE_SIZE: usize = 9;
+const MAX_MESSAGE_SIZE: usize = 1785;
 
+/// Handles ISO11783-3 Transport Protocol (TP) sessions.
+///
+/// Messages of 9 up to 1785 bytes are send using connection mode data transfer (RTS/CTS/EoMA)
+/// when they have a specific destination, or using the broadcast announce message (BAM) when
+/// send to global.
 pub struct TransportProtocolManager {
-    backlog: Deque<CanMessage, BACKLOG_SIZE>,
-    message_to_send: Option<CanMessage>,
-    // timeout_time: u64,
-    // receive_buffer: Vec<u8>,
-    // receive_pgn: Option<PGN>,
-    // receive_nr_of_packets: u8,
+    sessions: SessionTable<Session>,
+    aborts_to_send: VecDeque<CanMessage>,
 }
 
 impl TransportProtocolManager {
@@ -31,271 +34,901 @@ impl TransportProtocolManager {
         Self::default()
     }
 
-    pub fn send(&mut self, can: &mut Box<dyn CanDriverTrait>, pdu: PDU, time: u64) {
-        // Start a new sending connection or
-        // if we are already in a connection, store the PDU in the backlog.
-        match self.state() {
-            State::Idle => {
-                self.open_sending_connection(can, pdu, time);
-            }
-            State::Sending | State::Receiving => {
-                self.backlog.push_back(pdu);
-            }
+    /// Sets the maximum number of transmit and receive sessions that can run at the same time.
+    pub fn set_max_sessions(&mut self, max_sessions: usize) {
+        self.sessions.set_max_sessions(max_sessions);
+    }
+
+    pub fn send(&mut self, message: CanMessage) {
+        if !(MIN_MESSAGE_SIZE..=MAX_MESSAGE_SIZE).contains(&message.len()) {
+            log::error!("[TP]: Can not send a message of {} bytes", message.len());
+            return;
+        }
+
+        if let Some(message) = self.sessions.admit(message) {
+            self.open_connection(message);
         }
     }
 
-    // All pdu's to process are global or ment for us.
-    pub fn process(
-        &mut self,
-        can: &mut Box<dyn CanDriverTrait>,
-        claimed_address: IsobusAddress,
-        pdu: Option<PDU>,
-        time: u64,
-    ) -> Option<PDU> {
-        // If connected check if a timeout has occurred.
-        if self.is_connected() {
-            self.process_timeout(can, claimed_address, time);
+    /// Sends all pending control and data messages and checks the session timeouts.
+    pub fn update(&mut self, network_manager: &mut CanNetworkManager) {
+        while let Some(message) = self.aborts_to_send.pop_front() {
+            network_manager.send_can_message(message);
         }
 
-        // Statements after this need to process a PDU.
-        // Return if no pdu was given
-        let pdu = match pdu {
-            Some(pdu) => pdu,
-            None => return None,
-        };
+        self.sessions.retain_mut(|session| session.update(network_manager));
 
-        // Received a request to send meant for us.
-        if pdu.is_tp_request_to_send() {
-            // When idling, accept the request to send and send a clear to send message.
-            if self.state() == State::Idle {
-                let data: [u8; 8] = pdu.data::<8>();
-
-                let nr_of_bytes: u16 = u16::from_le_bytes([data[1], data[2]]);
-                self.receive_buffer = vec![0xFF; nr_of_bytes as usize];
-                self.receive_nr_of_packets = u8::min(data[3], data[4]);
-                let packet_pgn: PGN = PGN::from_le_bytes([data[5], data[6], data[7]]);
-
-                can.write(
-                    PDU::new_tp_clear_to_send(
-                        self.receive_nr_of_packets,
-                        1,
-                        packet_pgn,
-                        pdu.source_address(),
-                        claimed_address,
-                    )
-                    .into(),
-                );
+        // Start sending backlogged messages once their source and destination pair is free again.
+        while let Some(message) = self.sessions.next_from_backlog() {
+            self.open_connection(message);
+        }
+    }
 
-                self.receive_pgn = Some(packet_pgn);
-                self.timeout_time = TP_TIMEOUT_T2;
-            } else {
-                // If we are already in a connection, abort the new connection.
-                can.write(
-                    PDU::new_tp_connection_abort(
-                        TpAbortReasons::AlreadyConnected,
-                        pdu.pgn(),
-                        pdu.source_address(),
-                        claimed_address,
-                    )
-                    .into(),
-                );
+    /// Processes a TP connection management or data transfer message.
+    ///
+    /// Returns the reassembled message once all the data of a session is received.
+    pub fn process_can_message(&mut self, message: &CanMessage) -> Option<CanMessage> {
+        match message.pgn() {
+            ParameterGroupNumber::TransportProtocolCommand => {
+                if let Ok(control_byte) = message.get_u8_at(0).try_into() {
+                    match control_byte {
+                        TpCmControlByte::RequestToSend => self.process_request_to_send(message),
+                        TpCmControlByte::BroadcastAnnounceMessage => self.process_broadcast_announce(message),
+                        TpCmControlByte::ClearToSend => self.process_clear_to_send(message),
+                        TpCmControlByte::EndOfMessageAcknowledgement => {
+                            if let Some(index) = self.sessions.transmit_session_index(message) {
+                                log::debug!("[TP]: Send {} bytes to {}", self.sessions[index].message_size, message.source_address());
+                                self.sessions.remove(index);
+                            }
+                        }
+                        TpCmControlByte::ConnectionAbort => {
+                            let reason = AbortReason::from(message.get_u8_at(1));
+                            let pgn = message.get_pgn_at(5);
+                            if let Some(index) = self.sessions.transmit_session_index(message)
+                                .filter(|&index| self.sessions[index].key.pgn == pgn)
+                            {
+                                log::error!("[TP]: Transmit session aborted by {}: {:?}", message.source_address(), reason);
+                                self.sessions.remove(index);
+                            } else if let Some(index) = self.sessions.receive_session_index(message)
+                                .filter(|&index| self.sessions[index].key.pgn == pgn)
+                            {
+                                log::error!("[TP]: Receive session aborted by {}: {:?}", message.source_address(), reason);
+                                self.sessions.remove(index);
+                            }
+                        }
+                    }
+                }
+                None
             }
+            ParameterGroupNumber::TransportProtocolData => self.process_data_transfer(message),
+            _ => None,
         }
+    }
 
-        // Received a clear to send meant for us.
-        if pdu.is_tp_clear_to_send() {
-            if self.state() == State::Sending {
-                let data: [u8; 8] = pdu.data::<8>();
-                let nr_of_packets = data[1];
-                let next_packet = data[2];
+    fn process_request_to_send(&mut self, message: &CanMessage) {
+        if message.is_address_global() {
+            return;
+        }
 
-                if nr_of_packets == 0 {
-                    self.timeout_time = TP_TIMEOUT_T4;
-                } else {
-                    self.send_pdu_data(
-                        can,
-                        next_packet,
-                        nr_of_packets,
-                        pdu.source_address(),
-                        claimed_address,
-                    );
-                    self.timeout_time = TP_TIMEOUT_T3;
-                }
-            }
+        let pgn = message.get_pgn_at(5);
+        let message_size = message.get_u16_at(1) as usize;
+        let number_of_packets = message.get_u8_at(3);
+
+        // A new RTS from a sender we are already receiving from is not allowed.
+        if self.sessions.receive_session_index(message).is_some() || !self.sessions.is_session_available() {
+            self.send_abort(message.destination_address(), message.source_address(), pgn, AbortReason::AlreadyConnected);
+            return;
         }
 
-        // Received an end of message meant for us.
-        if pdu.is_tp_end_of_message_acknowledge() {
-            if self.state() == State::Sending {
-                let finished_pdu = self.pdu_to_send.take();
-                self.close_connection();
+        if message_size > MAX_MESSAGE_SIZE {
+            self.send_abort(message.destination_address(), message.source_address(), pgn, AbortReason::MessageTooLarge);
+            return;
+        }
+        if message_size < MIN_MESSAGE_SIZE || number_of_packets as usize != number_of_packets_for(message_size) {
+            self.send_abort(message.destination_address(), message.source_address(), pgn, AbortReason::Other);
+            return;
+        }
 
-                // If we have PDUs in the backlog, start a new connection.
-                if let Some(pdu) = self.backlog.pop_front() {
-                    self.open_sending_connection(can, pdu, time);
-                }
+        let mut session = Session::new_receive(message, pgn, message_size, number_of_packets);
+        session.packets_per_clear_to_send = message.get_u8_at(4).clamp(1, MAX_NUMBER_OF_PACKETS_TO_SEND);
+        session.state = State::SendClearToSend;
+        self.sessions.push(session);
+    }
 
-                return finished_pdu;
-            }
+    fn process_broadcast_announce(&mut self, message: &CanMessage) {
+        if !message.is_address_global() {
+            return;
+        }
+
+        let pgn = message.get_pgn_at(5);
+        let message_size = message.get_u16_at(1) as usize;
+        let number_of_packets = message.get_u8_at(3);
+
+        if !(MIN_MESSAGE_SIZE..=MAX_MESSAGE_SIZE).contains(&message_size)
+            || number_of_packets as usize != number_of_packets_for(message_size)
+        {
+            return;
+        }
+
+        // A new BAM from the same source replaces the previous one.
+        if let Some(index) = self.sessions.receive_session_index(message) {
+            log::warn!("[TP]: BAM from {} restarted before it was completed", message.source_address());
+            self.sessions.remove(index);
         }
 
-        // Received an data transfer meant for us.
-        if pdu.is_tp_data_transfer() {
-            if self.state() == State::Receiving {
-                let data: [u8; 8] = pdu.data::<8>();
-                let packet_nr = data[0];
+        // A BAM can not be aborted, so it is ignored when there is no session available.
+        if !self.sessions.is_session_available() {
+            log::warn!("[TP]: BAM from {} ignored, all sessions are in use", message.source_address());
+            return;
+        }
+
+        let mut session = Session::new_receive(message, pgn, message_size, number_of_packets);
+        session.packets_per_clear_to_send = number_of_packets;
+        session.timeout = TimeDriver::time_elapsed() + TP_TIMEOUT_T1;
+        session.state = State::WaitForDataTransfer;
+        self.sessions.push(session);
+    }
+
+    fn process_clear_to_send(&mut self, message: &CanMessage) {
+        let index = match self.sessions.transmit_session_index(message) {
+            Some(index) => index,
+            None => return,
+        };
+        let session = &mut self.sessions[index];
+
+        if session.key.pgn != message.get_pgn_at(5) {
+            session.state = State::SendConnectionAbort(AbortReason::Other);
+            return;
+        }
+
+        match session.state {
+            State::WaitForClearToSend | State::WaitForEndOfMessageAcknowledgement => {
+                let number_of_packets_to_send = message.get_u8_at(1);
+                let next_packet_number = message.get_u8_at(2);
+
+                // A CTS for zero packets means the receiver wants us to hold the connection open.
+                if number_of_packets_to_send == 0 {
+                    session.timeout = TimeDriver::time_elapsed() + TP_TIMEOUT_T4;
+                    return;
+                }
 
-                for i in 0..7 {
-                    self.receive_buffer[((packet_nr - 1) * 7 + i) as usize] =
-                        data[(i + 1) as usize];
+                if next_packet_number == 0 || next_packet_number > session.number_of_packets {
+                    session.state = State::SendConnectionAbort(AbortReason::Other);
+                    return;
                 }
 
-                self.timeout_time = TP_TIMEOUT_T1;
-
-                if self.receive_nr_of_packets == packet_nr {
-                    if let Some(pgn) = self.receive_pgn {
-                        can.write(
-                            PDU::new_tp_end_of_message_acknowledge(
-                                self.receive_buffer.len() as u16,
-                                self.receive_nr_of_packets,
-                                pgn,
-                                pdu.source_address(),
-                                claimed_address,
-                            )
-                            .into(),
-                        );
+                // A CTS for packets we already send is a request to retransmit them.
+                if next_packet_number < session.next_packet_number {
+                    session.retransmit_count += 1;
+                    if session.retransmit_count > MAX_NUMBER_OF_RETRANSMITS {
+                        session.state = State::SendConnectionAbort(AbortReason::RetransmitLimitReached);
+                        return;
                     }
+                    log::warn!("[TP]: Retransmit from packet {} requested by {}", next_packet_number, message.source_address());
+                }
 
-                    let finished_pdu = self.pdu_to_send.take();
-                    self.close_connection();
+                session.next_packet_number = next_packet_number;
+                session.last_packet_number = u8::min(
+                    next_packet_number.saturating_add(number_of_packets_to_send - 1),
+                    session.number_of_packets,
+                );
+                session.state = State::SendDataTransfer;
+            }
+            State::SendDataTransfer => {
+                session.state = State::SendConnectionAbort(AbortReason::DataTransferInProgress);
+            }
+            _ => {}
+        }
+    }
 
-                    return finished_pdu;
-                }
+    fn process_data_transfer(&mut self, message: &CanMessage) -> Option<CanMessage> {
+        let index = self.sessions.receive_session_index(message)?;
+        let session = &mut self.sessions[index];
+
+        if session.state != State::WaitForDataTransfer {
+            if !session.is_broadcast() {
+                session.state = State::SendConnectionAbort(AbortReason::UnexpectedDataTransfer);
+            }
+            return None;
+        }
+
+        let sequence_number = message.get_u8_at(0);
+        if sequence_number != session.next_packet_number {
+            if session.is_broadcast() {
+                log::error!("[TP]: BAM from {} received a bad sequence number", message.source_address());
+                self.sessions.remove(index);
+            } else if sequence_number == session.next_packet_number.wrapping_sub(1) {
+                session.state = State::SendConnectionAbort(AbortReason::DuplicateSequenceNumber);
+            } else {
+                session.state = State::SendConnectionAbort(AbortReason::BadSequenceNumber);
+            }
+            return None;
+        }
+
+        let offset = (sequence_number as usize - 1) * BYTES_PER_PACKET;
+        let length = usize::min(BYTES_PER_PACKET, session.message_size - offset);
+        if message.len() <= length {
+            return None;
+        }
+        session.data[offset..offset + length].copy_from_slice(&message.data()[1..=length]);
+        session.next_packet_number = sequence_number.wrapping_add(1);
+        session.timeout = TimeDriver::time_elapsed() + TP_TIMEOUT_T1;
+
+        if sequence_number == session.number_of_packets {
+            let message = session.reassembled_message();
+            if session.is_broadcast() {
+                self.sessions.remove(index);
+            } else {
+                session.state = State::SendEndOfMessageAcknowledgement;
             }
+            return Some(message);
         }
 
+        if sequence_number == session.last_packet_number {
+            session.state = State::SendClearToSend;
+        }
         None
     }
 
-    fn process_timeout(
-        &mut self,
-        can: &mut Box<dyn CanDriverTrait>,
-        claimed_address: IsobusAddress,
-        time: u64,
-    ) {
-        if let Some(pdu_to_send) = &self.pdu_to_send {
-            if time > self.timeout_time && self.state() != State::Idle {
-                can.write(
-                    PDU::new_tp_connection_abort(
-                        TpAbortReasons::Timeout,
-                        pdu_to_send.pgn(),
-                        pdu_to_send.destination_address(),
-                        claimed_address,
-                    )
-                    .into(),
-                );
-                self.close_connection();
-            }
+    fn open_connection(&mut self, message: CanMessage) {
+        self.sessions.push(Session::new_transmit(message));
+    }
+
+    fn send_abort(&mut self, source: Address, destination: Address, pgn: ParameterGroupNumber, abort_reason: AbortReason) {
+        self.aborts_to_send.push_back(abort_message(source, destination, pgn, abort_reason));
+    }
+}
+
+impl Default for TransportProtocolManager {
+    fn default() -> Self {
+        Self {
+            sessions: SessionTable::new(),
+            aborts_to_send: VecDeque::new(),
         }
     }
+}
+
+struct Session {
+    direction: Direction,
+    state: State,
+    timeout: Duration,
+
+    key: SessionKey,
+    priority: CanPriority,
+    data: Vec<u8>,
+    message_size: usize,
+
+    number_of_packets: u8,
+    packets_per_clear_to_send: u8,
+    next_packet_number: u8,
+    last_packet_number: u8,
+    retransmit_count: u8,
+}
+
+impl TransportSession for Session {
+    fn key(&self) -> SessionKey {
+        self.key
+    }
 
-    // TODO: Cleanup connection creation
-    fn open_sending_connection(&mut self, can: &mut Box<dyn CanDriverTrait>, pdu: PDU, _time: u64) {
-        let number_of_bytes = pdu.data_len() as u16;
-        let number_of_packets = ((number_of_bytes - 1) / 7) as u8;
-
-        if pdu.is_pdu2() {
-            can.write(
-                PDU::new_tp_broadcast_announce_message(
-                    number_of_bytes,
-                    number_of_packets,
-                    pdu.pgn(),
-                    pdu.source_address(),
-                )
-                .into(),
-            );
-            self.send_pdu_data(
-                can,
-                1,
-                number_of_packets,
-                IsobusAddress::GLOBAL,
-                pdu.source_address(),
-            );
-        } else {
-            can.write(
-                PDU::new_tp_request_to_send(
-                    number_of_bytes,
-                    number_of_packets,
-                    pdu.pgn(),
-                    pdu.destination_address(),
-                    pdu.source_address(),
-                )
-                .into(),
-            );
+    fn direction(&self) -> Direction {
+        self.direction
+    }
+}
+
+impl Session {
+    fn new_transmit(message: CanMessage) -> Self {
+        let number_of_packets = number_of_packets_for(message.len()) as u8;
+        Self {
+            direction: Direction::Transmit,
+            state: if message.destination_address() == Address::GLOBAL {
+                State::SendBroadcastAnnounce
+            } else {
+                State::SendRequestToSend
+            },
+            timeout: Duration::MAX,
+            key: SessionKey::new(message.source_address(), message.destination_address(), message.pgn()),
+            priority: message.priority(),
+            data: message.data().into(),
+            message_size: message.len(),
+            number_of_packets,
+            packets_per_clear_to_send: 0xFF,
+            next_packet_number: 1,
+            last_packet_number: number_of_packets,
+            retransmit_count: 0,
         }
+    }
+
+    fn new_receive(message: &CanMessage, pgn: ParameterGroupNumber, message_size: usize, number_of_packets: u8) -> Self {
+        Self {
+            direction: Direction::Receive,
+            state: State::WaitForDataTransfer,
+            timeout: Duration::MAX,
+            key: SessionKey::new(message.source_address(), message.destination_address(), pgn),
+            priority: message.priority(),
+            data: alloc::vec![0xFF; message_size],
+            message_size,
+            number_of_packets,
+            packets_per_clear_to_send: number_of_packets,
+            next_packet_number: 1,
+            last_packet_number: 0,
+            retransmit_count: 0,
+        }
+    }
 
-        self.timeout_time = TP_TIMEOUT_T3;
-        self.pdu_to_send = Some(pdu);
-    }
-
-    fn close_connection(&mut self) {
-        self.pdu_to_send = None;
-        self.timeout_time = u64::MAX;
-        self.receive_buffer.clear();
-        self.receive_pgn = None;
-        self.receive_nr_of_packets = 0;
-    }
-
-    fn send_pdu_data(
-        &self,
-        can: &mut Box<dyn CanDriverTrait>,
-        next_packet: u8,
-        number_of_packets: u8,
-        da: IsobusAddress,
-        sa: IsobusAddress,
-    ) {
-        if let Some(pdu_to_send) = &self.pdu_to_send {
-            let chunks: Vec<&[u8]> = pdu_to_send.data_raw().chunks(7).collect();
-            for i in 0..number_of_packets {
-                can.write(
-                    PDU::new_tp_data_transfer(
-                        next_packet + i,
-                        chunks[(next_packet - 1 + i) as usize],
-                        da,
-                        sa,
-                    )
-                    .into(),
+    fn is_broadcast(&self) -> bool {
+        self.key.destination == Address::GLOBAL
+    }
+
+    /// Our own address in this session.
+    fn local_address(&self) -> Address {
+        match self.direction {
+            Direction::Transmit => self.key.source,
+            Direction::Receive => self.key.destination,
+        }
+    }
+
+    /// The address of the other control function in this session.
+    fn remote_address(&self) -> Address {
+        match self.direction {
+            Direction::Transmit => self.key.destination,
+            Direction::Receive => self.key.source,
+        }
+    }
+
+    fn reassembled_message(&self) -> CanMessage {
+        CanMessage::new(self.priority, self.key.pgn, self.key.source, self.key.destination, &self.data)
+    }
+
+    /// Update based on the current state, returns false when the session is finished.
+    fn update(&mut self, network_manager: &mut CanNetworkManager) -> bool {
+        match self.state {
+            State::SendRequestToSend => {
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = TpCmControlByte::RequestToSend as u8;
+                data[1..=2].copy_from_slice(&(self.message_size as u16).to_le_bytes());
+                data[3] = self.number_of_packets;
+                data[4] = self.packets_per_clear_to_send;
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
+
+                self.timeout = TimeDriver::time_elapsed() + TP_TIMEOUT_T3;
+                self.state = State::WaitForClearToSend;
+            }
+            State::SendBroadcastAnnounce => {
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = TpCmControlByte::BroadcastAnnounceMessage as u8;
+                data[1..=2].copy_from_slice(&(self.message_size as u16).to_le_bytes());
+                data[3] = self.number_of_packets;
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
+
+                self.timeout = TimeDriver::time_elapsed() + TP_BAM_PACKET_DELAY;
+                self.state = State::SendDataTransfer;
+            }
+            State::WaitForClearToSend => {
+                if TimeDriver::time_elapsed() > self.timeout {
+                    log::error!("[TP]: Wait For Clear To Send Timeout");
+                    self.state = State::SendConnectionAbort(AbortReason::Timeout);
+                }
+            }
+            State::SendDataTransfer => {
+                if self.is_broadcast() {
+                    // Broadcast data is paced, send a single packet each time the delay expires.
+                    if TimeDriver::time_elapsed() < self.timeout {
+                        return true;
+                    }
+                    self.send_data_transfer(network_manager, self.next_packet_number);
+                    if self.next_packet_number == self.number_of_packets {
+                        return false;
+                    }
+                    self.next_packet_number += 1;
+                    self.timeout = TimeDriver::time_elapsed() + TP_BAM_PACKET_DELAY;
+                    return true;
+                }
+
+                for packet_number in self.next_packet_number..=self.last_packet_number {
+                    self.send_data_transfer(network_manager, packet_number);
+                }
+                self.next_packet_number = self.last_packet_number.saturating_add(1);
+
+                self.timeout = TimeDriver::time_elapsed() + TP_TIMEOUT_T3;
+                self.state = if self.last_packet_number == self.number_of_packets {
+                    State::WaitForEndOfMessageAcknowledgement
+                } else {
+                    State::WaitForClearToSend
+                };
+            }
+            State::WaitForEndOfMessageAcknowledgement => {
+                if TimeDriver::time_elapsed() > self.timeout {
+                    log::error!("[TP]: Wait For End Of Message Acknowledgement Timeout");
+                    self.state = State::SendConnectionAbort(AbortReason::Timeout);
+                }
+            }
+            State::SendClearToSend => {
+                let number_of_packets_to_send = u8::min(
+                    self.packets_per_clear_to_send,
+                    self.number_of_packets - self.next_packet_number + 1,
                 );
+
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = TpCmControlByte::ClearToSend as u8;
+                data[1] = number_of_packets_to_send;
+                data[2] = self.next_packet_number;
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
+
+                self.last_packet_number = self.next_packet_number + number_of_packets_to_send - 1;
+                self.timeout = TimeDriver::time_elapsed() + TP_TIMEOUT_T2;
+                self.state = State::WaitForDataTransfer;
+            }
+            State::WaitForDataTransfer => {
+                if TimeDriver::time_elapsed() > self.timeout {
+                    log::error!("[TP]: Wait For Data Timeout");
+                    if self.is_broadcast() {
+                        return false;
+                    }
+                    self.state = State::SendConnectionAbort(AbortReason::Timeout);
+                }
+            }
+            State::SendEndOfMessageAcknowledgement => {
+                let mut data: [u8; 8] = [0xFF; 8];
+                data[0] = TpCmControlByte::EndOfMessageAcknowledgement as u8;
+                data[1..=2].copy_from_slice(&(self.message_size as u16).to_le_bytes());
+                data[3] = self.number_of_packets;
+                data[5..=7].copy_from_slice(&self.key.pgn.as_bytes());
+                self.send_connection_management(network_manager, &data);
+
+                return false;
+            }
+            State::SendConnectionAbort(abort_reason) => {
+                network_manager.send_can_message(abort_message(
+                    self.local_address(),
+                    self.remote_address(),
+                    self.key.pgn,
+                    abort_reason,
+                ));
+
+                return false;
             }
         }
+        true
+    }
+
+    fn send_connection_management(&self, network_manager: &mut CanNetworkManager, data: &[u8; 8]) {
+        network_manager.send_can_message(CanMessage::new(
+            CanPriority::PriorityLowest7,
+            ParameterGroupNumber::TransportProtocolCommand,
+            self.local_address(),
+            self.remote_address(),
+            data,
+        ));
     }
 
-    fn state(&self) -> State {
-        if self.pdu_to_send.is_some() {
-            return State::Sending;
+    fn send_data_transfer(&self, network_manager: &mut CanNetworkManager, packet_number: u8) {
+        let offset = (packet_number as usize - 1) * BYTES_PER_PACKET;
+        let length = usize::min(BYTES_PER_PACKET, self.message_size - offset);
+
+        let mut data: [u8; 8] = [0xFF; 8];
+        data[0] = packet_number;
+        data[1..=length].copy_from_slice(&self.data[offset..offset + length]);
+
+        network_manager.send_can_message(CanMessage::new(
+            CanPriority::PriorityLowest7,
+            ParameterGroupNumber::TransportProtocolData,
+            self.local_address(),
+            self.remote_address(),
+            &data,
+        ));
+    }
+}
+
+
+fn abort_message(source: Address, destination: Address, pgn: ParameterGroupNumber, abort_reason: AbortReason) -> CanMessage {
+    let mut data: [u8; 8] = [0xFF; 8];
+    data[0] = TpCmControlByte::ConnectionAbort as u8;
+    data[1] = abort_reason as u8;
+    data[5..=7].copy_from_slice(&pgn.as_bytes());
+
+    CanMessage::new(
+        CanPriority::PriorityLowest7,
+        ParameterGroupNumber::TransportProtocolCommand,
+        source,
+        destination,
+        &data,
+    )
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    // Transmit states
+    SendRequestToSend,
+    SendBroadcastAnnounce,
+    WaitForClearToSend,
+    SendDataTransfer,
+    WaitForEndOfMessageAcknowledgement,
+    // Receive states
+    SendClearToSend,
+    WaitForDataTransfer,
+    SendEndOfMessageAcknowledgement,
+
+    SendConnectionAbort(AbortReason),
+}
+
+/// Enumerates the multiplexor byte values for TpCm Control Byte
+#[repr(u8)]
+#[derive(Debug, PartialEq)]
+enum TpCmControlByte {
+    RequestToSend = 0x10,
+    ClearToSend = 0x11,
+    EndOfMessageAcknowledgement = 0x13,
+    BroadcastAnnounceMessage = 0x20,
+    ConnectionAbort = 0xFF,
+}
+
+impl TryFrom<u8> for TpCmControlByte {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x10 => Ok(Self::RequestToSend),
+            0x11 => Ok(Self::ClearToSend),
+            0x13 => Ok(Self::EndOfMessageAcknowledgement),
+            0x20 => Ok(Self::BroadcastAnnounceMessage),
+            0xFF => Ok(Self::ConnectionAbort),
+            _ => Err(()),
         }
+    }
+}
 
-        if self.receive_pgn.is_some() {
-            return State::Receiving;
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum AbortReason {
+    AlreadyConnected = 0x01,        //< Already in one or more connection-managed sessions and cannot support another
+    Terminated = 0x02,              //< System resources were needed for another task so this connection managed session was terminated
+    Timeout = 0x03,                 //< A timeout occurred and this is the connection abort to close the session
+    DataTransferInProgress = 0x04,  //< CTS messages received when data transfer is in progress
+    RetransmitLimitReached = 0x05,  //< Maximum retransmit request limit reached
+    UnexpectedDataTransfer = 0x06,  //< Unexpected data transfer packet
+    BadSequenceNumber = 0x07,       //< Bad sequence number (and software is not able to recover)
+    DuplicateSequenceNumber = 0x08, //< Duplicate sequence number (and software is not able to recover)
+    MessageTooLarge = 0x09,         //< Total message size is greater than 1785 bytes
+    Other = 0xFA,                   //< Any other reason
+}
+
+impl From<u8> for AbortReason {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => Self::AlreadyConnected,
+            0x02 => Self::Terminated,
+            0x03 => Self::Timeout,
+            0x04 => Self::DataTransferInProgress,
+            0x05 => Self::RetransmitLimitReached,
+            0x06 => Self::UnexpectedDataTransfer,
+            0x07 => Self::BadSequenceNumber,
+            0x08 => Self::DuplicateSequenceNumber,
+            0x09 => Self::MessageTooLarge,
+            _ => Self::Other,
         }
+    }
+}
+
+#[cfg(all(test, feature = "mock_can_driver"))]
+mod tests {
+    use super::*;
+    use crate::hardware_integration::CanDriver;
+
+    const LOCAL: Address = Address(0x80);
+    const REMOTE: Address = Address(0x26);
+    const PGN: ParameterGroupNumber = ParameterGroupNumber::ProprietaryA;
 
-        State::Idle
+    fn setup() -> (TransportProtocolManager, CanNetworkManager, CanDriver) {
+        let driver = CanDriver::new();
+        (TransportProtocolManager::new(), CanNetworkManager::new(driver.clone()), driver)
     }
 
-    fn is_connected(&self) -> bool {
-        self.state() == State::Sending || self.state() == State::Receiving
+    fn sent(driver: &CanDriver) -> Vec<CanMessage> {
+        driver.take_written_frames().into_iter().map(CanMessage::from).collect()
     }
-}
 
-impl Default for TransportProtocolManager {
-    fn default() -> Self {
-        Self {
-            backlog: VecDeque::new(),
-            pdu_to_send: None,
-            timeout_time: u64::MAX,
-            receive_buffer: Vec::new(),
-            receive_pgn: None,
-            receive_nr_of_packets: 0,
+    fn payload(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    fn control(source: Address, destination: Address, data: [u8; 5]) -> CanMessage {
+        let pgn = PGN.as_bytes();
+        let data = [data[0], data[1], data[2], data[3], data[4], pgn[0], pgn[1], pgn[2]];
+        CanMessage::new(CanPriority::PriorityLowest7, ParameterGroupNumber::TransportProtocolCommand, source, destination, &data)
+    }
+
+    fn data_transfer(source: Address, destination: Address, sequence_number: u8, data: &[u8]) -> CanMessage {
+        let offset = (sequence_number as usize - 1) * BYTES_PER_PACKET;
+        let mut packet = [0xFF; 8];
+        packet[0] = sequence_number;
+        for (i, byte) in data.iter().skip(offset).take(BYTES_PER_PACKET).enumerate() {
+            packet[i + 1] = *byte;
+        }
+        CanMessage::new(CanPriority::PriorityLowest7, ParameterGroupNumber::TransportProtocolData, source, destination, &packet)
+    }
+
+    fn assert_abort(message: &CanMessage, reason: AbortReason) {
+        assert_eq!(message.pgn(), ParameterGroupNumber::TransportProtocolCommand);
+        assert_eq!(message.get_u8_at(0), TpCmControlByte::ConnectionAbort as u8);
+        assert_eq!(message.get_u8_at(1), reason as u8);
+        assert_eq!(message.get_pgn_at(5), PGN);
+    }
+
+    #[test]
+    fn connection_mode_transmit() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(20);
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &data));
+
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data()[..5], [0x10, 20, 0, 3, 0xFF]);
+        assert_eq!(messages[0].get_pgn_at(5), PGN);
+        assert_eq!(messages[0].destination_address(), REMOTE);
+
+        // The receiver asks for two packets, then for the last one.
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x11, 2, 1, 0xFF, 0xFF]));
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].data(), [1, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(messages[1].data(), [2, 7, 8, 9, 10, 11, 12, 13]);
+
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x11, 1, 3, 0xFF, 0xFF]));
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data(), [3, 14, 15, 16, 17, 18, 19, 0xFF]);
+
+        // The session ends with the end of message acknowledgement.
+        assert_eq!(manager.sessions.keys(), [(SessionKey::new(LOCAL, REMOTE, PGN), Direction::Transmit)]);
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x13, 20, 0, 3, 0xFF]));
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn retransmit_requests() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(20);
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &data));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x11, 3, 1, 0xFF, 0xFF]));
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver).len(), 3);
+
+        // A CTS while waiting for the end of message acknowledgement asks to send packet 2 again.
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x11, 1, 2, 0xFF, 0xFF]));
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data(), [2, 7, 8, 9, 10, 11, 12, 13]);
+
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x13, 20, 0, 3, 0xFF]));
+        assert!(manager.sessions.keys().is_empty());
+
+        // The session is aborted after too many retransmit requests.
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &data));
+        manager.update(&mut network_manager);
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x11, 3, 1, 0xFF, 0xFF]));
+        manager.update(&mut network_manager);
+        for _ in 0..MAX_NUMBER_OF_RETRANSMITS {
+            manager.process_can_message(&control(REMOTE, LOCAL, [0x11, 3, 1, 0xFF, 0xFF]));
+            manager.update(&mut network_manager);
         }
+        assert_eq!(sent(&driver).len(), 1 + 3 * (1 + MAX_NUMBER_OF_RETRANSMITS as usize));
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x11, 3, 1, 0xFF, 0xFF]));
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_abort(&messages[0], AbortReason::RetransmitLimitReached);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn connection_mode_receive() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(16);
+
+        assert_eq!(manager.process_can_message(&control(REMOTE, LOCAL, [0x10, 16, 0, 3, 2])), None);
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data()[..3], [0x11, 2, 1]);
+        assert_eq!(messages[0].destination_address(), REMOTE);
+
+        assert_eq!(manager.process_can_message(&data_transfer(REMOTE, LOCAL, 1, &data)), None);
+        assert_eq!(manager.process_can_message(&data_transfer(REMOTE, LOCAL, 2, &data)), None);
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver)[0].data()[..3], [0x11, 1, 3]);
+
+        let message = manager.process_can_message(&data_transfer(REMOTE, LOCAL, 3, &data));
+        assert_eq!(message, Some(CanMessage::new(CanPriority::PriorityLowest7, PGN, REMOTE, LOCAL, &data)));
+
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data()[..4], [0x13, 16, 0, 3]);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn broadcast_timing() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(9);
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, Address::GLOBAL, &data));
+
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data()[..4], [0x20, 9, 0, 2]);
+        assert!(messages[0].is_address_global());
+
+        // Data packets are not send before the BAM packet delay expired.
+        manager.update(&mut network_manager);
+        assert!(sent(&driver).is_empty());
+        assert!(manager.sessions[0].timeout >= TimeDriver::time_elapsed() + TP_BAM_PACKET_DELAY - Duration::from_millis(10));
+
+        manager.sessions[0].timeout = Duration::ZERO;
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver).len(), 1);
+        assert!(manager.sessions[0].timeout > TimeDriver::time_elapsed());
+
+        manager.sessions[0].timeout = Duration::ZERO;
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver)[0].data(), [2, 7, 8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn broadcast_receive() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(9);
+
+        manager.process_can_message(&control(REMOTE, Address::GLOBAL, [0x20, 9, 0, 2, 0xFF]));
+        assert_eq!(manager.process_can_message(&data_transfer(REMOTE, Address::GLOBAL, 1, &data)), None);
+        let message = manager.process_can_message(&data_transfer(REMOTE, Address::GLOBAL, 2, &data));
+        assert_eq!(message, Some(CanMessage::new(CanPriority::PriorityLowest7, PGN, REMOTE, Address::GLOBAL, &data)));
+
+        // A BAM with a bad sequence number is dropped without an abort.
+        manager.process_can_message(&control(REMOTE, Address::GLOBAL, [0x20, 9, 0, 2, 0xFF]));
+        assert_eq!(manager.process_can_message(&data_transfer(REMOTE, Address::GLOBAL, 2, &data)), None);
+        assert!(manager.sessions.keys().is_empty());
+        manager.update(&mut network_manager);
+        assert!(sent(&driver).is_empty());
+    }
+
+    #[test]
+    fn sequence_errors() {
+        let (mut manager, mut network_manager, driver) = setup();
+        let data = payload(16);
+
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x10, 16, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&data_transfer(REMOTE, LOCAL, 2, &data));
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::BadSequenceNumber);
+        assert!(manager.sessions.keys().is_empty());
+
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x10, 16, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&data_transfer(REMOTE, LOCAL, 1, &data));
+        manager.process_can_message(&data_transfer(REMOTE, LOCAL, 1, &data));
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::DuplicateSequenceNumber);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn timeouts() {
+        let (mut manager, mut network_manager, driver) = setup();
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &payload(20)));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+
+        // No CTS within T3.
+        manager.update(&mut network_manager);
+        assert!(sent(&driver).is_empty());
+        manager.sessions[0].timeout = Duration::ZERO;
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_abort(&messages[0], AbortReason::Timeout);
+        assert_eq!(messages[0].destination_address(), REMOTE);
+        assert!(manager.sessions.keys().is_empty());
+
+        // No data within T2 after our CTS.
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x10, 16, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.sessions[0].timeout = Duration::ZERO;
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::Timeout);
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn aborts() {
+        let (mut manager, mut network_manager, driver) = setup();
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &payload(20)));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+
+        // An abort for another PGN does not close the session.
+        let other_pgn = abort_message(REMOTE, LOCAL, ParameterGroupNumber::AddressClaim, AbortReason::Other);
+        manager.process_can_message(&other_pgn);
+        assert_eq!(manager.sessions.keys().len(), 1);
+        manager.process_can_message(&abort_message(REMOTE, LOCAL, PGN, AbortReason::Other));
+        assert!(manager.sessions.keys().is_empty());
+        manager.update(&mut network_manager);
+        assert!(sent(&driver).is_empty());
+
+        // A second RTS from a sender we are receiving from is aborted.
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x10, 16, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        let _ = sent(&driver);
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x10, 16, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        assert_abort(&sent(&driver)[0], AbortReason::AlreadyConnected);
+
+        // The sender aborts the receive session.
+        manager.process_can_message(&abort_message(REMOTE, LOCAL, PGN, AbortReason::Terminated));
+        assert!(manager.sessions.keys().is_empty());
+    }
+
+    #[test]
+    fn session_limit_and_backlog() {
+        let (mut manager, mut network_manager, driver) = setup();
+        manager.set_max_sessions(1);
+
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &payload(20)));
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, Address(0x27), &payload(20)));
+        assert_eq!(manager.sessions.keys().len(), 1);
+        assert_eq!(manager.sessions.backlog_len(), 1);
+
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].destination_address(), REMOTE);
+
+        // An RTS is aborted while all sessions are in use.
+        manager.process_can_message(&control(Address(0x30), LOCAL, [0x10, 16, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages[0].destination_address(), Address(0x30));
+        assert_abort(&messages[0], AbortReason::AlreadyConnected);
+
+        // The backlogged message is send once the first session is done.
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x13, 20, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        let messages = sent(&driver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_u8_at(0), TpCmControlByte::RequestToSend as u8);
+        assert_eq!(messages[0].destination_address(), Address(0x27));
+        assert_eq!(manager.sessions.backlog_len(), 0);
+    }
+
+    #[test]
+    fn one_session_per_destination() {
+        let (mut manager, mut network_manager, driver) = setup();
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &payload(20)));
+        manager.send(CanMessage::new(CanPriority::PriorityDefault6, PGN, LOCAL, REMOTE, &payload(30)));
+        assert_eq!(manager.sessions.keys().len(), 1);
+
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver).len(), 1);
+        manager.process_can_message(&control(REMOTE, LOCAL, [0x13, 20, 0, 3, 0xFF]));
+        manager.update(&mut network_manager);
+        manager.update(&mut network_manager);
+        assert_eq!(sent(&driver)[0].data()[..4], [0x10, 30, 0, 5]);
     }
 }
@@ -1,11 +1,11 @@
 
 use core::time::Duration;
 
-use alloc::collections::{BTreeMap, VecDeque};
+use alloc::{collections::{BTreeMap, VecDeque}, vec::Vec};
 
-use crate::hardware_integration::{TimeDriver, TimeDriverTrait, CanDriverTrait};
+use crate::hardware_integration::{TimeDriver, TimeDriverTrait};
 use crate::{
-	control_function::*, Address, CanMessage, CanNetworkManager, CanPriority, ObjectId,
+	control_function::*, implement_messages::LanguageCommand, AcknowledgementType, Address, CanMessage, CanNetworkManager, CanPriority, ObjectId,
 	ParameterGroupNumber, ObjectPool,
 };
 
@@ -15,7 +15,6 @@ const MAX_EVENT_QUEUE_SIZE: usize = 32;
 
 const VT_STATUS_TIMEOUT: Duration = Duration::from_millis(3000);                //< The max allowable time between VT status messages before its considered offline
 const WORKING_SET_MAINTENANCE_TIMEOUT: Duration = Duration::from_millis(1000);  //< The delay between working set maintenance messages
-const AUXILIARY_MAINTENANCE_TIMEOUT: Duration = Duration::from_millis(100);     //< The delay between auxiliary maintenance messages
 
 pub struct VirtualTerminalClient<'a> {
 	partnered_control_function: ControlFunctionHandle, //< The handle to the partnered control function this client will send to
@@ -25,7 +24,6 @@ pub struct VirtualTerminalClient<'a> {
 
 	current_state: State,
 	is_initialized: bool,
-	is_enabled: bool,
 
 	// TODO: VT status variables, make PartneredControlFunction hold this in tuple struct VirtualTerminalServer?
 	state_machine_timestamp: Duration,
@@ -40,6 +38,7 @@ pub struct VirtualTerminalClient<'a> {
 	soft_key_y_axis_pixels: u8,
 	number_virtual_softkeys_per_softkey_mask: u8,
 	number_physical_softkeys: u8,
+	language_command: Option<LanguageCommand>,
 
 	first_time_in_state: bool,
 	first_working_set_maintenance_message: bool,
@@ -79,7 +78,6 @@ impl<'a> VirtualTerminalClient<'a> {
 
 			current_state: State::default(),
 			is_initialized: false,
-			is_enabled: false,
 		
 			// TODO: VT status variables, make PartneredControlFunction hold this in tuple struct VirtualTerminalServer?
 			state_machine_timestamp: Duration::default(),
@@ -94,6 +92,7 @@ impl<'a> VirtualTerminalClient<'a> {
 			soft_key_y_axis_pixels: u8::default(),
 			number_virtual_softkeys_per_softkey_mask: u8::default(),
 			number_physical_softkeys: u8::default(),
+			language_command: None,
 
 			first_time_in_state: false,
 			first_working_set_maintenance_message: true,
@@ -200,23 +199,43 @@ impl<'a> VirtualTerminalClient<'a> {
 		let _ = self.object_pools.insert(pool_index, object_pool);
 	}
 
+	/// The last Language Command received, `None` until one is received.
+	pub fn language_command(&self) -> Option<LanguageCommand> {
+		self.language_command
+	}
+
 	pub fn next_event(&mut self) -> Option<Event> {
 		self.event_queue.pop_front()
 	}
 
 
 	pub fn update(&mut self, network_manager: &mut CanNetworkManager) {
-		// Firt update the internal control function.
-		if let Some(icf) = network_manager.internal_control_function_mut(self.internal_control_function) {
-			icf.update(network_manager);
-			for message in icf.received_can_message_queue.oldest_ordered() {
-				self.process_can_message(message);
+		// Process the messages received by the internal control function, it is updated by the network manager.
+		let messages: Vec<CanMessage> = match network_manager.internal_control_function_mut(self.internal_control_function) {
+			Some(icf) => {
+				let messages = icf.received_can_message_queue.oldest_ordered().cloned().collect();
+				icf.received_can_message_queue.clear();
+				messages
+			}
+			None => Vec::new(),
+		};
+		for message in &messages {
+			self.process_can_message(message);
+		}
+		// React to the VT server joining or leaving the bus.
+		if let Some(pcf) = network_manager.partnered_control_function_mut(self.partnered_control_function) {
+			while let Some(event) = pcf.next_event() {
+				match event {
+					PartnerEvent::Found(ecf) => {
+						log::info!("[VT]: Found VT server {} at address {}", ecf.name(), ecf.address());
+					}
+					PartnerEvent::Lost(ecf) => {
+						log::warn!("[VT]: Lost VT server {}, disconnecting.", ecf.name());
+						self.set_state(State::Disconnected);
+					}
+				}
 			}
 		}
-		// if let Some(pcf) = network_manager.partnered_control_function_mut(self.partnered_control_function) {
-		// 	pcf.update(network_manager)
-		// }
-		// self.partnered_control_function.update(network_manager);
 
 		// Process received messages and update internal state.
 		// network_manager.handle_message(|message| self.process_can_message(message));
@@ -232,13 +251,15 @@ impl<'a> VirtualTerminalClient<'a> {
 
 		// Do stuff based on the current internal state.
 		match self.current_state {
-			State::Disconnected => {
-				if self.partnered_control_function.is_address_valid() {
-					self.set_state(State::WaitForPartnerVTStatusMessage);
-				}
+			State::Disconnected
+				if network_manager.control_function(self.partnered_control_function)
+					.is_some_and(|cf| cf.is_address_valid()) =>
+			{
+				self.set_state(State::WaitForPartnerVTStatusMessage);
 			}
 			State::SendWorkingSetMasterMessage => {
 				self.send_working_set_master_message(network_manager);
+				self.send_request_language_command(network_manager);
 				self.send_working_set_maintenance = true;
 				// self.set_state(State::ReadyForObjectPool);
 				self.set_state(State::SendGetMemory);
@@ -269,44 +290,36 @@ impl<'a> VirtualTerminalClient<'a> {
 				self.send_get_memory_message(network_manager);
 				self.set_state(State::WaitForGetMemoryResponse);
 			}
-			State::WaitForGetMemoryResponse => {
-				if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT {
-					log::error!("[VT]: Get Memory Response Timeout");
-					self.set_state(State::Failed);
-				}
+			State::WaitForGetMemoryResponse if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT => {
+				log::error!("[VT]: Get Memory Response Timeout");
+				self.set_state(State::Failed);
 			}
 	
 			State::SendGetNumberSoftkeys => {
 				self.send_get_number_of_softkeys_message(network_manager);
 				self.set_state(State::WaitForGetNumberSoftKeysResponse);
 			}
-			State::WaitForGetNumberSoftKeysResponse => {
-				if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT {
-					log::error!("[VT]: Get Number Softkeys Response Timeout");
-					self.set_state(State::Failed);
-				}
+			State::WaitForGetNumberSoftKeysResponse if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT => {
+				log::error!("[VT]: Get Number Softkeys Response Timeout");
+				self.set_state(State::Failed);
 			}
 	
 			State::SendGetTextFontData => {
 				self.send_get_text_font_data_message(network_manager);
 				self.set_state(State::WaitForGetTextFontDataResponse);
 			}
-			State::WaitForGetTextFontDataResponse => {
-				if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT {
-					log::error!("[VT]: Get Text Font Data Response Timeout");
-					self.set_state(State::Failed);
-				}
+			State::WaitForGetTextFontDataResponse if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT => {
+				log::error!("[VT]: Get Text Font Data Response Timeout");
+				self.set_state(State::Failed);
 			}
 	
 			State::SendGetHardware => {
 				self.send_get_hardware_message(network_manager);
 				self.set_state(State::WaitForGetHardwareResponse);
 			}
-			State::WaitForGetHardwareResponse => {
-				if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT {
-					log::error!("[VT]: Get Hardware Response Timeout");
-					self.set_state(State::Failed);
-				}
+			State::WaitForGetHardwareResponse if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT => {
+				log::error!("[VT]: Get Hardware Response Timeout");
+				self.set_state(State::Failed);
 			}
 	
 			// State::SendGetVersions => {
@@ -504,12 +517,12 @@ impl<'a> VirtualTerminalClient<'a> {
 		// 		}
 		// 	}
 	
+			// Check for timeouts
+			State::Connected if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT => {
+				log::error!("[VT]: Status Timeout");
+				self.set_state(State::Disconnected);
+			}
 			State::Connected => {
-				// Check for timeouts
-				if TimeDriver::time_elapsed() >= self.state_machine_timestamp + VT_STATUS_TIMEOUT {
-					log::error!("[VT]: Status Timeout");
-					self.set_state(State::Disconnected);
-				}
 				// TODO
 				// update_auxiliary_input_status();
 			}
@@ -554,23 +567,29 @@ impl<'a> VirtualTerminalClient<'a> {
 		// }
 		// txFlags.process_all_flags();
 	
-		if self.current_state == previous_state {
-			self.first_time_in_state = false;
-		} else {
-			self.first_time_in_state = true;
-		}
+		self.first_time_in_state = self.current_state != previous_state;
 	}
 
 	pub fn process_can_message(&mut self, message: &CanMessage) -> bool {
 		let handled = false;
 
 		match message.pgn() {
-			ParameterGroupNumber::Acknowledge => {
-				if AcknowledgementType::Negative as u8 == message.data()[0] {
-					if ParameterGroupNumber::ECUtoVirtualTerminal == message.data()[5..=7].into() {
-						log::error!("[VT]: The VT Server is NACK-ing our VT messages. Disconnecting.");
-						self.set_state(State::Disconnected);
+			ParameterGroupNumber::Acknowledge
+				if AcknowledgementType::Negative as u8 == message.data()[0]
+					&& ParameterGroupNumber::ECUtoVirtualTerminal == message.data()[5..=7].into() =>
+			{
+				log::error!("[VT]: The VT Server is NACK-ing our VT messages. Disconnecting.");
+				self.set_state(State::Disconnected);
+			}
+			ParameterGroupNumber::LanguageCommand => {
+				match LanguageCommand::try_from(message) {
+					Ok(language_command) if self.language_command != Some(language_command) => {
+						log::info!("[VT]: Language command changed to {:?}", language_command.language());
+						self.language_command = Some(language_command);
+						self.event_queue.push_back(Event::LanguageCommandEvent(language_command));
 					}
+					Ok(_) => {}
+					Err(error) => log::warn!("[VT]: Invalid language command: {:?}", error),
 				}
 			}
 			ParameterGroupNumber::VirtualTerminalToECU => {
@@ -581,7 +600,7 @@ impl<'a> VirtualTerminalClient<'a> {
 							let key_event: KeyActivationCode = message
 								.get_u8_at(1)
 								.try_into()
-								.unwrap_or_else(|_| KeyActivationCode::ButtonPressAborted);
+								.unwrap_or(KeyActivationCode::ButtonPressAborted);
 							let object_id: u16 = message.get_u16_at(2);
 							let parent_object_id: u16 = message.get_u16_at(4);
 							let key_number: u8 = message.get_u8_at(6);
@@ -596,7 +615,7 @@ impl<'a> VirtualTerminalClient<'a> {
 							};
 
 							// Call all of the callbacks, passing in a copy of the event.
-							for (_, callback) in &self.soft_key_event_callbacks {
+							for callback in self.soft_key_event_callbacks.values() {
 								callback(event);
 							}
 
@@ -891,28 +910,25 @@ impl<'a> VirtualTerminalClient<'a> {
 								self.set_state(State::SendWorkingSetMasterMessage);
 							}
 						}
-						VTFunction::GetMemoryMessage => {
-							if State::WaitForGetMemoryResponse == self.current_state {
-								self.connected_vt_version = message.get_u8_at(1).into();
-								if 0 == message.get_u8_at(2) {
-									// There IS enough memory
-									self.set_state(State::SendGetNumberSoftkeys);
-								} else {
-									self.set_state(State::Failed);
-									log::error!("[VT]: Connection Failed, Not Enough Memory");
-								}
+						VTFunction::GetMemoryMessage if State::WaitForGetMemoryResponse == self.current_state => {
+							self.connected_vt_version = message.get_u8_at(1).into();
+							if 0 == message.get_u8_at(2) {
+								// There IS enough memory
+								self.set_state(State::SendGetNumberSoftkeys);
+							} else {
+								self.set_state(State::Failed);
+								log::error!("[VT]: Connection Failed, Not Enough Memory");
 							}
 						}
-						VTFunction::GetNumberOfSoftKeysMessage => {
-							if State::WaitForGetNumberSoftKeysResponse == self.current_state {
-								self.soft_key_x_axis_pixels = message.get_u8_at(4);
-								self.soft_key_y_axis_pixels = message.get_u8_at(5);
-								self.number_virtual_softkeys_per_softkey_mask = message.get_u8_at(6);
-								self.number_physical_softkeys = message.get_u8_at(7);
-								self.set_state(State::SendGetTextFontData);
-							}
+						VTFunction::GetNumberOfSoftKeysMessage if State::WaitForGetNumberSoftKeysResponse == self.current_state => {
+							self.soft_key_x_axis_pixels = message.get_u8_at(4);
+							self.soft_key_y_axis_pixels = message.get_u8_at(5);
+							self.number_virtual_softkeys_per_softkey_mask = message.get_u8_at(6);
+							self.number_physical_softkeys = message.get_u8_at(7);
+							self.set_state(State::SendGetTextFontData);
 						}
-						VTFunction::GetTextFontDataMessage => {
+						VTFunction::GetTextFontDataMessage if State::WaitForGetTextFontDataResponse == self.current_state => {
+							self.set_state(State::SendGetHardware);
 							// 			if (StateMachineState::WaitForGetTextFontDataResponse == parentVT->state)
 							// 			{
 							// 				parentVT->smallFontSizesBitfield = message.get_uint8_at(5);
@@ -1187,18 +1203,15 @@ impl<'a> VirtualTerminalClient<'a> {
 
 		self.current_state = state;
 
-		match state {
-			State::Disconnected => {
-				self.last_vtstatus_timestamp = Duration::default();
-				self.send_working_set_maintenance = false;
-				self.send_auxiliary_maintenance = false;
+		if state == State::Disconnected {
+			self.last_vtstatus_timestamp = Duration::default();
+			self.send_working_set_maintenance = false;
+			self.send_auxiliary_maintenance = false;
 
-				// for (std::size_t i = 0; i < objectPools.size(); i++)
-				// {
-				// 	objectPools[i].uploaded = false;
-				// }
-			}
-			_ => {}
+			// for (std::size_t i = 0; i < objectPools.size(); i++)
+			// {
+			// 	objectPools[i].uploaded = false;
+			// }
 		}
 	}
 
@@ -1207,34 +1220,41 @@ impl<'a> VirtualTerminalClient<'a> {
 		let mut data: [u8; 8] = [0xFF; 8];
 		data[0] = 1; // TODO; Remove hard coded Number of members in working set ISO11783-7
 
-		let message = CanMessage::new_from_pdu2(
+		let _ = network_manager.send_from(
+			self.internal_control_function,
 			CanPriority::PriorityLowest7,
 			ParameterGroupNumber::WorkingSetMaster,
-			self.internal_control_function.address(),
+			Address::GLOBAL,
 			&data,
 		);
-		network_manager.send_can_message(message);
 	}
 	
-	fn send_request_language_command(&mut self, network_manager: &mut CanNetworkManager) {
+	/// Request the language command from the VT, it is broadcast in response.
+	fn send_request_language_command(&self, network_manager: &mut CanNetworkManager) {
+		let destination = match network_manager.partnered_control_function(self.partnered_control_function).and_then(|pcf| pcf.address()) {
+			Some(address) => address,
+			None => {
+				log::error!("[VT]: Can not request the language command, the VT server is not on the bus");
+				return;
+			}
+		};
 		let data: [u8; 3] = ParameterGroupNumber::LanguageCommand.into();
 
-		let message = CanMessage::new(
+		let _ = network_manager.send_from(
+			self.internal_control_function,
 			CanPriority::PriorityDefault6,
 			ParameterGroupNumber::ParameterGroupNumberRequest,
-			Address::NULL,
-			Address::GLOBAL,
+			destination,
 			&data,
 		);
-		network_manager.send_can_message(message);
 	}
 
 	fn send_get_memory_message(&mut self, network_manager: &mut CanNetworkManager) {
 		let mut data: [u8; 8] = [0xFF; 8];
 		data[0] = VTFunction::GetMemoryMessage as u8;
 		data[2..=5].copy_from_slice(&self.object_pools
-			.iter()
-			.map(|(_, op)|{
+			.values()
+			.map(|op|{
 				op.size() as u32
 			})
 			.sum::<u32>()
@@ -1307,24 +1327,25 @@ impl<'a> VirtualTerminalClient<'a> {
 	}
 
 	pub fn send_to_virtual_terminal(&self, network_manager: &mut CanNetworkManager, data: &[u8]) {
-		let message = CanMessage::new(
+		let destination = match network_manager.partnered_control_function(self.partnered_control_function).and_then(|pcf| pcf.address()) {
+			Some(address) => address,
+			None => {
+				log::error!("[VT]: Can not send to the VT server, it is not on the bus");
+				return;
+			}
+		};
+
+		let _ = network_manager.send_from(
+			self.internal_control_function,
 			if self.connected_vt_version <= VTVersion::Version5 {
 				CanPriority::PriorityLowest7
 			} else {
 				CanPriority::Priority5
 			},
 			ParameterGroupNumber::ECUtoVirtualTerminal,
-			self.internal_control_function.address(),
-			self.partnered_control_function.address(),
-			&data,
+			destination,
+			data,
 		);
-		network_manager.send_can_message(message);
-	}
-}
-
-impl Drop for VirtualTerminalClient<'_> {
-	fn drop(&mut self) {
-		self.terminate();
 	}
 }
 
@@ -1334,58 +1355,58 @@ impl<'a> VirtualTerminalClient<'a> {
 	pub fn add_vt_soft_key_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTKeyEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
 		let val = self.soft_key_event_callbacks.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_soft_key_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_SOFT_KEY_EVENT_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_vt_button_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTKeyEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
 		let val = self.button_event_callbacks.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_button_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_BUTTON_EVENT_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_vt_pointing_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTPointingEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
 		let val = self.pointing_event_callbacks.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_pointing_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_POINTING_EVENT_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_vt_select_input_object_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTSelectInputObjectEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1394,27 +1415,27 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_select_input_object_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_SELECT_INPUT_OBJECT_EVENT_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_vt_esc_message_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTESCMessageEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
 		let val = self.esc_message_event_callbacks.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_esc_message_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_ESC_MESSAGE_EVENT_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
@@ -1423,7 +1444,7 @@ impl<'a> VirtualTerminalClient<'a> {
 	>(
 		&mut self,
 		callback: &'a dyn Fn(VTChangeNumericValueEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1432,17 +1453,17 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_change_numeric_value_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_CHANGE_NUMERIC_VALUE_EVENT_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_vt_change_active_mask_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTChangeActiveMaskEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1451,17 +1472,17 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_change_active_mask_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_CHANGE_ACTIVE_MASK_EVENT_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_vt_change_soft_key_mask_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTChangeSoftKeyMaskEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1470,17 +1491,17 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_change_soft_key_mask_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_CHANGE_SOFT_KEY_MASK_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_vt_change_string_value_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(VTChangeStringValueEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1489,10 +1510,10 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_change_string_value_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_CHANGE_STRING_VALUE_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
@@ -1501,7 +1522,7 @@ impl<'a> VirtualTerminalClient<'a> {
 	>(
 		&mut self,
 		callback: &'a dyn Fn(VTUserLayoutHideShowEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1510,10 +1531,10 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_user_layout_hide_show_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_USER_LAYOUT_HIDE_SHOW_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
@@ -1522,7 +1543,7 @@ impl<'a> VirtualTerminalClient<'a> {
 	>(
 		&mut self,
 		callback: &'a dyn Fn(VTAudioSignalTerminationEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1531,17 +1552,17 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("vt_audio_signal_termination_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("VT_AUDIO_SIGNAL_TERMINATION_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
 	pub fn add_auxiliary_function_event_listener(
 		&mut self,
 		callback: &'a dyn Fn(AuxiliaryFunctionEvent),
-	) -> Result<usize, ()> {
+	) -> Option<usize> {
 		// Generate a key based on raw address (extreamly unsafe)
 		let key: usize = unsafe { core::mem::transmute(&callback) };
 
@@ -1550,10 +1571,10 @@ impl<'a> VirtualTerminalClient<'a> {
 			.insert(key, callback);
 		if val.is_none() {
 			// log::debug!("auxiliary_function_event_listener registered! key:{key}");
-			Ok(key)
+			Some(key)
 		} else {
 			log::error!("AUXILIARY_FUNCTION_CALLBACK_LIST_SIZE to small!");
-			Err(())
+			None
 		}
 	}
 
@@ -1610,12 +1631,12 @@ impl<'a> VirtualTerminalClient<'a> {
 
 
 /// The internal state machine state of the VT client, mostly just public so tests can access it
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
 enum State {
+	#[default]
 	Disconnected,                       //< VT is not connected, and is not trying to connect yet
 	WaitForPartnerVTStatusMessage,      //< VT client is initialized, waiting for a VT server to come online
 	SendWorkingSetMasterMessage,        //< Client is sending the working state master message
-	ReadyForObjectPool,                 //< Client needs an object pool before connection can continue
 	SendGetMemory,                      //< Client is sending the "get memory" message to see if VT has enough memory available
 	WaitForGetMemoryResponse,           //< Client is waiting for a response to the "get memory" message
 	SendGetNumberSoftkeys,              //< Client is sending the "get number of soft keys" message
@@ -1624,21 +1645,6 @@ enum State {
 	WaitForGetTextFontDataResponse,     //< Client is waiting for a response to the "get text font data" message
 	SendGetHardware,                    //< Client is sending the "get hardware" message
 	WaitForGetHardwareResponse,         //< Client is waiting for a response to the "get hardware" message
-	SendGetVersions,                    //< If a version label was specified, check to see if the VT has that version already
-	WaitForGetVersionsResponse,         //< Client is waiting for a response to the "get versions" message
-	SendStoreVersion,                   //< Sending the store version command
-	WaitForStoreVersionResponse,        //< Client is waiting for a response to the store version command
-	SendLoadVersion,                    //< Sending the load version command
-	WaitForLoadVersionResponse, 		//< Client is waiting for the VT to respond to the "Load Version" command
-	UploadObjectPool,           		//< Client is uploading the object pool
-	SendEndOfObjectPool,        		//< Client is sending the end of object pool message
-	WaitForEndOfObjectPoolResponse, 	//< Client is waiting for the end of object pool response message
 	Connected, 							//< Client is connected to the VT server and the application layer is in control
 	Failed,    							//< Client could not connect to the VT due to an error
 }
-
-impl Default for State {
-	fn default() -> Self {
-		Self::Disconnected
-	}
-}
\ No newline at end of file
@@ -1,4 +1,5 @@
 use super::*;
+use crate::implement_messages::LanguageCommand;
 
 /// A enum containing all VT Events
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -14,6 +15,7 @@ pub enum Event {
     VTUserLayoutHideShowEvent(VTUserLayoutHideShowEvent),
     VTAudioSignalTerminationEvent(VTAudioSignalTerminationEvent),
     AuxiliaryFunctionEvent(AuxiliaryFunctionEvent),
+    LanguageCommandEvent(LanguageCommand), //< The language, formats or units changed
 }
 
 /// A struct for storing information of a VT key input event
@@ -4,20 +4,10 @@ pub use events::*;
 mod virtual_terminal_client_state_machine;
 // use virtual_terminal_client_state_machine::VirtualTerminalClientStateMachine;
 
-mod virtual_terminal_client;
-pub use virtual_terminal_client::*;
+mod client;
+pub use client::*;
 
 
-/// The types of acknowldegement that can be sent in the Ack PGN
-#[repr(u8)]
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-enum AcknowledgementType {
-	Positive = 0,		//< "ACK" Indicates that the request was completed
-	Negative = 1,		//< "NACK" Indicates the request was not completed or we do not support the PGN
-	AccessDenied = 2,	//< Signals to the requestor that their CF is not allowed to request this PGN
-	CannotRespond = 3,	//< Signals to the requestor that we are unable to accept the request for some reason
-}
-
 /// Enumerates the states that can be sent with a hide/show object command
 #[repr(u8)]
 #[derive(Debug, PartialEq)]
@@ -44,22 +34,17 @@ pub enum SelectInputObjectOptions {
 
 /// The different VT versions that a client or server might support
 #[repr(u8)]
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Default)]
 pub enum VTVersion {
     Version2OrOlder = 2,        //< Client or server supports VT version 2 or lower
     Version3 = 3,               //< Client or server supports all of VT version 3
     Version4 = 4,               //< Client or server supports all of VT version 4
     Version5 = 5,               //< Client or server supports all of VT version 5
     Version6 = 6,               //< Client or server supports all of VT version 6
+    #[default]
     ReservedOrUnknown = 0xFF,   //< Reserved value, not to be used
 }
 
-impl Default for VTVersion {
-    fn default() -> Self {
-        Self::ReservedOrUnknown
-    }
-}
-
 impl From<u8> for VTVersion {
     fn from(value: u8) -> Self {
         match value {